@@ -0,0 +1,86 @@
+use std::{env, fs, path::PathBuf};
+
+use proc_macro::{Delimiter, Literal, TokenStream, TokenTree};
+
+// Used by `reshape::embed_migrations!`. Expands to a slice of file names and
+// contents for all files in the given directories, which are relative to the
+// Cargo.toml of the crate being compiled. The files are included using
+// `include_str!` so changes to them cause a rebuild.
+#[proc_macro]
+pub fn embed_migration_files(input: TokenStream) -> TokenStream {
+    match embed(input) {
+        Ok(tokens) => tokens,
+        Err(message) => format!("compile_error!({})", Literal::string(&message))
+            .parse()
+            .unwrap(),
+    }
+}
+
+fn embed(input: TokenStream) -> Result<TokenStream, String> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .map_err(|_| "CARGO_MANIFEST_DIR is not set".to_string())?;
+
+    let dirs = parse_dirs(input)?;
+    if dirs.is_empty() {
+        return Err("expected at least one migration directory".to_string());
+    }
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        let path = manifest_dir.join(&dir);
+        let entries = fs::read_dir(&path)
+            .map_err(|err| format!("failed to read directory {}: {}", path.display(), err))?;
+
+        for entry in entries {
+            let path = entry
+                .map_err(|err| format!("failed to read directory {}: {}", dir, err))?
+                .path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let name = path.file_name().and_then(|name| name.to_str());
+            let absolute = path.to_str();
+            match (name, absolute) {
+                (Some(name), Some(absolute)) => files.push(format!(
+                    "({}, include_str!({}))",
+                    Literal::string(name),
+                    Literal::string(absolute)
+                )),
+                _ => return Err(format!("path {} is not valid UTF-8", path.display())),
+            }
+        }
+    }
+
+    format!("[{}]", files.join(", "))
+        .parse()
+        .map_err(|err| format!("failed to embed migrations: {}", err))
+}
+
+// The input is a comma separated list of string literals
+fn parse_dirs(input: TokenStream) -> Result<Vec<String>, String> {
+    let mut dirs = Vec::new();
+    for token in input {
+        match token {
+            TokenTree::Literal(literal) => {
+                let literal = literal.to_string();
+                match literal
+                    .strip_prefix('"')
+                    .and_then(|literal| literal.strip_suffix('"'))
+                {
+                    Some(dir) if !dir.contains('\\') => dirs.push(dir.to_string()),
+                    _ => return Err(format!("expected a plain string literal, got {}", literal)),
+                }
+            }
+            // Arguments forwarded by other macros can be wrapped in invisible groups
+            TokenTree::Group(group) if group.delimiter() == Delimiter::None => {
+                dirs.extend(parse_dirs(group.stream())?)
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ',' => {}
+            token => return Err(format!("expected a string literal, got {}", token)),
+        }
+    }
+
+    Ok(dirs)
+}
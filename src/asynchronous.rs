@@ -0,0 +1,243 @@
+// Async API, made public by the `async` feature.
+//
+// This is where `Reshape` is implemented. Queries are run through
+// tokio-postgres, so its methods can be awaited on any Tokio runtime,
+// including current-thread ones. The blocking `Reshape` in the crate root
+// wraps this one and runs it on a runtime of its own.
+
+use std::sync::Arc;
+
+use tokio_postgres::Config;
+
+use crate::{
+    db::DbLocker,
+    generate::RenameResolver,
+    migrations::{Action, BackfillOptions, Migration},
+    output::Output,
+    tls, DeclarativeSchema, DrainOptions, Event, History, Hooks, PossibleRename, SchemaDump, State,
+    Status, Timeouts, TlsOptions,
+};
+
+pub struct Reshape {
+    db: DbLocker,
+    backfill: BackfillOptions,
+    intermediate_schemas: bool,
+    retained_schemas: usize,
+    hooks: Hooks,
+    drain: Option<DrainOptions>,
+    output: Output,
+}
+
+impl Reshape {
+    pub async fn new(connection_string: &str) -> anyhow::Result<Reshape> {
+        Self::new_with_tls(connection_string, TlsOptions::default()).await
+    }
+
+    pub async fn new_with_tls(connection_string: &str, tls: TlsOptions) -> anyhow::Result<Reshape> {
+        let (connection_string, connection_string_tls) =
+            tls::parse_connection_string(connection_string)?;
+        let config: Config = connection_string.parse()?;
+        Self::new_with_config(&config, &tls.or(connection_string_tls)).await
+    }
+
+    pub async fn new_with_options(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<Reshape> {
+        Self::new_with_options_and_tls(
+            host,
+            port,
+            database,
+            username,
+            password,
+            TlsOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn new_with_options_and_tls(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: &str,
+        tls: TlsOptions,
+    ) -> anyhow::Result<Reshape> {
+        let mut config = Config::new();
+        config
+            .host(host)
+            .port(port)
+            .user(username)
+            .dbname(database)
+            .password(password);
+
+        Self::new_with_config(&config, &tls).await
+    }
+
+    async fn new_with_config(config: &Config, tls: &TlsOptions) -> anyhow::Result<Reshape> {
+        let db = DbLocker::connect(config, tls).await?;
+        Ok(Reshape {
+            db,
+            backfill: BackfillOptions::default(),
+            intermediate_schemas: false,
+            retained_schemas: 0,
+            hooks: Hooks::default(),
+            drain: None,
+            output: Output::default(),
+        })
+    }
+
+    pub fn with_backfill_options(mut self, options: BackfillOptions) -> Self {
+        self.backfill = options;
+        self
+    }
+
+    pub fn with_intermediate_schemas(mut self, enabled: bool) -> Self {
+        self.intermediate_schemas = enabled;
+        self
+    }
+
+    pub fn with_retained_schemas(mut self, count: usize) -> Self {
+        self.retained_schemas = count;
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn with_drain(mut self, options: Option<DrainOptions>) -> Self {
+        self.drain = options;
+        self
+    }
+
+    pub fn on_event(mut self, f: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.output = Output::new(Some(Arc::new(f)));
+        self.db.set_output(self.output.clone());
+        self
+    }
+
+    pub async fn with_timeouts(mut self, timeouts: Timeouts) -> anyhow::Result<Self> {
+        self.db.set_timeouts(timeouts).await?;
+        Ok(self)
+    }
+
+    pub async fn migrate(
+        &mut self,
+        migrations: impl IntoIterator<Item = Migration>,
+    ) -> anyhow::Result<()> {
+        let backfill = &self.backfill;
+        let intermediate_schemas = self.intermediate_schemas;
+        let hooks = &self.hooks;
+        let output = &self.output;
+        let connector = self.db.connector().clone();
+        let db = self.db.lock().await?;
+        let result = async {
+            let mut state = State::load(db).await?;
+            crate::migrate(
+                db,
+                &mut state,
+                migrations,
+                backfill,
+                intermediate_schemas,
+                hooks,
+                &connector,
+                output,
+            )
+            .await
+        }
+        .await;
+        self.db.unlock(result).await
+    }
+
+    pub async fn status(&mut self) -> anyhow::Result<Status> {
+        crate::status::status(self.db.read(), DbLocker::LOCK_KEY).await
+    }
+
+    pub async fn history(
+        &mut self,
+        migrations: impl IntoIterator<Item = Migration>,
+    ) -> anyhow::Result<History> {
+        crate::history::history(self.db.read(), migrations).await
+    }
+
+    pub async fn dump_schema(
+        &mut self,
+        migration: Option<&str>,
+    ) -> anyhow::Result<Vec<SchemaDump>> {
+        crate::dump::dump(self.db.read(), migration).await
+    }
+
+    pub async fn generate_migration(
+        &mut self,
+        target: &DeclarativeSchema,
+        mut resolve: impl FnMut(&PossibleRename) -> anyhow::Result<Option<String>> + Send,
+    ) -> anyhow::Result<Vec<Box<dyn Action>>> {
+        let resolve: &mut RenameResolver = &mut resolve;
+        crate::generate::generate(self.db.read(), target, resolve).await
+    }
+
+    pub async fn revert(&mut self) -> anyhow::Result<()> {
+        let backfill = &self.backfill;
+        let hooks = &self.hooks;
+        let output = &self.output;
+        let connector = self.db.connector().clone();
+        let db = self.db.lock().await?;
+        let result = async {
+            let mut state = State::load(db).await?;
+            crate::revert(db, &mut state, backfill, hooks, &connector, output).await
+        }
+        .await;
+        self.db.unlock(result).await
+    }
+
+    pub async fn rebaseline(
+        &mut self,
+        migrations: impl IntoIterator<Item = Migration>,
+    ) -> anyhow::Result<()> {
+        let db = self.db.lock().await?;
+        let result = crate::state::rebaseline_migrations(db, migrations).await;
+        self.db.unlock(result).await
+    }
+
+    pub async fn complete(&mut self) -> anyhow::Result<()> {
+        let retained_schemas = self.retained_schemas;
+        let hooks = &self.hooks;
+        let drain = self.drain.as_ref();
+        let output = &self.output;
+        let db = self.db.lock().await?;
+        let result = async {
+            let mut state = State::load(db).await?;
+            crate::complete(db, &mut state, retained_schemas, hooks, drain, output).await
+        }
+        .await;
+        self.db.unlock(result).await
+    }
+
+    pub async fn abort(&mut self) -> anyhow::Result<()> {
+        let hooks = &self.hooks;
+        let output = &self.output;
+        let db = self.db.lock().await?;
+        let result = async {
+            let mut state = State::load(db).await?;
+            crate::abort(db, &mut state, hooks, output).await
+        }
+        .await;
+        self.db.unlock(result).await
+    }
+
+    pub async fn remove(&mut self) -> anyhow::Result<()> {
+        let output = &self.output;
+        let db = self.db.lock().await?;
+        let result = async {
+            let mut state = State::load(db).await?;
+            crate::remove(db, &mut state, output).await
+        }
+        .await;
+        self.db.unlock(result).await
+    }
+}
@@ -0,0 +1,407 @@
+use std::{
+    cmp::{max, min},
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::Connector;
+use crate::{
+    drain::shorten_query,
+    tls::{self, TlsOptions},
+};
+
+// When a query times out waiting for a lock, the sessions holding the lock are
+// looked up so they can be reported. pg_blocking_pids only reports them while
+// the query is still waiting, so queries are watched while they run. A single
+// thread watches all queries run through the same connector, including those
+// of backfill workers. It only opens a connection of its own once a query has
+// been running for half the lock timeout, and only looks up the blockers of
+// queries which are actually waiting for a lock.
+
+// A session holding a lock which a query run by Reshape was waiting for
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Blocker {
+    pub pid: i32,
+    pub username: Option<String>,
+    pub application_name: Option<String>,
+    pub state: Option<String>,
+    pub query: Option<String>,
+    // How long the session's current transaction has been open for
+    pub transaction_age_ms: Option<u64>,
+}
+
+impl Blocker {
+    pub fn describe(&self) -> String {
+        let mut description = format!("pid {}", self.pid);
+        if let Some(username) = &self.username {
+            description.push_str(&format!(", user {}", username));
+        }
+        if let Some(application_name) = self.application_name.as_ref().filter(|n| !n.is_empty()) {
+            description.push_str(&format!(", application {}", application_name));
+        }
+        if let Some(state) = &self.state {
+            description.push_str(&format!(", {}", state));
+        }
+        if let Some(transaction_age_ms) = self.transaction_age_ms {
+            description.push_str(&format!(
+                ", transaction open for {:.1}s",
+                transaction_age_ms as f64 / 1000.0
+            ));
+        }
+        if let Some(query) = &self.query {
+            description.push_str(&format!(", last query: {}", shorten_query(query)));
+        }
+        description
+    }
+
+    fn is_idle_in_transaction(&self) -> bool {
+        self.state
+            .as_deref()
+            .is_some_and(|state| state.starts_with("idle in transaction"))
+    }
+}
+
+// What was seen while watching an attempt at running a query
+#[derive(Default)]
+pub(crate) struct Watched {
+    // The relation the query was waiting to lock, if any
+    pub relation: Option<String>,
+    pub blockers: Vec<Blocker>,
+    // Idle blockers which were terminated, see `Timeouts::terminate_idle_blockers_after`
+    pub terminated: Vec<i32>,
+}
+
+// How long the watching thread and its connection are kept around after the
+// last query has finished, so that they can be reused by the next one
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Watches the queries run on all connections opened by a connector. Clones
+// share the same watching thread, which stops once the last clone is dropped.
+#[derive(Clone, Default)]
+pub(crate) struct Watcher {
+    handle: Arc<Handle>,
+}
+
+#[derive(Default)]
+struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_one();
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<WatcherState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct WatcherState {
+    queries: HashMap<u64, WatchedQuery>,
+    next_id: u64,
+    running: bool,
+    // Set once the watcher has been dropped
+    closed: bool,
+}
+
+struct WatchedQuery {
+    // Backend running the query
+    pid: i32,
+    // When to start looking for blockers
+    watch_from: Instant,
+    poll_interval: Duration,
+    terminate_idle_blockers_after: Option<Duration>,
+    watched: Watched,
+}
+
+// Removes a query from the watcher once it has finished, even if it panicked
+struct Registration<'a> {
+    shared: &'a Shared,
+    id: u64,
+}
+
+impl Registration<'_> {
+    fn finish(self) -> Watched {
+        // Bound to a variable so that the lock is released before `self` is
+        // dropped, which takes it again
+        let watched = self
+            .shared
+            .lock()
+            .queries
+            .remove(&self.id)
+            .map(|query| query.watched)
+            .unwrap_or_default();
+        watched
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.shared.lock().queries.remove(&self.id);
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, WatcherState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Watcher {
+    // Await `query`, which runs on the connection with backend `pid`, while
+    // watching for sessions blocking it
+    pub(crate) async fn watch<T>(
+        &self,
+        connector: &Connector,
+        pid: i32,
+        query: impl Future<Output = T>,
+    ) -> (T, Watched) {
+        // A lock timeout of 0 disables it, so there's nothing to report
+        let lock_timeout = connector.timeouts.lock_timeout;
+        if lock_timeout.is_zero() {
+            return (query.await, Watched::default());
+        }
+
+        let shared = &self.handle.shared;
+        let registration = {
+            let mut state = shared.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queries.insert(
+                id,
+                WatchedQuery {
+                    pid,
+                    watch_from: Instant::now() + lock_timeout / 2,
+                    poll_interval: max(lock_timeout / 10, Duration::from_millis(10)),
+                    terminate_idle_blockers_after: connector.timeouts.terminate_idle_blockers_after,
+                    watched: Watched::default(),
+                },
+            );
+
+            if !state.running {
+                state.running = true;
+                // The thread doesn't keep the connector, and so the watcher, alive
+                let (shared, config, tls) = (
+                    shared.clone(),
+                    connector.config.clone().into(),
+                    connector.tls.clone(),
+                );
+                thread::spawn(move || watch_queries(&shared, &config, &tls));
+            }
+            shared.changed.notify_one();
+
+            Registration { shared, id }
+        };
+
+        let result = query.await;
+        (result, registration.finish())
+    }
+}
+
+// Runs on the watching thread until no queries have been run for a while, or
+// the watcher has been dropped
+fn watch_queries(shared: &Shared, config: &postgres::Config, tls: &TlsOptions) {
+    let mut client: Option<postgres::Client> = None;
+    let mut state = shared.lock();
+
+    loop {
+        if state.closed {
+            state.running = false;
+            return;
+        }
+        if state.queries.is_empty() {
+            state = shared
+                .changed
+                .wait_timeout(state, IDLE_TIMEOUT)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+            if state.queries.is_empty() {
+                state.running = false;
+                return;
+            }
+            continue;
+        }
+
+        // Most queries finish long before the lock timeout and are never looked at
+        let now = Instant::now();
+        let due: Vec<(i32, Option<Duration>)> = state
+            .queries
+            .values()
+            .filter(|query| query.watch_from <= now)
+            .map(|query| (query.pid, query.terminate_idle_blockers_after))
+            .collect();
+        let next_poll = state
+            .queries
+            .values()
+            .map(|query| {
+                if query.watch_from <= now {
+                    query.poll_interval
+                } else {
+                    query.watch_from - now
+                }
+            })
+            .fold(IDLE_TIMEOUT, min);
+
+        if !due.is_empty() {
+            drop(state);
+            let found = look_up_blockers(&mut client, config, tls, &due);
+            state = shared.lock();
+
+            for query in state.queries.values_mut() {
+                let (relation, blockers, terminated) = match found.get(&query.pid) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                if !blockers.is_empty() {
+                    query.watched.relation = relation.clone().or(query.watched.relation.take());
+                    query.watched.blockers = blockers.clone();
+                }
+                for pid in terminated {
+                    if !query.watched.terminated.contains(pid) {
+                        query.watched.terminated.push(*pid);
+                    }
+                }
+            }
+        }
+
+        state = shared
+            .changed
+            .wait_timeout(state, next_poll)
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .0;
+    }
+}
+
+type Found = (Option<String>, Vec<Blocker>, Vec<i32>);
+
+// Look up the blockers of the queries on the backends in `due` which are
+// waiting for a lock. Failing to look up blockers shouldn't fail the queries
+// being watched, so errors are ignored.
+fn look_up_blockers(
+    client: &mut Option<postgres::Client>,
+    config: &postgres::Config,
+    tls: &TlsOptions,
+    due: &[(i32, Option<Duration>)],
+) -> HashMap<i32, Found> {
+    let mut found = HashMap::new();
+
+    if client.as_ref().map_or(true, |client| client.is_closed()) {
+        *client = tls::connect(config, tls).ok();
+    }
+    let client = match client.as_mut() {
+        Some(client) => client,
+        None => return found,
+    };
+
+    let pids: Vec<i32> = due.iter().map(|(pid, _)| *pid).collect();
+    let waiting: Vec<i32> = match client.query(
+        "
+        SELECT pid
+        FROM pg_stat_activity
+        WHERE pid = ANY($1) AND wait_event_type = 'Lock'
+        ",
+        &[&pids],
+    ) {
+        Ok(rows) => rows.iter().map(|row| row.get(0)).collect(),
+        Err(_) => return found,
+    };
+
+    for (pid, terminate_idle_blockers_after) in due {
+        if !waiting.contains(pid) {
+            continue;
+        }
+        let (relation, blockers) = match find_blockers(client, *pid) {
+            Ok(result) => result,
+            Err(_) => continue,
+        };
+
+        // Idle sessions don't have a running query to cancel, so they are
+        // terminated instead, which rolls back their transaction
+        let mut terminated = Vec::new();
+        if let Some(threshold) = terminate_idle_blockers_after {
+            let threshold_ms = threshold.as_millis().try_into().unwrap_or(u64::MAX);
+            for blocker in &blockers {
+                let is_old = blocker
+                    .transaction_age_ms
+                    .is_some_and(|age| age >= threshold_ms);
+                if !blocker.is_idle_in_transaction() || !is_old {
+                    continue;
+                }
+
+                let was_terminated = client
+                    .query_one("SELECT pg_terminate_backend($1)", &[&blocker.pid])
+                    .is_ok_and(|row| row.get(0));
+                if was_terminated {
+                    terminated.push(blocker.pid);
+                }
+            }
+        }
+
+        found.insert(*pid, (relation, blockers, terminated));
+    }
+
+    found
+}
+
+fn find_blockers(
+    client: &mut postgres::Client,
+    pid: i32,
+) -> Result<(Option<String>, Vec<Blocker>), postgres::Error> {
+    let blockers = client
+        .query(
+            "
+            SELECT
+                pid,
+                usename::TEXT AS username,
+                application_name,
+                state,
+                query,
+                (EXTRACT(EPOCH FROM now() - xact_start) * 1000)::BIGINT AS transaction_age_ms
+            FROM pg_stat_activity
+            WHERE pid = ANY(pg_blocking_pids($1))
+            ORDER BY pid
+            ",
+            &[&pid],
+        )?
+        .iter()
+        .map(|row| Blocker {
+            pid: row.get("pid"),
+            username: row.get("username"),
+            application_name: row.get("application_name"),
+            state: row.get("state"),
+            query: row.get("query"),
+            transaction_age_ms: row
+                .get::<'_, _, Option<i64>>("transaction_age_ms")
+                .map(|age| age.max(0) as u64),
+        })
+        .collect();
+
+    // Looked up after the blockers so that it's found whenever blockers are,
+    // unless the query stopped waiting in between
+    let relation = client
+        .query(
+            "
+            SELECT relation::regclass::TEXT
+            FROM pg_locks
+            WHERE pid = $1 AND NOT granted AND relation IS NOT NULL
+            LIMIT 1
+            ",
+            &[&pid],
+        )?
+        .first()
+        .map(|row| row.get(0));
+
+    Ok((relation, blockers))
+}
@@ -0,0 +1,407 @@
+use std::{cmp::min, future::Future, time::Duration};
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use rand::prelude::*;
+use tokio_postgres::{types::ToSql, Config, Row};
+
+use crate::{
+    output::{Event, Output},
+    tls::{self, TlsOptions},
+};
+
+mod blockers;
+
+pub use blockers::Blocker;
+use blockers::Watcher;
+
+// DbLocker wraps a regular DbConn, only allowing access using the
+// `lock` method. This method will acquire the advisory lock before
+// allowing access to the database, and `unlock` releases it afterwards.
+//
+// We use advisory locks to avoid multiple Reshape instances working
+// on the same database as the same time. DbLocker is the only way to
+// get a DbConn which ensures that all database access is protected by
+// a lock.
+//
+// Postgres docs on advisory locks:
+//   https://www.postgresql.org/docs/current/explicit-locking.html#ADVISORY-LOCKS
+pub struct DbLocker {
+    client: DbConn,
+}
+
+impl DbLocker {
+    // Advisory lock keys in Postgres are 64-bit integers.
+    // The key we use was chosen randomly.
+    pub(crate) const LOCK_KEY: i64 = 4036779288569897133;
+
+    pub async fn connect(config: &Config, tls: &TlsOptions) -> anyhow::Result<Self> {
+        let connector = Connector::new(config.clone(), tls.clone());
+        let client = connector.connect().await?;
+
+        Ok(Self { client })
+    }
+
+    pub fn connector(&self) -> &Connector {
+        &self.client.connector
+    }
+
+    // Apply new timeouts to the current connection and any connections
+    // opened from now on
+    pub async fn set_timeouts(&mut self, timeouts: Timeouts) -> anyhow::Result<()> {
+        self.client
+            .run(&timeouts.query())
+            .await
+            .context("failed to set timeouts")?;
+        self.client.connector.timeouts = timeouts;
+        Ok(())
+    }
+
+    // Send events reported by the current connection and any connections
+    // opened from now on, such as lock timeouts, to `output`
+    pub(crate) fn set_output(&mut self, output: Output) {
+        self.client.connector.output = output;
+    }
+
+    // Acquire the lock and return the connection to use while holding it.
+    // Once done, the result of the work must be passed to `unlock`, which
+    // releases the lock whether the work succeeded or not.
+    pub async fn lock(&mut self) -> anyhow::Result<&mut DbConn> {
+        self.acquire_lock().await?;
+        Ok(&mut self.client)
+    }
+
+    pub async fn unlock<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.release_lock().await?;
+        result
+    }
+
+    // Access the database without taking the lock. This must only be used
+    // for reading, for example to report status while another instance of
+    // Reshape is running.
+    pub fn read(&mut self) -> &mut DbConn {
+        &mut self.client
+    }
+
+    async fn acquire_lock(&mut self) -> anyhow::Result<()> {
+        let success = self
+            .client
+            .query(&format!("SELECT pg_try_advisory_lock({})", Self::LOCK_KEY))
+            .await?
+            .first()
+            .ok_or_else(|| anyhow!("unexpectedly failed when acquiring advisory lock"))
+            .map(|row| row.get::<'_, _, bool>(0))?;
+
+        if success {
+            Ok(())
+        } else {
+            Err(anyhow!("another instance of Reshape is already running"))
+        }
+    }
+
+    async fn release_lock(&mut self) -> anyhow::Result<()> {
+        self.client
+            .query(&format!("SELECT pg_advisory_unlock({})", Self::LOCK_KEY))
+            .await?
+            .first()
+            .ok_or_else(|| anyhow!("unexpectedly failed when releasing advisory lock"))?;
+        Ok(())
+    }
+}
+
+// Connector opens new connections using the same settings as the one
+// held by DbLocker. The extra connections are used by work which can be
+// spread across several connections, like backfills, and never take the
+// advisory lock themselves.
+#[derive(Clone)]
+pub struct Connector {
+    config: Config,
+    tls: TlsOptions,
+    timeouts: Timeouts,
+    output: Output,
+    // Shared by all connections opened using the connector
+    watcher: Watcher,
+}
+
+impl Connector {
+    pub fn new(config: Config, tls: TlsOptions) -> Self {
+        Connector {
+            config,
+            tls,
+            timeouts: Timeouts::default(),
+            output: Output::default(),
+            watcher: Watcher::default(),
+        }
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<DbConn> {
+        let pg = tls::connect_async(&self.config, &self.tls).await?;
+
+        // When running DDL queries that acquire locks, we risk causing a "lock queue".
+        // When attempting to acquire a lock, Postgres will wait for any long running queries to complete.
+        // At the same time, it will block other queries until the lock has been acquired and released.
+        // This has the bad effect of the long-running query blocking other queries because of us, forming
+        // a queue of other queries until we release our lock.
+        //
+        // We set the lock_timeout setting to avoid this. This puts an upper bound for how long Postgres will
+        // wait to acquire locks and also the maximum amount of time a long-running query can block other queries.
+        // We should also add automatic retries to handle these timeouts gracefully.
+        //
+        // Reference: https://medium.com/paypal-tech/postgresql-at-scale-database-schema-changes-without-downtime-20d3749ed680
+        pg.batch_execute(&self.timeouts.query())
+            .await
+            .context("failed to set timeouts")?;
+
+        let pid = backend_pid(&pg).await?;
+        Ok(DbConn::new(pg, self.clone(), pid))
+    }
+}
+
+// Timeouts set on every connection. The lock timeout bounds how long other
+// queries can be queued up behind a lock Reshape is waiting for, see
+// `Connector::connect`. No statement timeout is set by default.
+//
+// Sessions which are idle in a transaction while holding a lock Reshape is
+// waiting for can be terminated once their transaction has been open for
+// `terminate_idle_blockers_after`. They are only reported by default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    pub lock_timeout: Duration,
+    pub statement_timeout: Option<Duration>,
+    pub terminate_idle_blockers_after: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            lock_timeout: Duration::from_secs(1),
+            statement_timeout: None,
+            terminate_idle_blockers_after: None,
+        }
+    }
+}
+
+impl Timeouts {
+    pub(crate) fn query(&self) -> String {
+        // A timeout of 0 disables it in Postgres
+        format!(
+            "SET lock_timeout = '{}ms'; SET statement_timeout = '{}ms';",
+            self.lock_timeout.as_millis(),
+            self.statement_timeout
+                .map(|timeout| timeout.as_millis())
+                .unwrap_or(0)
+        )
+    }
+}
+
+#[async_trait]
+pub trait Conn: Send {
+    async fn run(&mut self, query: &str) -> anyhow::Result<()>;
+    async fn query(&mut self, query: &str) -> anyhow::Result<Vec<Row>>;
+    async fn query_with_params(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> anyhow::Result<Vec<Row>>;
+    async fn transaction(&mut self) -> anyhow::Result<Transaction<'_>>;
+}
+
+pub struct DbConn {
+    client: tokio_postgres::Client,
+    // The connector this connection was opened with, used to watch for
+    // sessions blocking its queries
+    connector: Connector,
+    // Backend running the connection's queries
+    pid: i32,
+}
+
+impl DbConn {
+    fn new(client: tokio_postgres::Client, connector: Connector, pid: i32) -> Self {
+        DbConn {
+            client,
+            connector,
+            pid,
+        }
+    }
+}
+
+#[async_trait]
+impl Conn for DbConn {
+    async fn run(&mut self, query: &str) -> anyhow::Result<()> {
+        let client = &self.client;
+        retry_automatically(&self.connector, self.pid, || client.batch_execute(query)).await?;
+        Ok(())
+    }
+
+    async fn query(&mut self, query: &str) -> anyhow::Result<Vec<Row>> {
+        self.query_with_params(query, &[]).await
+    }
+
+    async fn query_with_params(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> anyhow::Result<Vec<Row>> {
+        let client = &self.client;
+        let rows =
+            retry_automatically(&self.connector, self.pid, || client.query(query, params)).await?;
+        Ok(rows)
+    }
+
+    async fn transaction(&mut self) -> anyhow::Result<Transaction<'_>> {
+        let transaction = self.client.transaction().await?;
+        Ok(Transaction { transaction })
+    }
+}
+
+pub struct Transaction<'a> {
+    transaction: tokio_postgres::Transaction<'a>,
+}
+
+impl Transaction<'_> {
+    pub async fn commit(self) -> anyhow::Result<()> {
+        self.transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn rollback(self) -> anyhow::Result<()> {
+        self.transaction.rollback().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Conn for Transaction<'_> {
+    async fn run(&mut self, query: &str) -> anyhow::Result<()> {
+        self.transaction.batch_execute(query).await?;
+        Ok(())
+    }
+
+    async fn query(&mut self, query: &str) -> anyhow::Result<Vec<Row>> {
+        self.query_with_params(query, &[]).await
+    }
+
+    async fn query_with_params(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> anyhow::Result<Vec<Row>> {
+        let rows = self.transaction.query(query, params).await?;
+        Ok(rows)
+    }
+
+    async fn transaction(&mut self) -> anyhow::Result<Transaction<'_>> {
+        let transaction = self.transaction.transaction().await?;
+        Ok(Transaction { transaction })
+    }
+}
+
+async fn backend_pid(client: &tokio_postgres::Client) -> anyhow::Result<i32> {
+    let pid = client
+        .query_one("SELECT pg_backend_pid()", &[])
+        .await
+        .context("failed to get backend pid")?
+        .get(0);
+    Ok(pid)
+}
+
+// Retry a database operation with exponential backoff and jitter. `f` runs a
+// query on the connection with backend `pid`, and any sessions blocking it
+// are reported whenever it times out waiting for a lock.
+async fn retry_automatically<T, F>(
+    connector: &Connector,
+    pid: i32,
+    mut f: impl FnMut() -> F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = Result<T, tokio_postgres::Error>>,
+{
+    let output = &connector.output;
+    let mut attempts = 0;
+    loop {
+        let (result, watched) = connector.watcher.watch(connector, pid, f()).await;
+        for pid in watched.terminated {
+            output.emit(Event::SessionTerminated { pid });
+        }
+
+        let error = match result {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        attempts += 1;
+        let wait_time = retry_wait_time(&error, attempts);
+
+        let is_lock_timeout = error.as_db_error().is_some_and(error_retryable);
+        if is_lock_timeout {
+            output.emit(Event::LockTimeout {
+                attempt: attempts,
+                retrying: wait_time.is_some(),
+                relation: watched.relation.clone(),
+                blockers: watched.blockers.clone(),
+            });
+        }
+
+        match wait_time {
+            Some(wait_time) => tokio::time::sleep(wait_time).await,
+            None if is_lock_timeout && !watched.blockers.is_empty() => {
+                let blockers: Vec<String> = watched
+                    .blockers
+                    .iter()
+                    .map(|blocker| blocker.describe())
+                    .collect();
+                let relation = watched
+                    .relation
+                    .map(|relation| format!(" on {}", relation))
+                    .unwrap_or_default();
+                return Err(anyhow::Error::new(error).context(format!(
+                    "timed out waiting for a lock{} held by {}",
+                    relation,
+                    blockers.join("; ")
+                )));
+            }
+            None => return Err(error.into()),
+        }
+    }
+}
+
+// Determine how long to wait before retrying a failed database operation.
+// Returns None if the operation shouldn't be retried.
+fn retry_wait_time(error: &tokio_postgres::Error, attempts: u32) -> Option<Duration> {
+    const STARTING_WAIT_TIME: u64 = 100;
+    const MAX_WAIT_TIME: u64 = 3_200;
+    const MAX_ATTEMPTS: u32 = 10;
+
+    // If we got a database error, we check if it's retryable.
+    // If we didn't get a database error, then it's most likely some kind of connection
+    // error which should also be retried.
+    if let Some(db_error) = error.as_db_error() {
+        if !error_retryable(db_error) {
+            return None;
+        }
+    }
+
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    // The wait time increases exponentially, starting at 100ms and doubling up to a max of 3.2s.
+    let wait_time = min(
+        MAX_WAIT_TIME,
+        STARTING_WAIT_TIME * u64::pow(2, attempts - 1),
+    );
+
+    // The jitter is up to half the wait time
+    let jitter: u64 = rand::thread_rng().gen_range(0..wait_time / 2);
+
+    Some(Duration::from_millis(wait_time + jitter))
+}
+
+// Check if a database error can be retried
+fn error_retryable(error: &tokio_postgres::error::DbError) -> bool {
+    // LOCK_NOT_AVAILABLE is caused by lock_timeout being exceeded
+    matches!(
+        error.code(),
+        &tokio_postgres::error::SqlState::LOCK_NOT_AVAILABLE
+    )
+}
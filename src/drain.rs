@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+
+use serde::Serialize;
+
+use crate::{
+    db::Conn,
+    output::{Event, Output},
+};
+
+// Completing a migration drops the schemas which are no longer needed, taking
+// the views of any application still using them along. With draining enabled,
+// completion instead waits for the sessions using those schemas to end first.
+//
+// Postgres doesn't expose the search_path of other sessions, and a pooled
+// connection which has set its search_path and then run other queries holds
+// no locks while idle. Applications must therefore identify the schema they
+// use in their application_name, for example by connecting with
+// `application_name=myapp/migration_2_add_email`. Sessions which don't are
+// only found while they hold locks on objects in the schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrainOptions {
+    // How long to wait for sessions to end before giving up
+    pub timeout: Duration,
+    // How often to check for sessions
+    pub poll_interval: Duration,
+    // Terminate any sessions remaining after the timeout instead of failing
+    pub terminate: bool,
+}
+
+impl Default for DrainOptions {
+    fn default() -> Self {
+        DrainOptions {
+            timeout: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(1),
+            terminate: false,
+        }
+    }
+}
+
+// A session using a schema which is about to be removed, as reported by
+// pg_stat_activity. `query` is the most recent query run by the session.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActiveSession {
+    pub pid: i32,
+    pub username: Option<String>,
+    pub application_name: Option<String>,
+    pub client_address: Option<String>,
+    pub state: Option<String>,
+    pub query: Option<String>,
+}
+
+impl ActiveSession {
+    pub fn describe(&self) -> String {
+        let mut description = format!("pid {}", self.pid);
+        if let Some(username) = &self.username {
+            description.push_str(&format!(", user {}", username));
+        }
+        if let Some(application_name) = self.application_name.as_ref().filter(|n| !n.is_empty()) {
+            description.push_str(&format!(", application {}", application_name));
+        }
+        if let Some(client_address) = &self.client_address {
+            description.push_str(&format!(", from {}", client_address));
+        }
+        if let Some(state) = &self.state {
+            description.push_str(&format!(", {}", state));
+        }
+        if let Some(query) = &self.query {
+            description.push_str(&format!(", last query: {}", shorten_query(query)));
+        }
+        description
+    }
+}
+
+// Collapse the whitespace in a query and cut it short to fit on a single line
+pub(crate) fn shorten_query(query: &str) -> String {
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    match query.char_indices().nth(60) {
+        Some((index, _)) => format!("{}...", &query[..index]),
+        None => query,
+    }
+}
+
+// Wait until no sessions are using any of `schemas`, terminating those left
+// after the timeout if enabled
+pub(crate) async fn wait_for_drain(
+    db: &mut dyn Conn,
+    schemas: &[String],
+    options: &DrainOptions,
+    output: &Output,
+) -> anyhow::Result<()> {
+    if schemas.is_empty() {
+        return Ok(());
+    }
+
+    let started_at = Instant::now();
+    let mut previous_pids: Vec<i32> = Vec::new();
+
+    loop {
+        let sessions = sessions_using_schemas(db, schemas).await?;
+        if sessions.is_empty() {
+            if !previous_pids.is_empty() {
+                output.message("All sessions using the previous schema have ended");
+                output.text("");
+            }
+            return Ok(());
+        }
+
+        // Only print the sessions when they change to avoid flooding the output
+        let pids: Vec<i32> = sessions.iter().map(|session| session.pid).collect();
+        if pids != previous_pids {
+            output.emit(Event::WaitingForSessions {
+                schemas: schemas.to_vec(),
+                sessions,
+            });
+        }
+
+        if started_at.elapsed() >= options.timeout {
+            if !options.terminate {
+                return Err(anyhow!(
+                    "timed out waiting for {} sessions using the previous schema to end",
+                    pids.len()
+                ));
+            }
+
+            for pid in pids {
+                db.query_with_params("SELECT pg_terminate_backend($1)", &[&pid])
+                    .await
+                    .with_context(|| format!("failed to terminate session {}", pid))?;
+                output.emit(Event::SessionTerminated { pid });
+            }
+            output.text("");
+            return Ok(());
+        }
+
+        previous_pids = pids;
+        tokio::time::sleep(options.poll_interval).await;
+    }
+}
+
+// Sessions are found by their application_name mentioning one of the schemas
+// as a whole word or by them holding locks on objects in the schemas. Schema
+// names may contain `$`, which has a meaning in regexes, so they are escaped
+// before being matched.
+async fn sessions_using_schemas(
+    db: &mut dyn Conn,
+    schemas: &[String],
+) -> anyhow::Result<Vec<ActiveSession>> {
+    let rows = db
+        .query_with_params(
+            r#"
+            SELECT
+                a.pid,
+                a.usename::TEXT AS username,
+                a.application_name,
+                host(a.client_addr) AS client_address,
+                a.state,
+                a.query
+            FROM pg_stat_activity a
+            WHERE a.pid <> pg_backend_pid()
+            AND a.backend_type = 'client backend'
+            AND a.datname = current_database()
+            AND (
+                EXISTS (
+                    SELECT 1 FROM unnest($1::TEXT[]) AS schema
+                    WHERE a.application_name ~ (
+                        '\m' || regexp_replace(schema, '([^[:alnum:]_])', '\\\1', 'g') || '\M'
+                    )
+                )
+                OR EXISTS (
+                    SELECT 1
+                    FROM pg_locks l
+                    JOIN pg_class c ON c.oid = l.relation
+                    JOIN pg_namespace n ON n.oid = c.relnamespace
+                    WHERE l.pid = a.pid AND n.nspname = ANY($1)
+                )
+            )
+            ORDER BY a.pid
+            "#,
+            &[&schemas],
+        )
+        .await
+        .context("failed to get sessions using schemas")?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ActiveSession {
+            pid: row.get("pid"),
+            username: row.get("username"),
+            application_name: row.get("application_name"),
+            client_address: row.get("client_address"),
+            state: row.get("state"),
+            query: row.get("query"),
+        })
+        .collect())
+}
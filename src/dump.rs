@@ -0,0 +1,427 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+
+use crate::{
+    db::Conn,
+    schema::{Schema, Table},
+    schema_before_action, schema_name_for_migration, state, State,
+};
+
+// The logical schema of a migration, as seen by an application using the
+// migration's schema. Tables and columns have the names they are exposed
+// under by the views, not the names of the underlying tables and columns.
+#[derive(Serialize, Debug, Clone)]
+pub struct SchemaDump {
+    pub migration: String,
+    pub schema: String,
+    pub tables: Vec<TableDump>,
+    pub enums: Vec<EnumDump>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TableDump {
+    pub name: String,
+    pub columns: Vec<ColumnDump>,
+    pub primary_key: Vec<String>,
+    pub indices: Vec<IndexDump>,
+    pub foreign_keys: Vec<ForeignKeyDump>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ColumnDump {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+    pub default: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexDump {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ForeignKeyDump {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EnumDump {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+// Dump the schema of the passed migration or, if no migration is passed, of
+// the current migration and the migration in progress, if any
+pub(crate) async fn dump(
+    db: &mut dyn Conn,
+    migration: Option<&str>,
+) -> anyhow::Result<Vec<SchemaDump>> {
+    let state = State::load_read_only(db).await?;
+    let current_migration = state::current_migration(db).await?;
+
+    // The current schema is backed directly by the tables, while the schema of
+    // an in-progress migration is found by replaying its actions
+    let mut available: Vec<(String, Schema)> = Vec::new();
+    if let Some(current_migration) = &current_migration {
+        available.push((current_migration.to_string(), Schema::new()));
+    }
+
+    match &state {
+        State::Idle => {}
+        State::InProgress { migrations } => {
+            let target_migration = migrations.last().unwrap().name.to_string();
+            let schema = schema_before_action(migrations, migrations.len(), 0, &current_migration);
+            available.push((target_migration, schema));
+        }
+        _ => {
+            return Err(anyhow!(
+                "can't dump schema while migrations are {}, please wait for it to finish",
+                state.name()
+            ))
+        }
+    }
+
+    if let Some(migration) = migration {
+        let names: Vec<String> = available.iter().map(|(name, _)| name.clone()).collect();
+        available.retain(|(name, _)| name == migration);
+
+        if available.is_empty() {
+            return Err(anyhow!(
+                "no schema exists for migration {}, schemas exist for: {}",
+                migration,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            ));
+        }
+    }
+
+    if available.is_empty() {
+        return Err(anyhow!("no migrations have been applied"));
+    }
+
+    let mut dumps = Vec::new();
+    for (migration, schema) in &available {
+        dumps.push(dump_schema(db, migration, schema).await?);
+    }
+    Ok(dumps)
+}
+
+async fn dump_schema(
+    db: &mut dyn Conn,
+    migration: &str,
+    schema: &Schema,
+) -> anyhow::Result<SchemaDump> {
+    let schema_name = schema_name_for_migration(migration);
+
+    // Only tables and columns exposed by the migration's views are part of its
+    // schema. This hides temporary columns and tables which are only part of
+    // another migration's schema.
+    let mut view_columns: HashMap<String, HashSet<String>> = HashMap::new();
+    for row in db
+        .query_with_params(
+            "
+            SELECT table_name::TEXT, column_name::TEXT
+            FROM information_schema.columns
+            WHERE table_schema = $1
+            ",
+            &[&schema_name],
+        )
+        .await
+        .with_context(|| format!("failed to get views in schema {}", schema_name))?
+    {
+        view_columns
+            .entry(row.get("table_name"))
+            .or_default()
+            .insert(row.get("column_name"));
+    }
+
+    let mut tables: Vec<Table> = schema
+        .get_tables(db)
+        .await?
+        .into_iter()
+        .filter_map(|mut table| {
+            let columns = view_columns.get(&table.name)?;
+            table
+                .columns
+                .retain(|column| columns.contains(&column.name));
+            Some(table)
+        })
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut table_dumps = Vec::with_capacity(tables.len());
+    for table in &tables {
+        table_dumps.push(dump_table(db, table, &tables).await?);
+    }
+
+    let enums = db
+        .query(
+            "
+            SELECT t.typname::TEXT AS name, ARRAY_AGG(e.enumlabel::TEXT ORDER BY e.enumsortorder) AS values
+            FROM pg_type t
+            JOIN pg_enum e ON e.enumtypid = t.oid
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+            WHERE n.nspname = 'public'
+            GROUP BY t.typname
+            ORDER BY t.typname
+            ",
+        ).await
+        .context("failed to get enums")?
+        .iter()
+        .map(|row| EnumDump {
+            name: row.get("name"),
+            values: row.get("values"),
+        })
+        .collect();
+
+    Ok(SchemaDump {
+        migration: migration.to_string(),
+        schema: schema_name,
+        tables: table_dumps,
+        enums,
+    })
+}
+
+async fn dump_table(
+    db: &mut dyn Conn,
+    table: &Table,
+    tables: &[Table],
+) -> anyhow::Result<TableDump> {
+    let columns = table
+        .columns
+        .iter()
+        .map(|column| ColumnDump {
+            name: column.name.to_string(),
+            data_type: column.data_type.to_string(),
+            nullable: column.nullable,
+            default: column.default.clone(),
+        })
+        .collect();
+
+    // Expression and partial indices are left out as they can't be expressed
+    // using the logical column names
+    let index_rows = db
+        .query_with_params(
+            "
+            SELECT
+                i.relname::TEXT AS name,
+                ix.indisunique AS unique,
+                ix.indisprimary AS primary,
+                ARRAY(
+                    SELECT a.attname::TEXT
+                    FROM UNNEST(ix.indkey::SMALLINT[]) WITH ORDINALITY AS k(attnum, position)
+                    JOIN pg_attribute a ON a.attrelid = ix.indrelid AND a.attnum = k.attnum
+                    ORDER BY k.position
+                ) AS columns
+            FROM pg_index ix
+            JOIN pg_class i ON i.oid = ix.indexrelid
+            JOIN pg_class t ON t.oid = ix.indrelid
+            JOIN pg_namespace n ON n.oid = t.relnamespace
+            WHERE n.nspname = 'public'
+            AND t.relname = $1
+            AND ix.indexprs IS NULL
+            AND ix.indpred IS NULL
+            ORDER BY i.relname
+            ",
+            &[&table.real_name],
+        )
+        .await
+        .with_context(|| format!("failed to get indices for table {}", table.name))?;
+
+    let mut primary_key = Vec::new();
+    let mut indices = Vec::new();
+    for row in index_rows {
+        let real_columns: Vec<String> = row.get("columns");
+        let columns = match logical_column_names(table, &real_columns) {
+            Some(columns) => columns,
+            None => continue,
+        };
+
+        if row.get("primary") {
+            primary_key = columns;
+        } else {
+            indices.push(IndexDump {
+                name: row.get("name"),
+                columns,
+                unique: row.get("unique"),
+            });
+        }
+    }
+
+    let foreign_key_rows = db
+        .query_with_params(
+            "
+            SELECT
+                con.conname::TEXT AS name,
+                ARRAY(
+                    SELECT a.attname::TEXT
+                    FROM UNNEST(con.conkey) WITH ORDINALITY AS k(attnum, position)
+                    JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+                    ORDER BY k.position
+                ) AS columns,
+                rt.relname::TEXT AS referenced_table,
+                ARRAY(
+                    SELECT a.attname::TEXT
+                    FROM UNNEST(con.confkey) WITH ORDINALITY AS k(attnum, position)
+                    JOIN pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+                    ORDER BY k.position
+                ) AS referenced_columns
+            FROM pg_constraint con
+            JOIN pg_class t ON t.oid = con.conrelid
+            JOIN pg_class rt ON rt.oid = con.confrelid
+            JOIN pg_namespace n ON n.oid = t.relnamespace
+            WHERE con.contype = 'f' AND n.nspname = 'public' AND t.relname = $1
+            ORDER BY con.conname
+            ",
+            &[&table.real_name],
+        )
+        .await
+        .with_context(|| format!("failed to get foreign keys for table {}", table.name))?;
+
+    let mut foreign_keys = Vec::new();
+    for row in foreign_key_rows {
+        let real_referenced_table: String = row.get("referenced_table");
+        let referenced_table = match tables
+            .iter()
+            .find(|table| table.real_name == real_referenced_table)
+        {
+            Some(table) => table,
+            None => continue,
+        };
+
+        let real_columns: Vec<String> = row.get("columns");
+        let real_referenced_columns: Vec<String> = row.get("referenced_columns");
+        if let (Some(columns), Some(referenced_columns)) = (
+            logical_column_names(table, &real_columns),
+            logical_column_names(referenced_table, &real_referenced_columns),
+        ) {
+            foreign_keys.push(ForeignKeyDump {
+                name: row.get("name"),
+                columns,
+                referenced_table: referenced_table.name.to_string(),
+                referenced_columns,
+            });
+        }
+    }
+
+    Ok(TableDump {
+        name: table.name.to_string(),
+        columns,
+        primary_key,
+        indices,
+        foreign_keys,
+    })
+}
+
+// Map real column names to the names exposed in the schema. Returns None if
+// any of the columns isn't part of the schema.
+fn logical_column_names(table: &Table, real_columns: &[String]) -> Option<Vec<String>> {
+    real_columns
+        .iter()
+        .map(|real_name| {
+            table
+                .columns
+                .iter()
+                .find(|column| &column.real_name == real_name)
+                .map(|column| column.name.to_string())
+        })
+        .collect()
+}
+
+fn quoted_list(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("\"{}\"", name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Renders the schema as SQL DDL
+impl fmt::Display for SchemaDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "-- Schema {} for migration {}",
+            self.schema, self.migration
+        )?;
+
+        for enum_dump in &self.enums {
+            let values: Vec<String> = enum_dump
+                .values
+                .iter()
+                .map(|value| format!("'{}'", value.replace('\'', "''")))
+                .collect();
+            write!(
+                f,
+                "\n\nCREATE TYPE \"{}\" AS ENUM ({});",
+                enum_dump.name,
+                values.join(", ")
+            )?;
+        }
+
+        for table in &self.tables {
+            let mut definitions: Vec<String> = table
+                .columns
+                .iter()
+                .map(|column| {
+                    let mut definition = format!("\"{}\" {}", column.name, column.data_type);
+                    if !column.nullable {
+                        definition.push_str(" NOT NULL");
+                    }
+                    if let Some(default) = &column.default {
+                        definition.push_str(&format!(" DEFAULT {}", default));
+                    }
+                    definition
+                })
+                .collect();
+
+            if !table.primary_key.is_empty() {
+                definitions.push(format!("PRIMARY KEY ({})", quoted_list(&table.primary_key)));
+            }
+
+            for foreign_key in &table.foreign_keys {
+                definitions.push(format!(
+                    "CONSTRAINT \"{}\" FOREIGN KEY ({}) REFERENCES \"{}\" ({})",
+                    foreign_key.name,
+                    quoted_list(&foreign_key.columns),
+                    foreign_key.referenced_table,
+                    quoted_list(&foreign_key.referenced_columns)
+                ));
+            }
+
+            write!(
+                f,
+                "\n\nCREATE TABLE \"{}\" (\n    {}\n);",
+                table.name,
+                definitions.join(",\n    ")
+            )?;
+
+            for index in &table.indices {
+                write!(
+                    f,
+                    "\nCREATE {}INDEX \"{}\" ON \"{}\" ({});",
+                    if index.unique { "UNIQUE " } else { "" },
+                    index.name,
+                    table.name,
+                    quoted_list(&index.columns)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
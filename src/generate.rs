@@ -0,0 +1,608 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Conn,
+    dump::{self, SchemaDump, TableDump},
+    migrations::{
+        Action, AddColumn, AddForeignKey, AddIndex, AlterColumn, Column, ColumnChanges, CreateEnum,
+        CreateTable, ForeignKey, Index, RemoveColumn, RemoveEnum, RemoveForeignKey, RemoveIndex,
+        RemoveTable, RenameTable,
+    },
+    state, State,
+};
+
+// A declarative description of the schema an application wants. New
+// migrations are generated by comparing this against the current schema.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DeclarativeSchema {
+    #[serde(default)]
+    pub tables: Vec<DeclaredTable>,
+    #[serde(default)]
+    pub enums: Vec<DeclaredEnum>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeclaredTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+    #[serde(default)]
+    pub primary_key: Vec<String>,
+    #[serde(default)]
+    pub indices: Vec<Index>,
+    #[serde(default)]
+    pub foreign_keys: Vec<ForeignKey>,
+
+    // Hint that the table has been renamed from this name
+    pub renamed_from: Option<String>,
+    // Hints for renamed columns, mapping the new names to the old ones
+    #[serde(default)]
+    pub renamed_columns: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeclaredEnum {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+// A table or column which doesn't exist in the current schema but which could
+// be a rename of one of the candidates, as they are no longer declared.
+// `table` is set when the rename is of a column.
+#[derive(Debug, Clone)]
+pub struct PossibleRename {
+    pub table: Option<String>,
+    pub name: String,
+    pub candidates: Vec<String>,
+}
+
+// Decides whether a possible rename is a rename by returning the name of the
+// candidate it was renamed from, or None if it's a new table or column
+pub type RenameResolver<'a> =
+    dyn FnMut(&PossibleRename) -> anyhow::Result<Option<String>> + Send + 'a;
+
+pub(crate) async fn generate(
+    db: &mut dyn Conn,
+    target: &DeclarativeSchema,
+    resolve: &mut RenameResolver<'_>,
+) -> anyhow::Result<Vec<Box<dyn Action>>> {
+    // Compare against the latest schema, which is the one of the migration in
+    // progress if there is one. Before the first migration, the schema is empty.
+    let state = State::load_read_only(db).await?;
+    let current = if matches!(state, State::Idle) && state::current_migration(db).await?.is_none() {
+        SchemaDump {
+            migration: String::new(),
+            schema: String::new(),
+            tables: Vec::new(),
+            enums: Vec::new(),
+        }
+    } else {
+        dump::dump(db, None)
+            .await
+            .context("failed to get current schema")?
+            .pop()
+            .expect("dump should return at least one schema")
+    };
+
+    let mut generator = Generator {
+        current: &current,
+        actions: Vec::new(),
+        table_renames: HashMap::new(),
+    };
+    generator.diff_enums_before(target)?;
+    generator.diff_tables(db, target, resolve).await?;
+    generator.diff_enums_after(target);
+
+    Ok(generator.actions)
+}
+
+struct Generator<'a> {
+    current: &'a SchemaDump,
+    actions: Vec<Box<dyn Action>>,
+    // Maps current table names to their new names
+    table_renames: HashMap<String, String>,
+}
+
+impl<'a> Generator<'a> {
+    fn diff_enums_before(&mut self, target: &DeclarativeSchema) -> anyhow::Result<()> {
+        for declared in &target.enums {
+            match self
+                .current
+                .enums
+                .iter()
+                .find(|current| current.name == declared.name)
+            {
+                Some(current) if current.values != declared.values => {
+                    return Err(anyhow!(
+                        "changing the values of enum {} isn't supported, use a custom migration instead",
+                        declared.name
+                    ));
+                }
+                Some(_) => {}
+                None => self.actions.push(Box::new(CreateEnum {
+                    name: declared.name.to_string(),
+                    values: declared.values.clone(),
+                })),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Enums are removed last as they may still be used by columns which are
+    // removed by the migration
+    fn diff_enums_after(&mut self, target: &DeclarativeSchema) {
+        for current in &self.current.enums {
+            if !target
+                .enums
+                .iter()
+                .any(|declared| declared.name == current.name)
+            {
+                self.actions.push(Box::new(RemoveEnum {
+                    enum_name: current.name.to_string(),
+                }));
+            }
+        }
+    }
+
+    async fn diff_tables(
+        &mut self,
+        db: &mut dyn Conn,
+        target: &DeclarativeSchema,
+        resolve: &mut RenameResolver<'_>,
+    ) -> anyhow::Result<()> {
+        let declared_names: HashSet<&str> = target
+            .tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect();
+
+        // Pair each declared table with its current table, if any
+        let mut unmatched: Vec<String> = self
+            .current
+            .tables
+            .iter()
+            .map(|table| table.name.to_string())
+            .filter(|name| !declared_names.contains(name.as_str()))
+            .collect();
+        let mut pairs: Vec<(&DeclaredTable, Option<&'a TableDump>)> = Vec::new();
+        for declared in &target.tables {
+            let current_name = if self.current_table(&declared.name).is_some() {
+                Some(declared.name.to_string())
+            } else {
+                resolve_rename(
+                    None,
+                    &declared.name,
+                    declared.renamed_from.as_deref(),
+                    &mut unmatched,
+                    resolve,
+                )?
+            };
+
+            if let Some(current_name) = &current_name {
+                if current_name != &declared.name {
+                    self.table_renames
+                        .insert(current_name.to_string(), declared.name.to_string());
+                }
+            }
+
+            pairs.push((
+                declared,
+                current_name.and_then(|name| self.current_table(&name)),
+            ));
+        }
+
+        // Not all actions support tables which have been renamed earlier in the
+        // same migration. Existing tables are therefore changed using their
+        // current names and renamed afterwards. Foreign keys involving renamed
+        // tables are added last, as are foreign keys between new tables.
+        //
+        // New tables are created with the foreign keys which reference tables
+        // that already exist and keep their names.
+        let mut existing_tables: HashSet<String> = pairs
+            .iter()
+            .filter_map(|(_, current)| *current)
+            .filter(|current| !self.table_renames.contains_key(&current.name))
+            .map(|current| current.name.to_string())
+            .collect();
+        let mut deferred_foreign_keys: Vec<(String, ForeignKey)> = Vec::new();
+        for (declared, current) in &pairs {
+            if current.is_some() {
+                continue;
+            }
+
+            let (foreign_keys, deferred): (Vec<ForeignKey>, Vec<ForeignKey>) = declared
+                .foreign_keys
+                .iter()
+                .cloned()
+                .partition(|foreign_key| existing_tables.contains(&foreign_key.referenced_table));
+            deferred_foreign_keys.extend(
+                deferred
+                    .into_iter()
+                    .map(|foreign_key| (declared.name.to_string(), foreign_key)),
+            );
+
+            self.actions.push(Box::new(CreateTable {
+                name: declared.name.to_string(),
+                columns: declared.columns.clone(),
+                primary_key: declared.primary_key.clone(),
+                foreign_keys,
+                up: None,
+                backfill_workers: None,
+            }));
+            for index in &declared.indices {
+                self.actions.push(Box::new(AddIndex {
+                    table: declared.name.to_string(),
+                    index: index.clone(),
+                }));
+            }
+
+            existing_tables.insert(declared.name.to_string());
+        }
+
+        for (declared, current) in &pairs {
+            if let Some(current) = current {
+                self.diff_table(db, declared, current, resolve, &mut deferred_foreign_keys)
+                    .await?;
+            }
+        }
+
+        for (declared, current) in &pairs {
+            if let Some(current) = current {
+                if current.name != declared.name {
+                    self.actions.push(Box::new(RenameTable {
+                        table: current.name.to_string(),
+                        new_name: declared.name.to_string(),
+                    }));
+                }
+            }
+        }
+
+        for (table, foreign_key) in deferred_foreign_keys {
+            self.actions
+                .push(Box::new(AddForeignKey { table, foreign_key }));
+        }
+
+        // Foreign keys referencing removed tables are no longer declared and
+        // have already been removed by `diff_table`
+        for name in unmatched {
+            self.actions.push(Box::new(RemoveTable { table: name }));
+        }
+
+        Ok(())
+    }
+
+    async fn diff_table(
+        &mut self,
+        db: &mut dyn Conn,
+        declared: &DeclaredTable,
+        current: &TableDump,
+        resolve: &mut RenameResolver<'_>,
+        deferred_foreign_keys: &mut Vec<(String, ForeignKey)>,
+    ) -> anyhow::Result<()> {
+        // Actions refer to the table by its current name as it's renamed last
+        let table = current.name.to_string();
+        let renamed = current.name != declared.name;
+
+        // Maps current column names to their new names
+        let mut column_renames: HashMap<String, String> = HashMap::new();
+        let declared_names: HashSet<&str> = declared
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        let mut unmatched: Vec<String> = current
+            .columns
+            .iter()
+            .map(|column| column.name.to_string())
+            .filter(|name| !declared_names.contains(name.as_str()))
+            .collect();
+
+        let mut column_pairs = Vec::new();
+        for column in &declared.columns {
+            let current_name = if current.columns.iter().any(|c| c.name == column.name) {
+                Some(column.name.to_string())
+            } else {
+                resolve_rename(
+                    Some(&declared.name),
+                    &column.name,
+                    declared
+                        .renamed_columns
+                        .get(&column.name)
+                        .map(|name| name.as_str()),
+                    &mut unmatched,
+                    resolve,
+                )?
+            };
+
+            if let Some(current_name) = &current_name {
+                column_renames.insert(current_name.to_string(), column.name.to_string());
+            }
+            column_pairs.push((column, current_name));
+        }
+
+        let map_columns = |columns: &[String]| -> Vec<String> {
+            columns
+                .iter()
+                .map(|name| column_renames.get(name).unwrap_or(name).to_string())
+                .collect()
+        };
+
+        if !declared.primary_key.is_empty()
+            && map_columns(&current.primary_key) != declared.primary_key
+        {
+            return Err(anyhow!(
+                "changing the primary key of table {} isn't supported, use a custom migration instead",
+                table
+            ));
+        }
+
+        // Foreign keys and indices which have changed or been removed are
+        // removed before any columns they use
+        let current_foreign_keys: Vec<(String, ForeignKey)> = current
+            .foreign_keys
+            .iter()
+            .map(|foreign_key| {
+                (
+                    foreign_key.name.to_string(),
+                    ForeignKey {
+                        columns: map_columns(&foreign_key.columns),
+                        referenced_table: self
+                            .table_renames
+                            .get(&foreign_key.referenced_table)
+                            .unwrap_or(&foreign_key.referenced_table)
+                            .to_string(),
+                        referenced_columns: foreign_key.referenced_columns.clone(),
+                    },
+                )
+            })
+            .collect();
+        for (name, foreign_key) in &current_foreign_keys {
+            if !declared
+                .foreign_keys
+                .iter()
+                .any(|declared| same_foreign_key(declared, foreign_key))
+            {
+                self.actions.push(Box::new(RemoveForeignKey {
+                    table: table.to_string(),
+                    foreign_key: name.to_string(),
+                }));
+            }
+        }
+
+        for index in &current.indices {
+            match declared
+                .indices
+                .iter()
+                .find(|declared| declared.name == index.name)
+            {
+                Some(declared_index)
+                    if declared_index.columns != map_columns(&index.columns)
+                        || declared_index.unique != index.unique =>
+                {
+                    return Err(anyhow!(
+                        "index {} on table {} has changed, give the changed index a new name to replace it",
+                        index.name,
+                        table
+                    ));
+                }
+                Some(_) => {}
+                None => self.actions.push(Box::new(RemoveIndex {
+                    index: index.name.to_string(),
+                })),
+            }
+        }
+
+        for (column, current_name) in column_pairs {
+            let current_name = match current_name {
+                Some(current_name) => current_name,
+                None => {
+                    self.actions.push(Box::new(AddColumn {
+                        table: table.to_string(),
+                        column: column.clone(),
+                        up: None,
+                        backfill_workers: None,
+                    }));
+                    continue;
+                }
+            };
+
+            let current_column = current
+                .columns
+                .iter()
+                .find(|c| c.name == current_name)
+                .expect("matched column should exist");
+            let (data_type, default) = normalize_column(db, column).await?;
+
+            // Primary key columns are always non-nullable
+            let nullable = column.nullable && !declared.primary_key.contains(&column.name);
+
+            let mut changes = ColumnChanges::default();
+            if current_name != column.name {
+                changes.name = Some(column.name.to_string());
+            }
+            if data_type != current_column.data_type {
+                changes.data_type = Some(column.data_type.to_string());
+            }
+            if nullable != current_column.nullable {
+                changes.nullable = Some(nullable);
+            }
+            if default != current_column.default {
+                match &column.default {
+                    Some(default) => changes.default = Some(default.to_string()),
+                    None => {
+                        return Err(anyhow!(
+                            "removing the default of column {}.{} isn't supported, use a custom migration instead",
+                            table,
+                            column.name
+                        ))
+                    }
+                }
+            }
+
+            if changes.name.is_some()
+                || changes.data_type.is_some()
+                || changes.nullable.is_some()
+                || changes.default.is_some()
+            {
+                self.actions.push(Box::new(AlterColumn {
+                    table: table.to_string(),
+                    column: current_name,
+                    up: None,
+                    down: None,
+                    changes,
+                    backfill_workers: None,
+                }));
+            }
+        }
+
+        for name in unmatched {
+            self.actions.push(Box::new(RemoveColumn {
+                table: table.to_string(),
+                column: name,
+                down: None,
+            }));
+        }
+
+        for index in &declared.indices {
+            if !current
+                .indices
+                .iter()
+                .any(|current| current.name == index.name)
+            {
+                self.actions.push(Box::new(AddIndex {
+                    table: table.to_string(),
+                    index: index.clone(),
+                }));
+            }
+        }
+
+        for foreign_key in &declared.foreign_keys {
+            if current_foreign_keys
+                .iter()
+                .any(|(_, current)| same_foreign_key(foreign_key, current))
+            {
+                continue;
+            }
+
+            let references_renamed = self
+                .table_renames
+                .values()
+                .any(|new_name| new_name == &foreign_key.referenced_table);
+            if renamed || references_renamed {
+                deferred_foreign_keys.push((declared.name.to_string(), foreign_key.clone()));
+            } else {
+                self.actions.push(Box::new(AddForeignKey {
+                    table: table.to_string(),
+                    foreign_key: foreign_key.clone(),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn current_table(&self, name: &str) -> Option<&'a TableDump> {
+        let current: &'a SchemaDump = self.current;
+        current.tables.iter().find(|table| table.name == name)
+    }
+}
+
+// Find which of the unmatched tables or columns a declared one has been
+// renamed from, if any. Hints take precedence over asking the resolver.
+fn resolve_rename(
+    table: Option<&str>,
+    name: &str,
+    hint: Option<&str>,
+    unmatched: &mut Vec<String>,
+    resolve: &mut RenameResolver<'_>,
+) -> anyhow::Result<Option<String>> {
+    let old_name = match hint {
+        Some(hint) => {
+            if !unmatched.iter().any(|candidate| candidate == hint) {
+                return Err(anyhow!(
+                    "{} {} is marked as renamed from {}, which doesn't exist or is still declared",
+                    if table.is_some() { "column" } else { "table" },
+                    name,
+                    hint
+                ));
+            }
+            Some(hint.to_string())
+        }
+        None if unmatched.is_empty() => None,
+        None => {
+            let old_name = resolve(&PossibleRename {
+                table: table.map(|table| table.to_string()),
+                name: name.to_string(),
+                candidates: unmatched.clone(),
+            })?;
+            if let Some(old_name) = &old_name {
+                if !unmatched.contains(old_name) {
+                    return Err(anyhow!("{} is not a candidate for renaming", old_name));
+                }
+            }
+            old_name
+        }
+    };
+
+    if let Some(old_name) = &old_name {
+        unmatched.retain(|candidate| candidate != old_name);
+    }
+
+    Ok(old_name)
+}
+
+fn same_foreign_key(a: &ForeignKey, b: &ForeignKey) -> bool {
+    a.columns == b.columns
+        && a.referenced_table == b.referenced_table
+        && a.referenced_columns == b.referenced_columns
+}
+
+// Let Postgres format a declared column's type and default the same way as
+// they are reported for existing columns, by creating a temporary table in a
+// transaction which is then rolled back. Types which don't exist yet, like
+// enums created by the migration, are returned as declared.
+async fn normalize_column(
+    db: &mut dyn Conn,
+    column: &Column,
+) -> anyhow::Result<(String, Option<String>)> {
+    let mut transaction = db.transaction().await?;
+    let created = transaction
+        .run(&format!(
+            "CREATE TEMPORARY TABLE __reshape_generate (\"column\" {} {})",
+            column.data_type,
+            column
+                .default
+                .as_ref()
+                .map(|default| format!("DEFAULT {}", default))
+                .unwrap_or_default()
+        ))
+        .await;
+    let normalized = match created {
+        Ok(()) => {
+            transaction
+                .query(
+                    "
+                SELECT
+                    FORMAT_TYPE(a.atttypid, a.atttypmod) AS data_type,
+                    PG_GET_EXPR(d.adbin, d.adrelid) AS column_default
+                FROM pg_attribute a
+                LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+                WHERE a.attrelid = '__reshape_generate'::REGCLASS AND a.attname = 'column'
+                ",
+                )
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    transaction.rollback().await?;
+
+    Ok(
+        match normalized.ok().as_ref().and_then(|rows| rows.first()) {
+            Some(row) => (row.get("data_type"), row.get("column_default")),
+            None => (column.data_type.to_string(), column.default.clone()),
+        },
+    )
+}
@@ -2,7 +2,7 @@ use anyhow::Context;
 
 use crate::db::Conn;
 
-pub fn set_up_helpers(db: &mut dyn Conn, target_migration: &str) -> anyhow::Result<()> {
+pub async fn set_up_helpers(db: &mut dyn Conn, target_migration: &str) -> anyhow::Result<()> {
     let query = format!(
         "
 			CREATE OR REPLACE FUNCTION reshape.is_new_schema()
@@ -18,12 +18,73 @@ pub fn set_up_helpers(db: &mut dyn Conn, target_migration: &str) -> anyhow::Resu
         target_migration,
     );
     db.query(&query)
+        .await
         .context("failed creating helper function reshape.is_new_schema()")?;
 
     Ok(())
 }
 
-pub fn tear_down_helpers(db: &mut dyn Conn) -> anyhow::Result<()> {
-    db.query("DROP FUNCTION IF EXISTS reshape.is_new_schema;")?;
+pub async fn tear_down_helpers(db: &mut dyn Conn) -> anyhow::Result<()> {
+    db.query("DROP FUNCTION IF EXISTS reshape.is_new_schema;")
+        .await?;
+    Ok(())
+}
+
+// Make every view in a schema reject inserts, updates and deletes, failing
+// with "schema ... is read-only as <reason>". This is used both for the
+// schemas of intermediate migrations and for schemas retained after
+// completion, so the function isn't torn down with the other helpers.
+pub async fn make_schema_read_only(
+    db: &mut dyn Conn,
+    schema_name: &str,
+    reason: &str,
+    hint: &str,
+) -> anyhow::Result<()> {
+    db.run(
+        "
+        CREATE OR REPLACE FUNCTION reshape.reject_write()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            RAISE EXCEPTION 'schema % is read-only as %', TG_TABLE_SCHEMA, TG_ARGV[0]
+                USING HINT = TG_ARGV[1];
+        END
+        $$ language 'plpgsql';
+        ",
+    )
+    .await
+    .context("failed creating helper function reshape.reject_write()")?;
+
+    let views: Vec<String> = db
+        .query_with_params(
+            "
+            SELECT c.relname::TEXT AS name
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind = 'v' AND n.nspname = $1
+            ",
+            &[&schema_name],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    for view in views {
+        db.run(&format!(
+            r#"
+            DROP TRIGGER IF EXISTS reject_writes ON {schema}."{view}";
+            CREATE TRIGGER reject_writes
+            INSTEAD OF INSERT OR UPDATE OR DELETE ON {schema}."{view}"
+            FOR EACH ROW EXECUTE PROCEDURE reshape.reject_write('{reason}', '{hint}');
+            "#,
+            schema = schema_name,
+            view = view,
+            reason = reason,
+            hint = hint,
+        ))
+        .await
+        .with_context(|| format!("failed to make view {} read-only", view))?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,131 @@
+use std::{collections::HashSet, fmt};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    db::Conn,
+    migrations::{Action, Migration},
+    state,
+};
+
+// The migrations which have been completed, as returned by `Reshape::history`,
+// compared against the local migrations
+#[derive(Serialize, Debug, Clone)]
+pub struct History {
+    pub applied: Vec<AppliedMigration>,
+    // Local migrations which haven't been completed yet, in the order they will be applied
+    pub not_applied: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AppliedMigration {
+    pub name: String,
+    pub description: Option<String>,
+    pub completed_at: Option<String>,
+    // Descriptions of each action in the migration
+    pub actions: Vec<String>,
+    // Whether the migration has been completed but has no local migration file
+    pub missing_locally: bool,
+}
+
+pub(crate) async fn history(
+    db: &mut dyn Conn,
+    local_migrations: impl IntoIterator<Item = Migration>,
+) -> anyhow::Result<History> {
+    // Nothing has been applied before Reshape has created its tables
+    let rows = if !state::is_set_up(db).await? {
+        Vec::new()
+    } else {
+        db.query(
+            "
+            SELECT
+                name,
+                description,
+                actions,
+                TO_CHAR(completed_at, 'YYYY-MM-DD HH24:MI:SS') AS completed_at
+            FROM reshape.migrations
+            ORDER BY index ASC
+            ",
+        )
+        .await
+        .context("failed to get applied migrations")?
+    };
+
+    let local_names: Vec<String> = local_migrations
+        .into_iter()
+        .map(|migration| migration.name)
+        .collect();
+    let local_name_set: HashSet<&str> = local_names.iter().map(|name| name.as_str()).collect();
+
+    let mut applied = Vec::with_capacity(rows.len());
+    for row in rows {
+        let name: String = row.get("name");
+        let actions: Vec<Box<dyn Action>> = serde_json::from_value(row.get("actions"))
+            .with_context(|| format!("failed to parse actions of migration {}", name))?;
+
+        applied.push(AppliedMigration {
+            missing_locally: !local_name_set.contains(name.as_str()),
+            description: row.get("description"),
+            completed_at: row.get("completed_at"),
+            actions: actions.iter().map(|action| action.describe()).collect(),
+            name,
+        });
+    }
+
+    let applied_names: HashSet<&str> = applied
+        .iter()
+        .map(|migration| migration.name.as_str())
+        .collect();
+    let not_applied = local_names
+        .iter()
+        .filter(|name| !applied_names.contains(name.as_str()))
+        .cloned()
+        .collect();
+
+    Ok(History {
+        applied,
+        not_applied,
+    })
+}
+
+// The alternate format, `{:#}`, also lists the actions of each applied migration
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.applied.is_empty() {
+            write!(f, "No migrations have been applied")?;
+        } else {
+            write!(f, "Applied migrations:")?;
+        }
+
+        for migration in &self.applied {
+            write!(
+                f,
+                "\n  {}  {}",
+                migration.completed_at.as_deref().unwrap_or("unknown"),
+                migration.name
+            )?;
+            if migration.missing_locally {
+                write!(f, " (missing locally)")?;
+            }
+
+            if f.alternate() {
+                if let Some(description) = &migration.description {
+                    write!(f, "\n      {}", description)?;
+                }
+                for action in &migration.actions {
+                    write!(f, "\n      - {}", action)?;
+                }
+            }
+        }
+
+        if !self.not_applied.is_empty() {
+            write!(f, "\nNot yet applied:")?;
+            for name in &self.not_applied {
+                write!(f, "\n  {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    process::Stdio,
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{
+    db::Conn,
+    migrations::Migration,
+    output::{Event, Output},
+    schema_name_for_migration,
+};
+
+// Hooks run before and after migrations are started, completed and aborted.
+// They can be set for the whole project using `Reshape::with_hooks` and for
+// individual migrations, in which case they run when that migration is part of
+// the batch. Hooks are stored with the migrations in the state, so the hooks of
+// a migration are still around when it's completed or aborted later on.
+//
+// Pre hooks run before the phase saves any changes to the state, so a failing
+// pre hook leaves everything as it was. Phases which are resumed after being
+// interrupted have already changed the state and don't run their pre hooks
+// again. A failing post hook fails the command, but the phase itself has
+// already finished by then.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_migrate: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_migrate: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_complete: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_complete: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_abort: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_abort: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_migrate.is_empty()
+            && self.post_migrate.is_empty()
+            && self.pre_complete.is_empty()
+            && self.post_complete.is_empty()
+            && self.pre_abort.is_empty()
+            && self.post_abort.is_empty()
+    }
+
+    fn get(&self, hook_point: HookPoint) -> &[Hook] {
+        match hook_point {
+            HookPoint::PreMigrate => &self.pre_migrate,
+            HookPoint::PostMigrate => &self.post_migrate,
+            HookPoint::PreComplete => &self.pre_complete,
+            HookPoint::PostComplete => &self.post_complete,
+            HookPoint::PreAbort => &self.pre_abort,
+            HookPoint::PostAbort => &self.post_abort,
+        }
+    }
+}
+
+// SQL hooks run in a transaction on the connection which holds the migration
+// lock. Shell commands are run using `sh -c` in the current directory.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Hook {
+    Sql { sql: String },
+    Shell { command: String },
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::Sql { sql } => write!(f, "SQL: {}", sql.trim()),
+            Hook::Shell { command } => write!(f, "{}", command.trim()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HookPoint {
+    PreMigrate,
+    PostMigrate,
+    PreComplete,
+    PostComplete,
+    PreAbort,
+    PostAbort,
+}
+
+impl HookPoint {
+    fn name(&self) -> &'static str {
+        match self {
+            HookPoint::PreMigrate => "pre_migrate",
+            HookPoint::PostMigrate => "post_migrate",
+            HookPoint::PreComplete => "pre_complete",
+            HookPoint::PostComplete => "post_complete",
+            HookPoint::PreAbort => "pre_abort",
+            HookPoint::PostAbort => "post_abort",
+        }
+    }
+}
+
+// Run the project's hooks for `hook_point` followed by those of each migration
+// in the batch, stopping at the first one which fails
+pub(crate) async fn run_hooks(
+    db: &mut dyn Conn,
+    project_hooks: &Hooks,
+    migrations: &[Migration],
+    hook_point: HookPoint,
+    output: &Output,
+) -> anyhow::Result<()> {
+    let hooks = project_hooks.get(hook_point).iter().chain(
+        migrations
+            .iter()
+            .flat_map(|migration| migration.hooks.get(hook_point)),
+    );
+
+    for hook in hooks {
+        output.emit(Event::Hook {
+            hook: hook_point.name().to_string(),
+            description: hook.to_string(),
+        });
+        run_hook(db, hook, migrations, hook_point, output)
+            .await
+            .with_context(|| format!("{} hook failed: {}", hook_point.name(), hook))?;
+    }
+
+    Ok(())
+}
+
+async fn run_hook(
+    db: &mut dyn Conn,
+    hook: &Hook,
+    migrations: &[Migration],
+    hook_point: HookPoint,
+    output: &Output,
+) -> anyhow::Result<()> {
+    match hook {
+        Hook::Sql { sql } => {
+            let mut transaction = db.transaction().await?;
+            transaction.run(sql).await?;
+            transaction.commit().await
+        }
+        Hook::Shell {
+            command: command_line,
+        } => {
+            // Give the command enough information to know what's being migrated
+            let names: Vec<&str> = migrations
+                .iter()
+                .map(|migration| migration.name.as_str())
+                .collect();
+            let target_schema = migrations
+                .last()
+                .map(|migration| schema_name_for_migration(&migration.name))
+                .unwrap_or_default();
+
+            let mut command = Command::new("sh");
+            command
+                .arg("-c")
+                .arg(command_line)
+                .env("RESHAPE_HOOK", hook_point.name())
+                .env("RESHAPE_MIGRATIONS", names.join(","))
+                .env("RESHAPE_SCHEMA", target_schema);
+
+            // When events are sent elsewhere, stdout may be parsed by a
+            // program, so the command's output is moved to stderr
+            let status = if output.is_text() {
+                command.status().await.context("failed to run command")?
+            } else {
+                let result = command
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .await
+                    .context("failed to run command")?;
+                io::stderr().write_all(&result.stdout).ok();
+                result.status
+            };
+
+            if !status.success() {
+                return Err(anyhow!("command exited with {}", status));
+            }
+            Ok(())
+        }
+    }
+}
@@ -1,30 +1,75 @@
+use std::{future::Future, time::Instant};
+
 use crate::{
-    migrations::{Migration, MigrationContext},
+    migrations::{Action, BackfillOptions, Migration, MigrationContext},
+    output::Output,
     schema::Schema,
 };
 
 use anyhow::{anyhow, Context};
-use colored::*;
-use db::{Conn, DbConn, DbLocker};
-use postgres::Config;
+use db::{Conn, Connector};
+use hooks::{run_hooks, HookPoint};
 use schema::Table;
+use tokio::runtime::Runtime;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(not(feature = "async"))]
+mod asynchronous;
 mod db;
+mod drain;
+mod dump;
+mod generate;
 mod helpers;
+mod history;
+mod hooks;
+mod loader;
 pub mod migrations;
+mod offline;
+mod output;
+mod retention;
 mod schema;
 mod state;
-
+mod status;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod tls;
+
+pub use crate::db::{Blocker, Timeouts};
+pub use crate::drain::{ActiveSession, DrainOptions};
+pub use crate::dump::{ColumnDump, EnumDump, ForeignKeyDump, IndexDump, SchemaDump, TableDump};
+pub use crate::generate::{
+    DeclarativeSchema, DeclaredEnum, DeclaredTable, PossibleRename, RenameResolver,
+};
+pub use crate::history::{AppliedMigration, History};
+pub use crate::hooks::{Hook, Hooks};
+pub use crate::loader::{load_migrations, parse_migration, parse_migrations, MigrationFile};
+pub use crate::offline::{check_migrations, CheckProblem, OfflineSchema};
+pub use crate::output::{Event, EventCallback, Phase};
 pub use crate::state::State;
+pub use crate::status::{Status, StatusCursor, TemporaryObject};
+pub use crate::tls::{TlsMode, TlsOptions};
 
+#[doc(hidden)]
+pub use reshape_macros::embed_migration_files as __embed_migration_files;
+
+// Blocking API, which runs the async `Reshape` to completion on a
+// current-thread Tokio runtime owned by each instance. Like the blocking
+// Postgres client, it can't be used from within another Tokio runtime.
 pub struct Reshape {
-    db: DbLocker,
+    reshape: asynchronous::Reshape,
+    runtime: Runtime,
 }
 
 impl Reshape {
     pub fn new(connection_string: &str) -> anyhow::Result<Reshape> {
-        let config: Config = connection_string.parse()?;
-        Self::new_with_config(&config)
+        Self::connect(asynchronous::Reshape::new(connection_string))
+    }
+
+    // Connect using a connection string, with TLS settings taking precedence
+    // over any set in the connection string
+    pub fn new_with_tls(connection_string: &str, tls: TlsOptions) -> anyhow::Result<Reshape> {
+        Self::connect(asynchronous::Reshape::new_with_tls(connection_string, tls))
     }
 
     pub fn new_with_options(
@@ -34,94 +79,150 @@ impl Reshape {
         username: &str,
         password: &str,
     ) -> anyhow::Result<Reshape> {
-        let mut config = Config::new();
-        config
-            .host(host)
-            .port(port)
-            .user(username)
-            .dbname(database)
-            .password(password);
+        Self::connect(asynchronous::Reshape::new_with_options(
+            host, port, database, username, password,
+        ))
+    }
 
-        Self::new_with_config(&config)
+    pub fn new_with_options_and_tls(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: &str,
+        tls: TlsOptions,
+    ) -> anyhow::Result<Reshape> {
+        Self::connect(asynchronous::Reshape::new_with_options_and_tls(
+            host, port, database, username, password, tls,
+        ))
     }
 
-    fn new_with_config(config: &Config) -> anyhow::Result<Reshape> {
-        let db = DbLocker::connect(config)?;
-        Ok(Reshape { db })
+    fn connect(
+        connect: impl Future<Output = anyhow::Result<asynchronous::Reshape>>,
+    ) -> anyhow::Result<Reshape> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("failed to start Tokio runtime")?;
+        let reshape = runtime.block_on(connect)?;
+        Ok(Reshape { reshape, runtime })
+    }
+
+    // Set how existing rows are backfilled by actions which need to touch every row
+    pub fn with_backfill_options(mut self, options: BackfillOptions) -> Self {
+        self.reshape = self.reshape.with_backfill_options(options);
+        self
+    }
+
+    // Create a schema for every migration applied by `migrate`, not just the
+    // last one, so applications can use any of the intermediate migrations
+    pub fn with_intermediate_schemas(mut self, enabled: bool) -> Self {
+        self.reshape = self.reshape.with_intermediate_schemas(enabled);
+        self
+    }
+
+    // Keep the schemas of up to `count` previous migrations when completing,
+    // so applications which still use them keep working. Retained schemas are
+    // read-only and are dropped once the columns they use are removed.
+    pub fn with_retained_schemas(mut self, count: usize) -> Self {
+        self.reshape = self.reshape.with_retained_schemas(count);
+        self
+    }
+
+    // Set hooks which run before and after migrations are started, completed
+    // and aborted, in addition to any hooks set on the migrations themselves
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.reshape = self.reshape.with_hooks(hooks);
+        self
+    }
+
+    // Wait for sessions using the schemas which are removed by completing a
+    // migration to end before completing it. Disabled by default.
+    pub fn with_drain(mut self, options: Option<DrainOptions>) -> Self {
+        self.reshape = self.reshape.with_drain(options);
+        self
+    }
+
+    // Receive events for the progress of migrations instead of having them
+    // printed as text. See `Event` for the events which are sent.
+    pub fn on_event(mut self, f: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.reshape = self.reshape.on_event(f);
+        self
+    }
+
+    // Set the lock and statement timeouts used for all connections. By
+    // default, the lock timeout is one second and there is no statement timeout.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> anyhow::Result<Self> {
+        let Reshape { reshape, runtime } = self;
+        let reshape = runtime.block_on(reshape.with_timeouts(timeouts))?;
+        Ok(Reshape { reshape, runtime })
     }
 
     pub fn migrate(
         &mut self,
         migrations: impl IntoIterator<Item = Migration>,
     ) -> anyhow::Result<()> {
-        self.db.lock(|db| {
-            let mut state = State::load(db)?;
-            migrate(db, &mut state, migrations)
-        })
+        self.runtime.block_on(self.reshape.migrate(migrations))
     }
 
-    pub fn complete(&mut self) -> anyhow::Result<()> {
-        self.db.lock(|db| {
-            let mut state = State::load(db)?;
-            complete(db, &mut state)
-        })
+    // Report the current state without taking the lock, so that the status
+    // can be checked while another instance is running
+    pub fn status(&mut self) -> anyhow::Result<Status> {
+        self.runtime.block_on(self.reshape.status())
     }
 
-    pub fn abort(&mut self) -> anyhow::Result<()> {
-        self.db.lock(|db| {
-            let mut state = State::load(db)?;
-            abort(db, &mut state)
-        })
+    // List the completed migrations and compare them against the passed local
+    // migrations. Like `status`, this doesn't take the lock.
+    pub fn history(
+        &mut self,
+        migrations: impl IntoIterator<Item = Migration>,
+    ) -> anyhow::Result<History> {
+        self.runtime.block_on(self.reshape.history(migrations))
     }
 
-    pub fn remove(&mut self) -> anyhow::Result<()> {
-        self.db.lock(|db| {
-            let mut state = State::load(db)?;
-
-            // Remove migration schemas and views
-            if let Some(current_migration) = &state::current_migration(db)? {
-                db.run(&format!(
-                    "DROP SCHEMA IF EXISTS {} CASCADE",
-                    schema_name_for_migration(current_migration)
-                ))?;
-            }
+    // Dump the logical schema of a migration. Without a migration, the current
+    // schema is dumped together with the schema of the migration in progress.
+    pub fn dump_schema(&mut self, migration: Option<&str>) -> anyhow::Result<Vec<SchemaDump>> {
+        self.runtime.block_on(self.reshape.dump_schema(migration))
+    }
 
-            if let State::InProgress { migrations } = &state {
-                let target_migration = migrations.last().unwrap().name.to_string();
-                db.run(&format!(
-                    "DROP SCHEMA IF EXISTS {} CASCADE",
-                    schema_name_for_migration(&target_migration)
-                ))?;
-            }
+    // Generate the actions needed to go from the latest schema to the target
+    // schema. `resolve` is asked whether tables and columns which can't be
+    // matched up using hints have been renamed.
+    pub fn generate_migration(
+        &mut self,
+        target: &DeclarativeSchema,
+        resolve: impl FnMut(&PossibleRename) -> anyhow::Result<Option<String>> + Send,
+    ) -> anyhow::Result<Vec<Box<dyn Action>>> {
+        self.runtime
+            .block_on(self.reshape.generate_migration(target, resolve))
+    }
 
-            // Remove all tables
-            let schema = Schema::new();
-            for table in schema.get_tables(db)? {
-                db.run(&format!(
-                    r#"
-                    DROP TABLE IF EXISTS "{}" CASCADE
-                    "#,
-                    table.real_name
-                ))?;
-            }
+    // Revert the most recently completed migration. The revert is applied like
+    // a regular migration and must then be completed or aborted.
+    pub fn revert(&mut self) -> anyhow::Result<()> {
+        self.runtime.block_on(self.reshape.revert())
+    }
 
-            // Remove all enums
-            let enums: Vec<String> = db
-                .query("SELECT typname FROM pg_type WHERE typcategory = 'E'")?
-                .iter()
-                .map(|row| row.get("typname"))
-                .collect();
-            for enum_type in enums {
-                db.run(&format!("DROP TYPE {}", enum_type))?;
-            }
+    // Accept changes made to migrations which have already been applied by
+    // updating their stored checksums to match the passed migrations
+    pub fn rebaseline(
+        &mut self,
+        migrations: impl IntoIterator<Item = Migration>,
+    ) -> anyhow::Result<()> {
+        self.runtime.block_on(self.reshape.rebaseline(migrations))
+    }
 
-            // Reset state
-            state.clear(db)?;
+    pub fn complete(&mut self) -> anyhow::Result<()> {
+        self.runtime.block_on(self.reshape.complete())
+    }
 
-            println!("Reshape and all data has been removed");
+    pub fn abort(&mut self) -> anyhow::Result<()> {
+        self.runtime.block_on(self.reshape.abort())
+    }
 
-            Ok(())
-        })
+    pub fn remove(&mut self) -> anyhow::Result<()> {
+        self.runtime.block_on(self.reshape.remove())
     }
 }
 
@@ -136,27 +237,33 @@ pub fn schema_query_for_migration(migration_name: &str) -> String {
     format!("SET search_path TO {}", schema_name)
 }
 
-fn schema_name_for_migration(migration_name: &str) -> String {
+pub fn schema_name_for_migration(migration_name: &str) -> String {
     format!("migration_{}", migration_name)
 }
 
-fn migrate(
-    db: &mut DbConn,
+#[allow(clippy::too_many_arguments)]
+async fn migrate(
+    db: &mut dyn Conn,
     state: &mut State,
     migrations: impl IntoIterator<Item = Migration>,
+    backfill: &BackfillOptions,
+    intermediate_schemas: bool,
+    hooks: &Hooks,
+    connector: &Connector,
+    output: &Output,
 ) -> anyhow::Result<()> {
     // Make sure no migration is in progress
     if let State::InProgress { .. } = &state {
-        println!(
-            "Migration already in progress, please complete using 'reshape migration complete'"
+        output.message(
+            "Migration already in progress, please complete using 'reshape migration complete'",
         );
         return Ok(());
     }
 
     if let State::Completing { .. } = &state {
-        println!(
-                    "Migration already in progress and has started completion, please finish using 'reshape migration complete'"
-                );
+        output.message(
+            "Migration already in progress and has started completion, please finish using 'reshape migration complete'",
+        );
         return Ok(());
     }
 
@@ -164,12 +271,122 @@ fn migrate(
     // with the already applied ones stored in the state. This will throw an error if the
     // two sets of migrations don't agree, for example if a new migration has been added
     // in between two existing ones.
-    let remaining_migrations = state::remaining_migrations(db, migrations)?;
+    let remaining_migrations = state::remaining_migrations(db, migrations).await?;
     if remaining_migrations.is_empty() {
-        println!("No migrations left to apply");
+        output.message("No migrations left to apply");
         return Ok(());
     }
 
+    // Migrations which were interrupted while being applied have already
+    // started and don't run their pre hooks again
+    if !matches!(state, State::Applying { .. }) {
+        run_hooks(
+            db,
+            hooks,
+            &remaining_migrations,
+            HookPoint::PreMigrate,
+            output,
+        )
+        .await?;
+    }
+
+    apply_migrations(
+        db,
+        state,
+        remaining_migrations,
+        backfill,
+        intermediate_schemas,
+        hooks,
+        connector,
+        output,
+    )
+    .await
+}
+
+// Revert the most recently completed migration by applying a migration made
+// up of the inverse of its actions. The revert is named after the migration
+// before the reverted one, whose schema it recreates.
+async fn revert(
+    db: &mut dyn Conn,
+    state: &mut State,
+    backfill: &BackfillOptions,
+    hooks: &Hooks,
+    connector: &Connector,
+    output: &Output,
+) -> anyhow::Result<()> {
+    let revert_migrations = match state {
+        State::Idle => {
+            let (migration, previous_migration) = state::last_migration(db)
+                .await?
+                .ok_or_else(|| anyhow!("there are no completed migrations to revert"))?;
+            // Reverting needs an earlier schema for applications to go back to,
+            // which doesn't exist for the first migration
+            let previous_migration = previous_migration.ok_or_else(|| {
+                anyhow!(
+                    "migration {} can't be reverted as it's the first migration, so there is no earlier schema to go back to. Undo it with a new migration instead.",
+                    migration.name
+                )
+            })?;
+            let revert_migration = migration.inverse(previous_migration.clone())?;
+            run_hooks(
+                db,
+                hooks,
+                std::slice::from_ref(&revert_migration),
+                HookPoint::PreMigrate,
+                output,
+            )
+            .await?;
+
+            // The revert recreates the previous migration's schema, which
+            // may still be around if it was retained
+            db.run(&format!(
+                "DROP SCHEMA IF EXISTS {} CASCADE",
+                schema_name_for_migration(&previous_migration)
+            ))
+            .await
+            .context("failed to remove retained schema")?;
+
+            state::set_reverting_migration(db, &migration.name).await?;
+
+            output.message(format!("Reverting migration '{}'", migration.name));
+            output.text("");
+            vec![revert_migration]
+        }
+        // A revert which was interrupted is resumed
+        State::Applying { migrations } if state::reverting_migration(db).await?.is_some() => {
+            migrations.clone()
+        }
+        _ => {
+            return Err(anyhow!(
+                "a migration is already in progress. Please complete or abort it before reverting."
+            ))
+        }
+    };
+
+    apply_migrations(
+        db,
+        state,
+        revert_migrations,
+        backfill,
+        false,
+        hooks,
+        connector,
+        output,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn apply_migrations(
+    db: &mut dyn Conn,
+    state: &mut State,
+    remaining_migrations: Vec<Migration>,
+    backfill: &BackfillOptions,
+    intermediate_schemas: bool,
+    hooks: &Hooks,
+    connector: &Connector,
+    output: &Output,
+) -> anyhow::Result<()> {
     // If we have already started applying some migrations we need to ensure that
     // they are the same ones we want to apply now
     if let State::Applying {
@@ -181,6 +398,9 @@ fn migrate(
                 "a previous migration seems to have failed without cleaning up. Please run `reshape migration abort` and then run migrate again."
             ));
         }
+    } else {
+        // Backfill progress is only resumed when re-running migrations which were interrupted
+        migrations::clear_backfill_progress(db).await?;
     }
 
     // Move to the "Applying" state which is necessary as we can't run the migrations
@@ -188,12 +408,18 @@ fn migrate(
     // automatically aborting, this state saves us from dangling migrations. It forces the user
     // to either run migrate again (which works as all migrations are idempotent) or abort.
     state.applying(remaining_migrations.clone());
-    state.save(db)?;
+    state.save(db).await?;
 
-    println!("Applying {} migrations\n", remaining_migrations.len());
+    output.message(format!(
+        "Applying {} migrations",
+        remaining_migrations.len()
+    ));
+    output.text("");
 
     let target_migration = remaining_migrations.last().unwrap().name.to_string();
-    helpers::set_up_helpers(db, &target_migration).context("failed to set up helpers")?;
+    helpers::set_up_helpers(db, &target_migration)
+        .await
+        .context("failed to set up helpers")?;
 
     let mut new_schema = Schema::new();
     let mut last_migration_index = usize::MAX;
@@ -201,36 +427,97 @@ fn migrate(
     let mut result: anyhow::Result<()> = Ok(());
 
     'outer: for (migration_index, migration) in remaining_migrations.iter().enumerate() {
-        println!("Migrating '{}':", migration.name);
+        let migration_started_at = Instant::now();
+        output.emit(Event::MigrationStarted {
+            phase: Phase::Migrate,
+            migration: migration.name.to_string(),
+        });
         last_migration_index = migration_index;
 
         for (action_index, action) in migration.actions.iter().enumerate() {
             last_action_index = action_index;
 
             let description = action.describe();
-            print!("  + {} ", description);
-
-            let ctx =
-                MigrationContext::new(migration_index, action_index, state::current_migration(db)?);
+            let action_started_at = Instant::now();
+            output.emit(Event::ActionStarted {
+                phase: Phase::Migrate,
+                migration: migration.name.to_string(),
+                action: description.to_string(),
+            });
+
+            let ctx = MigrationContext::new(
+                migration_index,
+                action_index,
+                state::current_migration(db).await?,
+            )
+            .with_backfill_options(backfill.clone())
+            .with_connector(connector.clone())
+            .with_output(output.clone());
             result = action
                 .run(&ctx, db, &new_schema)
+                .await
                 .with_context(|| format!("failed to {}", description));
 
+            output.emit(Event::ActionFinished {
+                phase: Phase::Migrate,
+                migration: migration.name.to_string(),
+                action: description.to_string(),
+                duration_ms: output::duration_ms(action_started_at.elapsed()),
+                error: result.as_ref().err().map(|err| format!("{:#}", err)),
+            });
             if result.is_ok() {
                 action.update_schema(&ctx, &mut new_schema);
-                println!("{}", "done".green());
             } else {
-                println!("{}", "failed".red());
+                output.emit(Event::MigrationFinished {
+                    phase: Phase::Migrate,
+                    migration: migration.name.to_string(),
+                    duration_ms: output::duration_ms(migration_started_at.elapsed()),
+                    error: result.as_ref().err().map(|err| format!("{:#}", err)),
+                });
+                break 'outer;
+            }
+        }
+
+        // The schemas of intermediate migrations are created as soon as the
+        // migration has been applied, so that their views don't include any
+        // tables or columns added by later migrations. They are read-only, as
+        // the triggers would treat writes made through them like writes made
+        // through the old schema, undoing the earlier migrations' changes.
+        if intermediate_schemas && migration_index < remaining_migrations.len() - 1 {
+            result = async {
+                create_schema_for_migration(db, &migration.name, &new_schema).await?;
+                helpers::make_schema_read_only(
+                    db,
+                    &schema_name_for_migration(&migration.name),
+                    "it belongs to an intermediate migration",
+                    "Write through the schema of the last migration being applied instead",
+                )
+                .await
+            }
+            .await
+            .with_context(|| format!("failed to create schema for migration {}", migration.name));
+            if result.is_err() {
+                output.emit(Event::MigrationFinished {
+                    phase: Phase::Migrate,
+                    migration: migration.name.to_string(),
+                    duration_ms: output::duration_ms(migration_started_at.elapsed()),
+                    error: result.as_ref().err().map(|err| format!("{:#}", err)),
+                });
                 break 'outer;
             }
         }
 
-        println!();
+        output.emit(Event::MigrationFinished {
+            phase: Phase::Migrate,
+            migration: migration.name.to_string(),
+            duration_ms: output::duration_ms(migration_started_at.elapsed()),
+            error: None,
+        });
     }
 
     // If a migration failed, we abort all the migrations that were applied
     if let Err(err) = result {
-        println!("A migration failed, aborting migrations that have already been applied");
+        output.message("A migration failed, aborting migrations that have already been applied");
 
         // Set to the Aborting state. This is to ensure that the failed
         // migration is fully aborted and nothing is left dangling.
@@ -243,46 +530,94 @@ fn migrate(
         );
 
         // Abort will only
-        abort(db, state)?;
+        abort(db, state, hooks, output).await?;
 
         return Err(err);
     }
 
     // Create schema and views for migration
     create_schema_for_migration(db, &target_migration, &new_schema)
+        .await
         .with_context(|| format!("failed to create schema for migration {}", target_migration))?;
 
     // Update state once migrations have been performed
-    state.in_progress(remaining_migrations);
-    state.save(db).context("failed to save in-progress state")?;
-
-    println!("Migrations have been applied and the new schema is ready for use:");
-    println!(
-        "  - Run '{}' from your application to use the latest schema",
-        schema_query_for_migration(&target_migration)
-    );
-    println!(
-        "  - Run 'reshape migration complete' once your application has been updated and the previous schema is no longer in use"
+    let remaining_migrations_count = remaining_migrations.len();
+    state.in_progress(remaining_migrations.clone());
+    state
+        .save(db)
+        .await
+        .context("failed to save in-progress state")?;
+    migrations::clear_backfill_progress(db).await?;
+
+    run_hooks(
+        db,
+        hooks,
+        &remaining_migrations,
+        HookPoint::PostMigrate,
+        output,
+    )
+    .await?;
+
+    output.text("Migrations have been applied and the new schema is ready for use:");
+    output.emit(Event::SchemaQuery {
+        query: schema_query_for_migration(&target_migration),
+        migration: target_migration,
+    });
+    if intermediate_schemas && remaining_migrations_count > 1 {
+        output.text(&format!(
+            "  - Schemas for the {} intermediate migrations are also available",
+            remaining_migrations_count - 1
+        ));
+    }
+    output.text(
+        "  - Run 'reshape migration complete' once your application has been updated and the previous schema is no longer in use",
     );
     Ok(())
 }
 
-fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
+async fn complete(
+    db: &mut dyn Conn,
+    state: &mut State,
+    retained_schemas: usize,
+    hooks: &Hooks,
+    drain: Option<&DrainOptions>,
+    output: &Output,
+) -> anyhow::Result<()> {
     // Make sure a migration is in progress
     let (remaining_migrations, starting_migration_index, starting_action_index) = match state.clone() {
                 State::InProgress { migrations } => {
+                    // Wait for sessions using schemas which are about to be
+                    // removed. A retained previous schema is kept around, so
+                    // only the intermediate schemas need to drain then. This
+                    // happens before the hooks, as completion doesn't go ahead
+                    // if the sessions don't end.
+                    if let Some(drain) = drain {
+                        let mut schemas: Vec<String> = migrations[..migrations.len() - 1]
+                            .iter()
+                            .map(|migration| schema_name_for_migration(&migration.name))
+                            .collect();
+                        if retained_schemas == 0 {
+                            if let Some(previous) = state::current_migration(db).await? {
+                                schemas.insert(0, schema_name_for_migration(&previous));
+                            }
+                        }
+                        drain::wait_for_drain(db, &schemas, drain, output).await?;
+                    }
+
+                    run_hooks(db, hooks, &migrations, HookPoint::PreComplete, output).await?;
+
                     // Move into the Completing state. Once in this state,
                     // the migration can't be aborted and must be completed.
                     state.completing(migrations.clone(), 0, 0);
-                    state.save(db).context("failed to save state")?;
+                    state.save(db).await.context("failed to save state")?;
 
                     (migrations, 0, 0)
                 },
                 State::Completing {
                     migrations,
-                    current_migration_index,
-                    current_action_index
-                } => (migrations, current_migration_index, current_action_index),
+                    next_migration_index,
+                    next_action_index
+                } => (migrations, next_migration_index, next_action_index),
                 State::Aborting { .. } => {
                     return Err(anyhow!("migration been aborted and can't be completed. Please finish using `reshape migration abort`."))
                 }
@@ -290,19 +625,20 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
                     return Err(anyhow!("a previous migration unexpectedly failed. Please run `reshape migrate` to try applying the migration again."))
                 }
                 State::Idle => {
-                    println!("No migration in progress");
+                    output.message("No migration in progress");
                     return Ok(());
                 }
             };
 
-    // Remove previous migration's schema
-    if let Some(current_migration) = &state::current_migration(db)? {
-        db.run(&format!(
-            "DROP SCHEMA IF EXISTS {} CASCADE",
-            schema_name_for_migration(current_migration)
-        ))
+    // Remove previous migrations' schemas, except for those which should be retained
+    retention::retain_schemas(db, &remaining_migrations, retained_schemas, output)
+        .await
         .context("failed to remove previous migration's schema")?;
-    }
+
+    // Remove any schemas of intermediate migrations, leaving only the schema of
+    // the last migration
+    drop_schemas_for_migrations(db, &remaining_migrations[..remaining_migrations.len() - 1])
+        .await?;
 
     for (migration_index, migration) in remaining_migrations.iter().enumerate() {
         // Skip all the migrations which have already been completed
@@ -310,7 +646,11 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
             continue;
         }
 
-        println!("Completing '{}':", migration.name);
+        let migration_started_at = Instant::now();
+        output.emit(Event::MigrationStarted {
+            phase: Phase::Complete,
+            migration: migration.name.to_string(),
+        });
 
         for (action_index, action) in migration.actions.iter().enumerate() {
             // Skip all actions which have already been completed
@@ -319,16 +659,27 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
             }
 
             let description = action.describe();
-            print!("  + {} ", description);
-
-            let ctx =
-                MigrationContext::new(migration_index, action_index, state::current_migration(db)?);
+            let action_started_at = Instant::now();
+            output.emit(Event::ActionStarted {
+                phase: Phase::Complete,
+                migration: migration.name.to_string(),
+                action: description.to_string(),
+            });
+
+            let ctx = MigrationContext::new(
+                migration_index,
+                action_index,
+                state::current_migration(db).await?,
+            )
+            .with_output(output.clone());
 
             // Update state to indicate that this action has been completed.
             // We won't save this new state until after the action has completed.
+            // The state points at the next action to complete, which may be past
+            // the last action of this migration.
             state.completing(
                 remaining_migrations.clone(),
-                migration_index + 1,
+                migration_index,
                 action_index + 1,
             );
 
@@ -341,16 +692,26 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
             let did_save = {
                 let result = action
                     .complete(&ctx, db)
+                    .await
                     .with_context(|| format!("failed to complete migration {}", migration.name))
                     .with_context(|| format!("failed to complete action: {}", description));
 
+                output.emit(Event::ActionFinished {
+                    phase: Phase::Complete,
+                    migration: migration.name.to_string(),
+                    action: description.to_string(),
+                    duration_ms: output::duration_ms(action_started_at.elapsed()),
+                    error: result.as_ref().err().map(|err| format!("{:#}", err)),
+                });
                 let maybe_transaction = match result {
-                    Ok(maybe_transaction) => {
-                        println!("{}", "done".green());
-                        maybe_transaction
-                    }
+                    Ok(maybe_transaction) => maybe_transaction,
                     Err(e) => {
-                        println!("{}", "failed".red());
+                        output.emit(Event::MigrationFinished {
+                            phase: Phase::Complete,
+                            migration: migration.name.to_string(),
+                            duration_ms: output::duration_ms(migration_started_at.elapsed()),
+                            error: Some(format!("{:#}", e)),
+                        });
                         return Err(e);
                     }
                 };
@@ -365,9 +726,11 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
                 if let Some(mut transaction) = maybe_transaction {
                     state
                         .save(&mut transaction)
+                        .await
                         .context("failed to save state after completing action")?;
                     transaction
                         .commit()
+                        .await
                         .context("failed to commit transaction")?;
 
                     true
@@ -380,52 +743,75 @@ fn complete(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
             if !did_save {
                 state
                     .save(db)
+                    .await
                     .context("failed to save state after completing action")?;
             }
         }
 
-        println!();
+        output.emit(Event::MigrationFinished {
+            phase: Phase::Complete,
+            migration: migration.name.to_string(),
+            duration_ms: output::duration_ms(migration_started_at.elapsed()),
+            error: None,
+        });
     }
 
     // Remove helpers which are no longer in use
-    helpers::tear_down_helpers(db).context("failed to tear down helpers")?;
+    helpers::tear_down_helpers(db)
+        .await
+        .context("failed to tear down helpers")?;
 
     state
         .complete(db)
+        .await
         .context("failed to update state as completed")?;
 
+    run_hooks(
+        db,
+        hooks,
+        &remaining_migrations,
+        HookPoint::PostComplete,
+        output,
+    )
+    .await?;
+
     Ok(())
 }
 
-fn abort(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
-    let (remaining_migrations, last_migration_index, last_action_index) = match state.clone() {
+async fn abort(
+    db: &mut dyn Conn,
+    state: &mut State,
+    hooks: &Hooks,
+    output: &Output,
+) -> anyhow::Result<()> {
+    let (remaining_migrations, migrations_left, actions_left) = match state.clone() {
         State::InProgress { migrations } | State::Applying { migrations } => {
+            run_hooks(db, hooks, &migrations, HookPoint::PreAbort, output).await?;
+
             // Set to the Aborting state. Once this is done, the migration has to
             // be fully aborted and can't be completed.
-            state.aborting(migrations.clone(), 0, 0);
-            state.save(db)?;
+            state.aborting(migrations.clone(), usize::MAX, usize::MAX);
+            state.save(db).await?;
 
             (migrations, usize::MAX, usize::MAX)
         }
         State::Aborting {
             migrations,
-            last_migration_index,
-            last_action_index,
-        } => (migrations, last_migration_index, last_action_index),
+            migrations_left,
+            actions_left,
+        } => (migrations, migrations_left, actions_left),
         State::Completing { .. } => {
             return Err(anyhow!("migration completion has already been started. Please run `reshape migration complete` again to finish it."));
         }
         State::Idle => {
-            println!("No migration is in progress");
+            output.message("No migration is in progress");
             return Ok(());
         }
     };
 
-    // Remove new migration's schema
-    let target_migration = remaining_migrations.last().unwrap().name.to_string();
-    let schema_name = schema_name_for_migration(&target_migration);
-    db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema_name,))
-        .with_context(|| format!("failed to drop schema {}", schema_name))?;
+    // Remove new migration's schema, together with the schemas of any
+    // intermediate migrations
+    drop_schemas_for_migrations(db, &remaining_migrations).await?;
 
     // Abort all pending migrations
     // Abort all migrations in reverse order
@@ -433,52 +819,206 @@ fn abort(db: &mut DbConn, state: &mut State) -> anyhow::Result<()> {
         // Skip migrations which shouldn't be aborted
         // The reason can be that they have already been aborted or that
         // the migration was never applied in the first place.
-        if migration_index >= last_migration_index {
+        if migration_index >= migrations_left {
             continue;
         }
 
-        print!("Aborting '{}' ", migration.name);
+        let migration_started_at = Instant::now();
+        output.emit(Event::MigrationStarted {
+            phase: Phase::Abort,
+            migration: migration.name.to_string(),
+        });
 
         for (action_index, action) in migration.actions.iter().enumerate().rev() {
             // Skip actions which shouldn't be aborted
             // The reason can be that they have already been aborted or that
             // the action was never applied in the first place.
-            if migration_index == last_migration_index - 1 && action_index >= last_action_index {
+            if !state::is_left_to_abort(
+                migrations_left,
+                actions_left,
+                migration_index,
+                action_index,
+            ) {
                 continue;
             }
 
-            let ctx =
-                MigrationContext::new(migration_index, action_index, state::current_migration(db)?);
-            action
-                .abort(&ctx, db)
+            let existing_schema_name = state::current_migration(db).await?;
+            let schema = schema_before_action(
+                &remaining_migrations,
+                migration_index,
+                action_index,
+                &existing_schema_name,
+            );
+            let ctx = MigrationContext::new(migration_index, action_index, existing_schema_name)
+                .with_output(output.clone());
+
+            let description = action.describe();
+            let action_started_at = Instant::now();
+            output.emit(Event::ActionStarted {
+                phase: Phase::Abort,
+                migration: migration.name.to_string(),
+                action: description.to_string(),
+            });
+            let result = action
+                .abort(&ctx, db, &schema)
+                .await
                 .with_context(|| format!("failed to abort migration {}", migration.name))
-                .with_context(|| format!("failed to abort action: {}", action.describe()))?;
+                .with_context(|| format!("failed to abort action: {}", description));
+            output.emit(Event::ActionFinished {
+                phase: Phase::Abort,
+                migration: migration.name.to_string(),
+                action: description,
+                duration_ms: output::duration_ms(action_started_at.elapsed()),
+                error: result.as_ref().err().map(|err| format!("{:#}", err)),
+            });
+            if let Err(err) = result {
+                output.emit(Event::MigrationFinished {
+                    phase: Phase::Abort,
+                    migration: migration.name.to_string(),
+                    duration_ms: output::duration_ms(migration_started_at.elapsed()),
+                    error: Some(format!("{:#}", err)),
+                });
+                return Err(err);
+            }
 
             // Update state with which migrations and actions have been aborted.
             // We don't need to run this in a transaction as aborts are idempotent.
-            state.aborting(remaining_migrations.to_vec(), migration_index, action_index);
-            state.save(db).context("failed to save state")?;
+            // Everything from this action onwards has now been aborted.
+            state.aborting(
+                remaining_migrations.to_vec(),
+                migration_index + 1,
+                action_index,
+            );
+            state.save(db).await.context("failed to save state")?;
         }
 
-        println!("{}", "done".green());
+        output.emit(Event::MigrationFinished {
+            phase: Phase::Abort,
+            migration: migration.name.to_string(),
+            duration_ms: output::duration_ms(migration_started_at.elapsed()),
+            error: None,
+        });
     }
 
-    helpers::tear_down_helpers(db).context("failed to tear down helpers")?;
+    helpers::tear_down_helpers(db)
+        .await
+        .context("failed to tear down helpers")?;
+    migrations::clear_backfill_progress(db).await?;
+    state::clear_reverting_migration(db).await?;
 
     *state = State::Idle;
-    state.save(db).context("failed to save state")?;
+    state.save(db).await.context("failed to save state")?;
+
+    run_hooks(
+        db,
+        hooks,
+        &remaining_migrations,
+        HookPoint::PostAbort,
+        output,
+    )
+    .await?;
 
     Ok(())
 }
 
-fn create_schema_for_migration(
-    db: &mut DbConn,
+// Build the schema as it looked when an action was run by replaying the
+// schema changes of all actions before it. Actions refer to tables and columns
+// by the names they have at that point, which may differ from the real names
+// until the migrations are completed.
+fn schema_before_action(
+    migrations: &[Migration],
+    migration_index: usize,
+    action_index: usize,
+    existing_schema_name: &Option<String>,
+) -> Schema {
+    let mut schema = Schema::new();
+
+    for (current_migration_index, migration) in migrations.iter().enumerate() {
+        for (current_action_index, action) in migration.actions.iter().enumerate() {
+            if (current_migration_index, current_action_index) >= (migration_index, action_index) {
+                return schema;
+            }
+
+            let ctx = MigrationContext::new(
+                current_migration_index,
+                current_action_index,
+                existing_schema_name.clone(),
+            );
+            action.update_schema(&ctx, &mut schema);
+        }
+    }
+
+    schema
+}
+
+async fn remove(db: &mut dyn Conn, state: &mut State, output: &Output) -> anyhow::Result<()> {
+    // Remove migration schemas and views, including any retained schemas
+    for migration in state::completed_migrations(db).await? {
+        db.run(&format!(
+            "DROP SCHEMA IF EXISTS {} CASCADE",
+            schema_name_for_migration(&migration)
+        ))
+        .await?;
+    }
+
+    if let State::InProgress { migrations } = state {
+        drop_schemas_for_migrations(db, migrations).await?;
+    }
+
+    // Remove all tables
+    let schema = Schema::new();
+    for table in schema.get_tables(db).await? {
+        db.run(&format!(
+            r#"
+            DROP TABLE IF EXISTS "{}" CASCADE
+            "#,
+            table.real_name
+        ))
+        .await?;
+    }
+
+    // Remove all enums
+    let enums: Vec<String> = db
+        .query("SELECT typname FROM pg_type WHERE typcategory = 'E'")
+        .await?
+        .iter()
+        .map(|row| row.get("typname"))
+        .collect();
+    for enum_type in enums {
+        db.run(&format!("DROP TYPE {}", enum_type)).await?;
+    }
+
+    // Reset state
+    state.clear(db).await?;
+
+    output.message("Reshape and all data has been removed");
+
+    Ok(())
+}
+
+async fn drop_schemas_for_migrations(
+    db: &mut dyn Conn,
+    migrations: &[Migration],
+) -> anyhow::Result<()> {
+    for migration in migrations {
+        let schema_name = schema_name_for_migration(&migration.name);
+        db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema_name))
+            .await
+            .with_context(|| format!("failed to drop schema {}", schema_name))?;
+    }
+
+    Ok(())
+}
+
+async fn create_schema_for_migration(
+    db: &mut dyn Conn,
     migration_name: &str,
     schema: &Schema,
 ) -> anyhow::Result<()> {
     // Create schema for migration
     let schema_name = schema_name_for_migration(migration_name);
     db.run(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema_name))
+        .await
         .with_context(|| {
             format!(
                 "failed to create schema {} for migration {}",
@@ -487,14 +1027,18 @@ fn create_schema_for_migration(
         })?;
 
     // Create views inside schema
-    for table in schema.get_tables(db)? {
-        create_view_for_table(db, &table, &schema_name)?;
+    for table in schema.get_tables(db).await? {
+        create_view_for_table(db, &table, &schema_name).await?;
     }
 
     Ok(())
 }
 
-fn create_view_for_table(db: &mut impl Conn, table: &Table, schema: &str) -> anyhow::Result<()> {
+async fn create_view_for_table(
+    db: &mut dyn Conn,
+    table: &Table,
+    schema: &str,
+) -> anyhow::Result<()> {
     let select_columns: Vec<String> = table
         .columns
         .iter()
@@ -520,6 +1064,7 @@ fn create_view_for_table(db: &mut impl Conn, table: &Table, schema: &str) -> any
         view_name = table.name,
         columns = select_columns.join(","),
     ))
+    .await
     .with_context(|| format!("failed to create view for table {}", table.name))?;
 
     Ok(())
@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hooks::Hooks,
+    migrations::{Action, Migration},
+};
+
+// The contents of a migration file. The name is optional and defaults to the
+// file name without its extension.
+#[derive(Serialize, Deserialize)]
+pub struct MigrationFile {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub actions: Vec<Box<dyn Action>>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+}
+
+impl MigrationFile {
+    // Decode a migration file, with the format picked from its extension:
+    // json, toml, yaml or yml
+    pub fn decode(data: &str, extension: &str) -> anyhow::Result<MigrationFile> {
+        let migration = match extension {
+            "json" => serde_json::from_str(data)?,
+            "toml" => toml::from_str(data)?,
+            "yaml" | "yml" => serde_yaml::from_str(data)
+                .map_err(|err| anyhow!(clean_yaml_error(&err.to_string())))?,
+            extension => return Err(anyhow!("unrecognized file extension '{}'", extension)),
+        };
+
+        Ok(migration)
+    }
+
+    pub fn into_migration(self, file_stem: &str) -> Migration {
+        Migration {
+            name: self.name.unwrap_or_else(|| file_stem.to_string()),
+            description: self.description,
+            actions: self.actions,
+            hooks: self.hooks,
+        }
+    }
+}
+
+// Load all migrations from the files in `dirs`, in the order they should be
+// applied. Directories which don't exist are skipped.
+pub fn load_migrations(dirs: &[impl AsRef<Path>]) -> anyhow::Result<Vec<Migration>> {
+    let mut file_paths: Vec<PathBuf> = Vec::new();
+    for dir in dirs.iter().map(AsRef::as_ref).filter(|dir| dir.exists()) {
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("failed to read directory {}", dir.display()))?;
+        for entry in entries {
+            file_paths.push(entry?.path());
+        }
+    }
+
+    let files = file_paths
+        .iter()
+        .map(|path| {
+            let data = fs::read_to_string(path)
+                .with_context(|| format!("failed to read migration file {}", path.display()))?;
+            Ok((path.as_path(), data))
+        })
+        .collect::<anyhow::Result<Vec<(&Path, String)>>>()?;
+
+    parse_migrations(files.iter().map(|(path, data)| (*path, data.as_str())))
+}
+
+// Parse migrations from pairs of file names and file contents, for example
+// those embedded using `embed_migrations!`, in the order they should be applied
+pub fn parse_migrations<'a, P: AsRef<Path> + ?Sized + 'a>(
+    files: impl IntoIterator<Item = (&'a P, &'a str)>,
+) -> anyhow::Result<Vec<Migration>> {
+    let mut files: Vec<(&Path, &str)> = files
+        .into_iter()
+        .map(|(path, data)| (path.as_ref(), data))
+        .collect();
+
+    // Files are sorted by their names without extension, naturally so that
+    // "1_test_migration" < "10_test_migration"
+    files.sort_unstable_by(|(path1, _), (path2, _)| {
+        lexical_sort::natural_cmp(&file_stem(path1), &file_stem(path2))
+    });
+
+    files
+        .into_iter()
+        .map(|(path, data)| {
+            parse_migration(path, data)
+                .with_context(|| format!("failed to parse migration file {}", path.display()))
+        })
+        .collect()
+}
+
+// Parse a single migration file, using its name to pick the format and as the
+// default migration name
+pub fn parse_migration(path: impl AsRef<Path>, data: &str) -> anyhow::Result<Migration> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    Ok(MigrationFile::decode(data, extension)?.into_migration(&file_stem(path)))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// Errors from inside actions are wrapped once for every level of nesting, as
+// actions are deserialized through typetag, which repeats the path and the
+// location for each level. Only the innermost path and location are kept,
+// which point at the field that failed to parse, for example:
+// "actions[0].column.nullable: invalid type: string "maybe", expected a boolean at line 10 column 17"
+fn clean_yaml_error(error: &str) -> String {
+    let (mut message, location) = match error.find(" at line ") {
+        Some(index) => {
+            let location = error[index..].split(" at ").nth(1).unwrap_or_default();
+            (&error[..index], Some(location))
+        }
+        None => (error, None),
+    };
+
+    while let Some((path, rest)) = message.split_once(": ") {
+        let is_nested = rest.starts_with(path)
+            && matches!(rest[path.len()..].chars().next(), Some('.' | '[' | ':'));
+        if !is_nested {
+            break;
+        }
+        message = rest;
+    }
+
+    match location {
+        Some(location) => format!("{} at {}", message, location),
+        None => message.to_string(),
+    }
+}
+
+// Embed the migration files of one or more directories in the binary at
+// compile time, returning the parsed migrations in the order they should be
+// applied. Directories are relative to the crate's Cargo.toml.
+//
+//     let migrations = reshape::embed_migrations!("migrations")?;
+//     reshape.migrate(migrations).await?;
+//
+// Changes to the embedded files are picked up automatically, but adding new
+// files requires a rebuild of the crate using the macro, for example by adding
+// `println!("cargo:rerun-if-changed=migrations");` to its build script.
+#[macro_export]
+macro_rules! embed_migrations {
+    ($($dir:literal),+ $(,)?) => {
+        {
+            let files: &[(&str, &str)] = &$crate::__embed_migration_files!($($dir),+);
+            $crate::parse_migrations(files.iter().copied())
+        }
+    };
+}
@@ -1,14 +1,20 @@
 use std::{
-    fs::{self, File},
-    io::Read,
-    path::Path,
+    collections::HashMap,
+    fs,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
 };
 
 use anyhow::Context;
 use clap::{Args, Parser};
+use colored::*;
+use notify::{RecursiveMode, Watcher};
 use reshape::{
-    migrations::{Action, Migration},
-    Reshape,
+    migrations::{Action, BackfillOptions, Migration},
+    DeclarativeSchema, DrainOptions, Hooks, MigrationFile, PossibleRename, Reshape, Timeouts,
+    TlsMode, TlsOptions,
 };
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +23,95 @@ use serde::{Deserialize, Serialize};
 struct Opts {
     #[clap(subcommand)]
     cmd: Command,
+    // Path to the project configuration file. Defaults to reshape.toml, if it exists.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    // Environment from the configuration file to use settings from
+    #[clap(long, global = true)]
+    env: Option<String>,
+    // Either text or json, which prints newline-delimited JSON events
+    #[clap(long, global = true, default_value = "text")]
+    output: OutputFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow::anyhow!(
+                "unknown output format '{}', expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+// Events printed by the CLI itself with `--output json`, in addition to the
+// events sent by the library (see `reshape::Event`). Both use the same format
+// of a single JSON object per line with an `event` field.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum CliEvent<'a> {
+    // The state left behind by a command which starts, completes or aborts
+    // migrations, whether it succeeded or not
+    State {
+        state: String,
+    },
+    Status(&'a reshape::Status),
+    History(&'a reshape::History),
+    SchemaDump {
+        schemas: &'a [reshape::SchemaDump],
+    },
+    Problem(&'a reshape::CheckProblem),
+    MigrationCreated {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        actions: Option<Vec<String>>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+fn print_json(event: &impl Serialize) {
+    // Events only hold types which always serialize successfully
+    println!("{}", serde_json::to_string(event).unwrap());
+}
+
+// Print a message, or send it as a message event with `--output json`
+fn print_message(output: OutputFormat, message: &str) {
+    match output {
+        OutputFormat::Text => println!("{}", message),
+        OutputFormat::Json => print_json(&reshape::Event::Message {
+            message: message.to_string(),
+        }),
+    }
+}
+
+// With `--output json`, report which state a command left the migrations in,
+// even if it failed
+fn report_state(
+    reshape: &mut Reshape,
+    output: OutputFormat,
+    result: anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if output == OutputFormat::Json {
+        if let Ok(status) = reshape.status() {
+            print_json(&CliEvent::State {
+                state: status.state,
+            });
+        }
+    }
+    result
 }
 
 #[derive(Parser)]
@@ -26,30 +121,51 @@ enum Command {
     Migration(MigrationCommand),
 
     #[clap(
-        about = "Output the query your application should use to select the right schema",
+        about = "Watch migrations and apply them again whenever they change, for use during development",
         display_order = 2
     )]
+    Dev(DevOptions),
+
+    #[clap(
+        about = "Show the current state of migrations and any leftover temporary objects",
+        display_order = 3
+    )]
+    Status(StatusOptions),
+
+    #[clap(
+        about = "List completed migrations and compare them against the local migrations",
+        display_order = 4
+    )]
+    History(HistoryOptions),
+
+    #[clap(subcommand)]
+    Schema(SchemaCommand),
+
+    #[clap(
+        about = "Output the query your application should use to select the right schema",
+        display_order = 6
+    )]
     SchemaQuery(FindMigrationsOptions),
 
     #[clap(
         about = "Deprecated. Use `reshape schema-query` instead",
-        display_order = 3
+        display_order = 7
     )]
     GenerateSchemaQuery(FindMigrationsOptions),
 
     #[clap(
         about = "Deprecated. Use `reshape migration start` instead",
-        display_order = 4
+        display_order = 8
     )]
     Migrate(MigrateOptions),
     #[clap(
         about = "Deprecated. Use `reshape migration complete` instead",
-        display_order = 5
+        display_order = 9
     )]
-    Complete(ConnectionOptions),
+    Complete(CompleteOptions),
     #[clap(
         about = "Deprecated. Use `reshape migration abort` instead",
-        display_order = 6
+        display_order = 10
     )]
     Abort(ConnectionOptions),
 }
@@ -58,19 +174,175 @@ enum Command {
 #[clap(about = "Commands for managing migrations", display_order = 1)]
 enum MigrationCommand {
     #[clap(
-        about = "Starts a new migration, applying any migrations which haven't yet been applied",
+        about = "Creates a new migration file with skeletons for the chosen actions",
         display_order = 1
     )]
+    New(NewOptions),
+
+    #[clap(
+        about = "Starts a new migration, applying any migrations which haven't yet been applied",
+        display_order = 2
+    )]
     Start(MigrateOptions),
 
-    #[clap(about = "Completes an in-progress migration", display_order = 2)]
-    Complete(ConnectionOptions),
+    #[clap(about = "Completes an in-progress migration", display_order = 3)]
+    Complete(CompleteOptions),
 
     #[clap(
         about = "Aborts an in-progress migration without losing any data",
-        display_order = 3
+        display_order = 4
     )]
     Abort(ConnectionOptions),
+
+    #[clap(
+        about = "Reverts the most recently completed migration, which then has to be completed or aborted",
+        display_order = 5
+    )]
+    Revert(RevertOptions),
+
+    #[clap(
+        about = "Generates a new migration from the differences between a declarative schema file and the current schema",
+        display_order = 6
+    )]
+    Generate(GenerateOptions),
+
+    #[clap(
+        about = "Checks migrations for problems without connecting to the database",
+        display_order = 7
+    )]
+    Check(FindMigrationsOptions),
+}
+
+#[derive(Args)]
+struct NewOptions {
+    // Name of the migration, which is prefixed with a sequence number
+    name: String,
+    #[clap(long, default_value = "toml")]
+    format: MigrationFormat,
+    // Actions to include a skeleton for, like `--action add_column`. Can be
+    // passed multiple times.
+    #[clap(long)]
+    action: Vec<String>,
+    // Prefix the file with a UTC timestamp instead of the next sequence number
+    #[clap(long)]
+    timestamp: bool,
+    #[clap(flatten)]
+    find_migrations_options: FindMigrationsOptions,
+}
+
+#[derive(Clone, Copy)]
+enum MigrationFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl std::str::FromStr for MigrationFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(anyhow::anyhow!(
+                "unknown format '{}', expected toml, json or yaml",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Args)]
+struct GenerateOptions {
+    // Path to the declarative schema file, in TOML or JSON
+    #[clap(long, default_value = "schema.toml")]
+    schema: PathBuf,
+    // Name of the new migration, used in the file name
+    #[clap(long)]
+    name: String,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+    #[clap(flatten)]
+    find_migrations_options: FindMigrationsOptions,
+}
+
+#[derive(Parser)]
+#[clap(about = "Commands for inspecting schemas", display_order = 5)]
+enum SchemaCommand {
+    #[clap(
+        about = "Output the schema of a migration as seen by the application",
+        display_order = 1
+    )]
+    Dump(SchemaDumpOptions),
+}
+
+#[derive(Args)]
+struct SchemaDumpOptions {
+    // Migration to dump the schema for. Defaults to the current schema and the
+    // schema of the migration in progress, if any.
+    #[clap(long)]
+    migration: Option<String>,
+    // Output the schema as JSON instead of SQL
+    #[clap(long)]
+    json: bool,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+}
+
+#[derive(Args)]
+struct DevOptions {
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+    #[clap(flatten)]
+    find_migrations_options: FindMigrationsOptions,
+    #[clap(flatten)]
+    backfill_options: BackfillCliOptions,
+}
+
+#[derive(Args)]
+struct StatusOptions {
+    // Output the status as JSON instead of human-readable text
+    #[clap(long)]
+    json: bool,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+}
+
+#[derive(Args)]
+struct HistoryOptions {
+    // Also list the actions of each migration
+    #[clap(long, short)]
+    verbose: bool,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+    #[clap(flatten)]
+    find_migrations_options: FindMigrationsOptions,
+}
+
+#[derive(Args)]
+struct CompleteOptions {
+    // Number of previous migrations whose schemas are kept, read-only
+    #[clap(long, default_value = "0")]
+    keep_schemas: usize,
+    #[clap(flatten)]
+    drain_options: DrainCliOptions,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+}
+
+#[derive(Args)]
+struct RevertOptions {
+    #[clap(long, short)]
+    complete: bool,
+    #[clap(long, default_value = "0")]
+    keep_schemas: usize,
+    #[clap(flatten)]
+    drain_options: DrainCliOptions,
+    #[clap(flatten)]
+    connection_options: ConnectionOptions,
+    #[clap(flatten)]
+    backfill_options: BackfillCliOptions,
 }
 
 #[derive(Args)]
@@ -78,180 +350,1205 @@ struct MigrateOptions {
     // Some comment
     #[clap(long, short)]
     complete: bool,
+    // Accept changes to migrations which have already been applied
+    #[clap(long)]
+    rebaseline: bool,
+    // Create schemas for every migration being applied, not just the last one
+    #[clap(long)]
+    intermediate_schemas: bool,
+    // Used together with --complete
+    #[clap(long, default_value = "0")]
+    keep_schemas: usize,
+    #[clap(flatten)]
+    drain_options: DrainCliOptions,
     #[clap(flatten)]
     connection_options: ConnectionOptions,
     #[clap(flatten)]
     find_migrations_options: FindMigrationsOptions,
+    #[clap(flatten)]
+    backfill_options: BackfillCliOptions,
+}
+
+#[derive(Args)]
+struct DrainCliOptions {
+    // Wait for sessions using the schemas removed by completing to end first
+    #[clap(
+        long,
+        help = "Wait for sessions using the schemas which are removed to end first. Sessions are only detected while their application_name contains the schema name or while they hold locks on objects in the schema"
+    )]
+    wait_for_drain: bool,
+    #[clap(long, default_value = "300", requires = "wait-for-drain")]
+    drain_timeout_secs: u64,
+    // Terminate sessions still using the schemas after the timeout
+    #[clap(long, requires = "wait-for-drain")]
+    terminate_stragglers: bool,
+}
+
+impl DrainCliOptions {
+    fn to_drain_options(&self) -> Option<DrainOptions> {
+        if !self.wait_for_drain {
+            return None;
+        }
+
+        Some(DrainOptions {
+            timeout: Duration::from_secs(self.drain_timeout_secs),
+            terminate: self.terminate_stragglers,
+            ..Default::default()
+        })
+    }
+}
+
+// Options which can also be set in the configuration file don't have clap
+// defaults, so that it's possible to tell whether they were passed. The
+// defaults are applied once all sources have been checked, see `ProjectConfig`.
+#[derive(Args)]
+struct BackfillCliOptions {
+    // Defaults to 1000
+    #[clap(long)]
+    backfill_batch_size: Option<u32>,
+    // Defaults to 0
+    #[clap(long)]
+    backfill_delay_ms: Option<u64>,
+    #[clap(long)]
+    backfill_rows_per_second: Option<u32>,
+    // Defaults to 1
+    #[clap(long)]
+    backfill_workers: Option<u32>,
+}
+
+impl BackfillCliOptions {
+    fn to_backfill_options(&self, config: &ProjectConfig) -> BackfillOptions {
+        let backfill = &config.backfill;
+        BackfillOptions {
+            batch_size: self
+                .backfill_batch_size
+                .or(backfill.batch_size)
+                .unwrap_or(1000)
+                .max(1),
+            batch_delay: Duration::from_millis(
+                self.backfill_delay_ms.or(backfill.delay_ms).unwrap_or(0),
+            ),
+            rows_per_second: self.backfill_rows_per_second.or(backfill.rows_per_second),
+            workers: self
+                .backfill_workers
+                .or(backfill.workers)
+                .unwrap_or(1)
+                .max(1),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Parser)]
 struct ConnectionOptions {
     #[clap(long)]
     url: Option<String>,
-    #[clap(long, default_value = "localhost")]
-    host: String,
-    #[clap(long, default_value = "5432")]
-    port: u16,
-    #[clap(long, short, default_value = "postgres")]
-    database: String,
-    #[clap(long, short, default_value = "postgres")]
-    username: String,
-    #[clap(long, short, default_value = "postgres")]
-    password: String,
+    // Defaults to localhost
+    #[clap(long)]
+    host: Option<String>,
+    // Defaults to 5432
+    #[clap(long)]
+    port: Option<u16>,
+    // Defaults to postgres
+    #[clap(long, short)]
+    database: Option<String>,
+    // Defaults to postgres
+    #[clap(long, short)]
+    username: Option<String>,
+    // Defaults to postgres
+    #[clap(long, short)]
+    password: Option<String>,
+    #[clap(long)]
+    sslmode: Option<TlsMode>,
+    #[clap(long)]
+    sslrootcert: Option<PathBuf>,
+    #[clap(long)]
+    sslcert: Option<PathBuf>,
+    #[clap(long)]
+    sslkey: Option<PathBuf>,
+    // Defaults to 1000
+    #[clap(long)]
+    lock_timeout_ms: Option<u64>,
+    // Defaults to no timeout
+    #[clap(long)]
+    statement_timeout_ms: Option<u64>,
+    // Defaults to never terminating blockers
+    #[clap(long)]
+    terminate_idle_blockers_after_ms: Option<u64>,
 }
 
 #[derive(Parser)]
 struct FindMigrationsOptions {
-    #[clap(long, default_value = "migrations")]
+    // Defaults to migrations
+    #[clap(long)]
     dirs: Vec<String>,
 }
 
+impl FindMigrationsOptions {
+    fn dirs(&self, config: &ProjectConfig) -> Vec<String> {
+        if !self.dirs.is_empty() {
+            return self.dirs.clone();
+        }
+        config
+            .dirs
+            .clone()
+            .unwrap_or_else(|| vec!["migrations".to_string()])
+    }
+}
+
+// The project configuration file, reshape.toml by default. Settings are
+// resolved in this order, with the first one set taking precedence:
+//   1. Command line flags
+//   2. Environment variables (only for connection settings)
+//   3. The environment selected with --env
+//   4. The top level of the configuration file
+//   5. Built-in defaults
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ProjectConfig {
+    dirs: Option<Vec<String>>,
+    #[serde(default)]
+    connection: ConnectionConfig,
+    #[serde(default)]
+    timeouts: TimeoutsConfig,
+    #[serde(default)]
+    backfill: BackfillConfig,
+    // Hooks set for an environment replace all hooks at the top level
+    hooks: Option<Hooks>,
+    #[serde(default)]
+    environments: HashMap<String, ProjectConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConnectionConfig {
+    url: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    sslmode: Option<String>,
+    sslrootcert: Option<PathBuf>,
+    sslcert: Option<PathBuf>,
+    sslkey: Option<PathBuf>,
+}
+
+impl ConnectionConfig {
+    fn target(&self) -> ConnectionTarget {
+        ConnectionTarget {
+            url: self.url.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            database: self.database.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+        }
+    }
+
+    fn set_target(&mut self, target: ConnectionTarget) {
+        self.url = target.url;
+        self.host = target.host;
+        self.port = target.port;
+        self.database = target.database;
+        self.username = target.username;
+        self.password = target.password;
+    }
+}
+
+// Where to connect, given either as a URL or as individual settings. A URL
+// from one source replaces all individual settings from sources with lower
+// precedence and the other way around, so the two are never mixed.
+#[derive(Default)]
+struct ConnectionTarget {
+    url: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl ConnectionTarget {
+    fn has_settings(&self) -> bool {
+        self.host.is_some()
+            || self.port.is_some()
+            || self.database.is_some()
+            || self.username.is_some()
+            || self.password.is_some()
+    }
+
+    // It's ambiguous which should be used if a single source sets both
+    fn check(self, source: &str) -> anyhow::Result<Self> {
+        if self.url.is_some() && self.has_settings() {
+            return Err(anyhow::anyhow!(
+                "a connection URL can't be combined with the host, port, database, username or password in {}",
+                source
+            ));
+        }
+        Ok(self)
+    }
+
+    // Apply the settings of a source with higher precedence
+    fn override_with(self, higher: ConnectionTarget) -> ConnectionTarget {
+        if higher.url.is_some() {
+            higher
+        } else if higher.has_settings() {
+            ConnectionTarget {
+                url: None,
+                host: higher.host.or(self.host),
+                port: higher.port.or(self.port),
+                database: higher.database.or(self.database),
+                username: higher.username.or(self.username),
+                password: higher.password.or(self.password),
+            }
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TimeoutsConfig {
+    lock_timeout_ms: Option<u64>,
+    statement_timeout_ms: Option<u64>,
+    terminate_idle_blockers_after_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BackfillConfig {
+    batch_size: Option<u32>,
+    delay_ms: Option<u64>,
+    rows_per_second: Option<u32>,
+    workers: Option<u32>,
+}
+
+impl ProjectConfig {
+    const DEFAULT_PATH: &'static str = "reshape.toml";
+
+    // Load the configuration file and merge the selected environment into it.
+    // A missing file is only an error if its path was passed explicitly.
+    fn load(path: Option<&Path>, env: Option<&str>) -> anyhow::Result<ProjectConfig> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(Self::DEFAULT_PATH), false),
+        };
+
+        let mut config: ProjectConfig = if path.exists() {
+            let data = fs::read_to_string(path)
+                .with_context(|| format!("failed to read config file {}", path.display()))?;
+            toml::from_str(&data)
+                .with_context(|| format!("failed to parse config file {}", path.display()))?
+        } else if required {
+            return Err(anyhow::anyhow!(
+                "config file {} doesn't exist",
+                path.display()
+            ));
+        } else {
+            ProjectConfig::default()
+        };
+
+        config
+            .connection
+            .target()
+            .check(&format!("config file {}", path.display()))?;
+
+        if let Some(env) = env {
+            let environment = config.environments.remove(env).ok_or_else(|| {
+                anyhow::anyhow!("environment '{}' isn't defined in {}", env, path.display())
+            })?;
+            if !environment.environments.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "environment '{}' can't define environments of its own",
+                    env
+                ));
+            }
+            environment.connection.target().check(&format!(
+                "environment '{}' of {}",
+                env,
+                path.display()
+            ))?;
+            environment.merge_into(&mut config);
+        }
+
+        // Directories are relative to the configuration file rather than to
+        // the working directory
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(dirs) = &mut config.dirs {
+            for dir in dirs.iter_mut() {
+                *dir = base.join(&*dir).to_string_lossy().to_string();
+            }
+        }
+
+        Ok(config)
+    }
+
+    // Override the settings of `config` with any set in self
+    fn merge_into(self, config: &mut ProjectConfig) {
+        fn set<T>(target: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *target = value;
+            }
+        }
+
+        set(&mut config.dirs, self.dirs);
+        set(&mut config.hooks, self.hooks);
+
+        let (connection, target) = (self.connection, &mut config.connection);
+        target.set_target(target.target().override_with(connection.target()));
+        set(&mut target.sslmode, connection.sslmode);
+        set(&mut target.sslrootcert, connection.sslrootcert);
+        set(&mut target.sslcert, connection.sslcert);
+        set(&mut target.sslkey, connection.sslkey);
+
+        let (timeouts, target) = (self.timeouts, &mut config.timeouts);
+        set(&mut target.lock_timeout_ms, timeouts.lock_timeout_ms);
+        set(
+            &mut target.statement_timeout_ms,
+            timeouts.statement_timeout_ms,
+        );
+        set(
+            &mut target.terminate_idle_blockers_after_ms,
+            timeouts.terminate_idle_blockers_after_ms,
+        );
+
+        let (backfill, target) = (self.backfill, &mut config.backfill);
+        set(&mut target.batch_size, backfill.batch_size);
+        set(&mut target.delay_ms, backfill.delay_ms);
+        set(&mut target.rows_per_second, backfill.rows_per_second);
+        set(&mut target.workers, backfill.workers);
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let opts: Opts = Opts::parse();
-    run(opts)
+    let output = opts.output;
+
+    match run(opts) {
+        Err(err) if output == OutputFormat::Json => {
+            print_json(&CliEvent::Error {
+                message: format!("{:#}", err),
+            });
+            std::process::exit(1);
+        }
+        result => result,
+    }
 }
 
 fn run(opts: Opts) -> anyhow::Result<()> {
+    let config = ProjectConfig::load(opts.config.as_deref(), opts.env.as_deref())?;
+    let config = &config;
+    let output = opts.output;
+
     match opts.cmd {
         Command::Migration(MigrationCommand::Start(opts)) | Command::Migrate(opts) => {
-            let mut reshape = reshape_from_connection_options(&opts.connection_options)?;
-            let migrations = find_migrations(&opts.find_migrations_options)?;
-            reshape.migrate(migrations)?;
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?
+                    .with_backfill_options(opts.backfill_options.to_backfill_options(config))
+                    .with_intermediate_schemas(opts.intermediate_schemas)
+                    .with_retained_schemas(opts.keep_schemas)
+                    .with_drain(opts.drain_options.to_drain_options());
+            let migrations = find_migrations(&opts.find_migrations_options, config)?;
+
+            let result = (|| -> anyhow::Result<()> {
+                if opts.rebaseline {
+                    reshape.rebaseline(migrations.clone())?;
+                }
+
+                reshape.migrate(migrations)?;
+
+                // Automatically complete migration if --complete flag is set
+                if opts.complete {
+                    reshape.complete()?;
+                }
+
+                Ok(())
+            })();
+            report_state(&mut reshape, output, result)
+        }
+        Command::Migration(MigrationCommand::Revert(opts)) => {
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?
+                    .with_backfill_options(opts.backfill_options.to_backfill_options(config))
+                    .with_retained_schemas(opts.keep_schemas)
+                    .with_drain(opts.drain_options.to_drain_options());
+
+            let result = (|| -> anyhow::Result<()> {
+                reshape.revert()?;
 
-            // Automatically complete migration if --complete flag is set
-            if opts.complete {
-                reshape.complete()?;
+                // Automatically complete revert if --complete flag is set
+                if opts.complete {
+                    reshape.complete()?;
+                }
+
+                Ok(())
+            })();
+            report_state(&mut reshape, output, result)
+        }
+        Command::Migration(MigrationCommand::New(opts)) => new_migration(&opts, config, output),
+        Command::Migration(MigrationCommand::Generate(opts)) => {
+            generate_migration(&opts, config, output)
+        }
+        Command::Dev(opts) => dev(&opts, config, output),
+        Command::Migration(MigrationCommand::Check(opts)) => {
+            let migrations = find_migrations(&opts, config)?;
+            let problems = reshape::check_migrations(&migrations);
+
+            if problems.is_empty() {
+                print_message(
+                    output,
+                    &format!("No problems found in {} migrations", migrations.len()),
+                );
+                return Ok(());
             }
 
-            Ok(())
+            for problem in &problems {
+                match output {
+                    OutputFormat::Text => println!("{}", problem),
+                    OutputFormat::Json => print_json(&CliEvent::Problem(problem)),
+                }
+            }
+            Err(anyhow::anyhow!("found {} problems", problems.len()))
         }
         Command::Migration(MigrationCommand::Complete(opts)) | Command::Complete(opts) => {
-            let mut reshape = reshape_from_connection_options(&opts)?;
-            reshape.complete()
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?
+                    .with_retained_schemas(opts.keep_schemas)
+                    .with_drain(opts.drain_options.to_drain_options());
+            let result = reshape.complete();
+            report_state(&mut reshape, output, result)
         }
         Command::Migration(MigrationCommand::Abort(opts)) | Command::Abort(opts) => {
-            let mut reshape = reshape_from_connection_options(&opts)?;
-            reshape.abort()
+            let mut reshape = reshape_from_connection_options(&opts, config, output)?;
+            let result = reshape.abort();
+            report_state(&mut reshape, output, result)
+        }
+        Command::Status(opts) => {
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?;
+            let status = reshape.status()?;
+
+            if output == OutputFormat::Json {
+                print_json(&CliEvent::Status(&status));
+            } else if opts.json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                println!("{}", status);
+            }
+
+            Ok(())
+        }
+        Command::History(opts) => {
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?;
+            let migrations = find_migrations(&opts.find_migrations_options, config)?;
+            let history = reshape.history(migrations)?;
+
+            if output == OutputFormat::Json {
+                print_json(&CliEvent::History(&history));
+            } else if opts.verbose {
+                println!("{:#}", history);
+            } else {
+                println!("{}", history);
+            }
+
+            Ok(())
+        }
+        Command::Schema(SchemaCommand::Dump(opts)) => {
+            let mut reshape =
+                reshape_from_connection_options(&opts.connection_options, config, output)?;
+            let schemas = reshape.dump_schema(opts.migration.as_deref())?;
+
+            if output == OutputFormat::Json {
+                print_json(&CliEvent::SchemaDump { schemas: &schemas });
+            } else if opts.json {
+                println!("{}", serde_json::to_string_pretty(&schemas)?);
+            } else {
+                let schemas: Vec<String> =
+                    schemas.iter().map(|schema| schema.to_string()).collect();
+                println!("{}", schemas.join("\n\n"));
+            }
+
+            Ok(())
         }
         Command::SchemaQuery(opts) | Command::GenerateSchemaQuery(opts) => {
-            let migrations = find_migrations(&opts)?;
-            let query = migrations
+            let migrations = find_migrations(&opts, config)?;
+            let migration = migrations
                 .last()
-                .map(|migration| reshape::schema_query_for_migration(&migration.name));
-            println!("{}", query.unwrap_or_else(|| "".to_string()));
+                .map(|migration| migration.name.to_string());
+
+            match (output, migration) {
+                (OutputFormat::Text, migration) => println!(
+                    "{}",
+                    migration
+                        .map(|migration| reshape::schema_query_for_migration(&migration))
+                        .unwrap_or_default()
+                ),
+                (OutputFormat::Json, Some(migration)) => print_json(&reshape::Event::SchemaQuery {
+                    query: reshape::schema_query_for_migration(&migration),
+                    migration,
+                }),
+                (OutputFormat::Json, None) => print_message(output, "No migrations found"),
+            }
 
             Ok(())
         }
     }
 }
 
-fn reshape_from_connection_options(opts: &ConnectionOptions) -> anyhow::Result<Reshape> {
+fn reshape_from_connection_options(
+    opts: &ConnectionOptions,
+    config: &ProjectConfig,
+    output: OutputFormat,
+) -> anyhow::Result<Reshape> {
     // Load environment variables from .env file if it exists
     dotenv::dotenv().ok();
 
-    let url_env = std::env::var("DB_URL").ok();
-    let url = url_env.as_ref().or_else(|| opts.url.as_ref());
+    // Flags take precedence over environment variables, which take precedence
+    // over the configuration file
+    let file = &config.connection;
+    let env = |key: &str| std::env::var(key).ok();
+    let resolve = |flag: &Option<String>, key: &str, file: &Option<String>| {
+        flag.clone().or_else(|| env(key)).or_else(|| file.clone())
+    };
+    let resolve_path = |flag: &Option<PathBuf>, key: &str, file: &Option<PathBuf>| {
+        flag.clone()
+            .or_else(|| env(key).map(PathBuf::from))
+            .or_else(|| file.clone())
+    };
+
+    let sslmode = match opts.sslmode {
+        Some(sslmode) => Some(sslmode),
+        None => resolve(&None, "DB_SSLMODE", &file.sslmode)
+            .map(|sslmode| sslmode.parse())
+            .transpose()?,
+    };
+    let tls = TlsOptions {
+        mode: sslmode,
+        root_cert: resolve_path(&opts.sslrootcert, "DB_SSLROOTCERT", &file.sslrootcert),
+        cert: resolve_path(&opts.sslcert, "DB_SSLCERT", &file.sslcert),
+        key: resolve_path(&opts.sslkey, "DB_SSLKEY", &file.sslkey),
+    };
+
+    let timeouts = Timeouts {
+        lock_timeout: Duration::from_millis(
+            opts.lock_timeout_ms
+                .or(config.timeouts.lock_timeout_ms)
+                .unwrap_or(1000),
+        ),
+        statement_timeout: opts
+            .statement_timeout_ms
+            .or(config.timeouts.statement_timeout_ms)
+            .filter(|timeout| *timeout > 0)
+            .map(Duration::from_millis),
+        terminate_idle_blockers_after: opts
+            .terminate_idle_blockers_after_ms
+            .or(config.timeouts.terminate_idle_blockers_after_ms)
+            .map(Duration::from_millis),
+    };
+
+    let hooks = config.hooks.clone().unwrap_or_default();
+
+    // Where to connect is resolved per source, so that a URL and individual
+    // settings from different sources aren't mixed
+    let flags = ConnectionTarget {
+        url: opts.url.clone(),
+        host: opts.host.clone(),
+        port: opts.port,
+        database: opts.database.clone(),
+        username: opts.username.clone(),
+        password: opts.password.clone(),
+    }
+    .check("the command line flags")?;
+    let env_vars = ConnectionTarget {
+        url: env("DB_URL"),
+        host: env("DB_HOST"),
+        port: env("DB_PORT")
+            .map(|port| {
+                port.parse()
+                    .with_context(|| format!("invalid port in DB_PORT: {}", port))
+            })
+            .transpose()?,
+        database: env("DB_NAME"),
+        username: env("DB_USERNAME"),
+        password: env("DB_PASSWORD"),
+    }
+    .check("the environment variables")?;
+    let target = file.target().override_with(env_vars).override_with(flags);
 
     // Use the connection URL if it has been set
-    if let Some(url) = url {
-        return Reshape::new(url);
+    if let Some(url) = target.url {
+        let reshape = Reshape::new_with_tls(&url, tls)?
+            .with_timeouts(timeouts)?
+            .with_hooks(hooks);
+        return Ok(with_output_format(reshape, output));
     }
 
-    let host_env = std::env::var("DB_HOST").ok();
-    let host = host_env.as_ref().unwrap_or_else(|| &opts.host);
+    let host = target.host.unwrap_or_else(|| "localhost".to_string());
+    let port = target.port.unwrap_or(5432);
+    let username = target.username.unwrap_or_else(|| "postgres".to_string());
+    let password = target.password.unwrap_or_else(|| "postgres".to_string());
+    let database = target.database.unwrap_or_else(|| "postgres".to_string());
+
+    let reshape =
+        Reshape::new_with_options_and_tls(&host, port, &database, &username, &password, tls)?
+            .with_timeouts(timeouts)?
+            .with_hooks(hooks);
+    Ok(with_output_format(reshape, output))
+}
+
+fn with_output_format(reshape: Reshape, output: OutputFormat) -> Reshape {
+    match output {
+        OutputFormat::Text => reshape,
+        OutputFormat::Json => reshape.on_event(print_json),
+    }
+}
+
+// Apply all migrations, completing all but the newest one, and then wait for
+// any changes to the migration files before doing it again. Any in-progress
+// migration is aborted first so that changes to the newest migration are
+// picked up.
+fn dev(opts: &DevOptions, config: &ProjectConfig, output: OutputFormat) -> anyhow::Result<()> {
+    let mut reshape = reshape_from_connection_options(&opts.connection_options, config, output)?
+        .with_backfill_options(opts.backfill_options.to_backfill_options(config));
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("failed to set up file watcher")?;
+    for dir in &opts.find_migrations_options.dirs(config) {
+        watcher
+            .watch(Path::new(dir), RecursiveMode::Recursive)
+            .with_context(|| format!("failed to watch directory {}", dir))?;
+    }
+
+    loop {
+        if let Err(err) = dev_cycle(&mut reshape, &opts.find_migrations_options, config, output) {
+            match output {
+                OutputFormat::Text => println!("{} {:#}", "Error:".red(), err),
+                OutputFormat::Json => print_json(&CliEvent::Error {
+                    message: format!("{:#}", err),
+                }),
+            }
+        }
+        if output == OutputFormat::Text {
+            println!();
+        }
+        print_message(output, "Watching for changes to migrations...");
+
+        // Wait for a change and then for the changes to settle, as editors
+        // often write files in several steps
+        loop {
+            let event = receiver
+                .recv()
+                .context("file watcher stopped")?
+                .context("failed to watch migrations")?;
+            if !event.kind.is_access() {
+                break;
+            }
+        }
+        while receiver.recv_timeout(Duration::from_millis(200)).is_ok() {}
 
-    let port = std::env::var("DB_PORT")
-        .ok()
-        .and_then(|port| port.parse::<u16>().ok())
-        .unwrap_or(opts.port);
+        if output == OutputFormat::Text {
+            println!();
+        }
+    }
+}
 
-    let username_env = std::env::var("DB_USERNAME").ok();
-    let username = username_env.as_ref().unwrap_or_else(|| &opts.username);
+fn dev_cycle(
+    reshape: &mut Reshape,
+    find_migrations_options: &FindMigrationsOptions,
+    config: &ProjectConfig,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let migrations = find_migrations(find_migrations_options, config)?;
+    let last_migration = match migrations.last() {
+        Some(migration) => migration.name.to_string(),
+        None => {
+            print_message(output, "No migrations found");
+            return Ok(());
+        }
+    };
 
-    let password_env = std::env::var("DB_PASSWORD").ok();
-    let password = password_env.as_ref().unwrap_or_else(|| &opts.password);
+    // A completion can't be aborted, and finishing it could complete the
+    // newest migration even if it has been changed since it was applied
+    match reshape.status()?.state.as_str() {
+        "idle" => {}
+        "completing" => {
+            return Err(anyhow::anyhow!(
+                "a migration completion was interrupted. Run `reshape migration complete` to finish it, then save a migration to continue"
+            ))
+        }
+        _ => reshape.abort()?,
+    }
 
-    let database_env = std::env::var("DB_NAME").ok();
-    let database = database_env.as_ref().unwrap_or_else(|| &opts.database);
+    if migrations.len() > 1 {
+        reshape.migrate(migrations[..migrations.len() - 1].to_vec())?;
+        reshape.complete()?;
+    }
+    reshape.migrate(migrations)?;
 
-    Reshape::new_with_options(host, port, database, username, password)
+    // The schema query is already part of the events
+    if output == OutputFormat::Json {
+        return Ok(());
+    }
+    println!(
+        "\nSchema query for the latest migration:\n  {}",
+        reshape::schema_query_for_migration(&last_migration)
+    );
+    Ok(())
 }
 
-fn find_migrations(opts: &FindMigrationsOptions) -> anyhow::Result<Vec<Migration>> {
-    let search_paths = opts
-        .dirs
-        .iter()
-        .map(Path::new)
-        // Filter out all directories that don't exist
-        .filter(|path| path.exists());
+fn generate_migration(
+    opts: &GenerateOptions,
+    config: &ProjectConfig,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let data = fs::read_to_string(&opts.schema)
+        .with_context(|| format!("failed to read schema file {}", opts.schema.display()))?;
+    let target: DeclarativeSchema = match opts.schema.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&data)?,
+        Some("toml") => toml::from_str(&data)?,
+        _ => return Err(anyhow::anyhow!("schema file must be a .toml or .json file")),
+    };
+
+    let mut reshape = reshape_from_connection_options(&opts.connection_options, config, output)?;
+    let migrations = find_migrations(&opts.find_migrations_options, config)?;
 
-    // Find all files in the search paths
-    let mut file_paths = Vec::new();
-    for search_path in search_paths {
-        let entries = fs::read_dir(search_path)?;
-        for entry in entries {
-            let path = entry?.path();
-            file_paths.push(path);
+    // The migration is generated from the database, so any local migrations
+    // which haven't been applied would be missing from the comparison
+    let history = reshape.history(migrations.clone())?;
+    if !history.not_applied.is_empty() {
+        return Err(anyhow::anyhow!(
+            "local migrations haven't been applied yet: {}. Apply them before generating a new migration",
+            history.not_applied.join(", ")
+        ));
+    }
+
+    // Prompting would mix with the JSON output, so renames must be marked in
+    // the schema file instead
+    let interactive = output == OutputFormat::Text;
+    let actions =
+        reshape.generate_migration(&target, |rename| prompt_for_rename(rename, interactive))?;
+    if actions.is_empty() {
+        print_message(output, "Schema is up to date, no migration generated");
+        return Ok(());
+    }
+
+    let dir = migrations_dir(&opts.find_migrations_options, config)?;
+    let path = dir.join(format!(
+        "{}_{}.toml",
+        next_migration_number(&migrations),
+        opts.name
+    ));
+
+    let migration = MigrationFile {
+        name: None,
+        description: None,
+        actions,
+        hooks: Hooks::default(),
+    };
+    // Converting to a value first orders plain values before tables, as TOML requires
+    let data = toml::to_string_pretty(&toml::Value::try_from(&migration)?)?;
+    fs::write(&path, data).with_context(|| format!("failed to write {}", path.display()))?;
+
+    match output {
+        OutputFormat::Text => {
+            println!("Generated migration {}", path.display());
+            for action in &migration.actions {
+                println!("  + {}", action.describe());
+            }
         }
+        OutputFormat::Json => print_json(&CliEvent::MigrationCreated {
+            path: path.display().to_string(),
+            actions: Some(
+                migration
+                    .actions
+                    .iter()
+                    .map(|action| action.describe())
+                    .collect(),
+            ),
+        }),
     }
 
-    // Sort all files by their file names (without extension)
-    // The files are sorted naturally, e.g. "1_test_migration" < "10_test_migration"
-    file_paths.sort_unstable_by(|path1, path2| {
-        let file1 = path1.as_path().file_stem().unwrap().to_str().unwrap();
-        let file2 = path2.as_path().file_stem().unwrap().to_str().unwrap();
+    Ok(())
+}
 
-        lexical_sort::natural_cmp(file1, file2)
-    });
+fn new_migration(
+    opts: &NewOptions,
+    config: &ProjectConfig,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let migrations = find_migrations(&opts.find_migrations_options, config)?;
 
-    file_paths
+    // Migrations are sorted by name, and digits sort before letters, so a new
+    // migration would be applied before any existing one without a number
+    if let Some(unnumbered) = migrations
         .iter()
-        .map(|path| {
-            let mut file = File::open(path)?;
+        .find(|migration| !is_number_prefix(migration_prefix(&migration.name)))
+    {
+        return Err(anyhow::anyhow!(
+            "migration {} isn't prefixed with a number, so new migrations would be sorted before it. Rename the existing migrations to start with a number first",
+            unnumbered.name
+        ));
+    }
 
-            // Read file data
-            let mut data = String::new();
-            file.read_to_string(&mut data)?;
+    // Numbered migrations would be sorted before any timestamped ones, so
+    // they can't be added once timestamps are used
+    let prefix = if opts.timestamp {
+        utc_timestamp()
+    } else if let Some(timestamped) = migrations
+        .iter()
+        .find(|migration| is_timestamp_prefix(migration_prefix(&migration.name)))
+    {
+        return Err(anyhow::anyhow!(
+            "migration {} is prefixed with a timestamp, pass --timestamp to do the same for new migrations",
+            timestamped.name
+        ));
+    } else {
+        next_migration_number(&migrations).to_string()
+    };
+    let name = format!("{}_{}", prefix, opts.name);
 
-            Ok((path, data))
-        })
-        .map(|result| {
-            result.and_then(|(path, data)| {
-                let extension = path.extension().and_then(|ext| ext.to_str()).unwrap();
-                let file_migration =
-                    decode_migration_file(&data, extension).with_context(|| {
-                        format!("failed to parse migration file {}", path.display())
-                    })?;
-
-                let file_name = path.file_stem().and_then(|name| name.to_str()).unwrap();
-                Ok(Migration {
-                    name: file_migration.name.unwrap_or_else(|| file_name.to_string()),
-                    description: file_migration.description,
-                    actions: file_migration.actions,
-                })
-            })
-        })
-        .collect()
+    // The name ends up unquoted in the schema name, so it must be a valid
+    // Postgres identifier as is
+    let schema_name = reshape::schema_name_for_migration(&name);
+    if opts.name.is_empty() {
+        return Err(anyhow::anyhow!("migration name can't be empty"));
+    }
+    if !opts
+        .name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(anyhow::anyhow!(
+            "migration name '{}' can only contain lowercase letters, digits and underscores",
+            opts.name
+        ));
+    }
+    if schema_name.len() > 63 {
+        return Err(anyhow::anyhow!(
+            "migration name '{}' is too long, schema name {} must be at most 63 characters",
+            opts.name,
+            schema_name
+        ));
+    }
+
+    let name_without_prefix = |name: &str| -> String {
+        match name.split_once('_') {
+            Some((prefix, rest)) if prefix.chars().all(|c| c.is_ascii_digit()) => rest.to_string(),
+            _ => name.to_string(),
+        }
+    };
+    if let Some(existing) = migrations
+        .iter()
+        .find(|migration| name_without_prefix(&migration.name) == opts.name)
+    {
+        return Err(anyhow::anyhow!(
+            "a migration with the same name already exists: {}",
+            existing.name
+        ));
+    }
+
+    let actions = opts
+        .action
+        .iter()
+        .map(|action| action_skeleton(action))
+        .collect::<anyhow::Result<Vec<serde_yaml::Value>>>()?;
+    let mut migration = serde_yaml::Mapping::new();
+    migration.insert("actions".into(), serde_yaml::Value::Sequence(actions));
+
+    let (extension, data) = match opts.format {
+        MigrationFormat::Toml => (
+            "toml",
+            toml::to_string_pretty(&toml::Value::try_from(&migration)?)?,
+        ),
+        MigrationFormat::Json => ("json", serde_json::to_string_pretty(&migration)? + "\n"),
+        MigrationFormat::Yaml => ("yaml", serde_yaml::to_string(&migration)?),
+    };
+
+    let path = migrations_dir(&opts.find_migrations_options, config)?
+        .join(format!("{}.{}", name, extension));
+    fs::write(&path, data).with_context(|| format!("failed to write {}", path.display()))?;
+
+    match output {
+        OutputFormat::Text => println!("Created migration {}", path.display()),
+        OutputFormat::Json => print_json(&CliEvent::MigrationCreated {
+            path: path.display().to_string(),
+            actions: None,
+        }),
+    }
+    Ok(())
 }
 
-fn decode_migration_file(data: &str, extension: &str) -> anyhow::Result<FileMigration> {
-    let migration: FileMigration = match extension {
-        "json" => serde_json::from_str(data)?,
-        "toml" => toml::from_str(data)?,
-        extension => {
+// A skeleton for an action with its fields filled in with placeholders. The
+// skeletons are checked against the action types so they can't drift.
+fn action_skeleton(action: &str) -> anyhow::Result<serde_yaml::Value> {
+    let skeleton = match action {
+        "create_table" => {
+            "
+            type: create_table
+            name: <table>
+            primary_key: [id]
+            columns:
+              - name: id
+                type: INTEGER
+                generated: ALWAYS AS IDENTITY
+              - name: <column>
+                type: <type>
+                nullable: false
+            "
+        }
+        "rename_table" => {
+            "
+            type: rename_table
+            table: <table>
+            new_name: <new name>
+            "
+        }
+        "remove_table" => {
+            "
+            type: remove_table
+            table: <table>
+            "
+        }
+        "add_column" => {
+            "
+            type: add_column
+            table: <table>
+            up: <expression to fill in existing rows>
+            column:
+              name: <column>
+              type: <type>
+              nullable: true
+            "
+        }
+        "alter_column" => {
+            "
+            type: alter_column
+            table: <table>
+            column: <column>
+            up: <expression converting the old value to the new one>
+            down: <expression converting the new value to the old one>
+            changes:
+              type: <new type>
+            "
+        }
+        "remove_column" => {
+            "
+            type: remove_column
+            table: <table>
+            column: <column>
+            down: <expression to fill in the column for the old schema>
+            "
+        }
+        "add_index" => {
+            "
+            type: add_index
+            table: <table>
+            index:
+              name: <index>
+              columns: [<column>]
+              unique: false
+            "
+        }
+        "remove_index" => {
+            "
+            type: remove_index
+            index: <index>
+            "
+        }
+        "add_foreign_key" => {
+            "
+            type: add_foreign_key
+            table: <table>
+            foreign_key:
+              columns: [<column>]
+              referenced_table: <referenced table>
+              referenced_columns: [<referenced column>]
+            "
+        }
+        "remove_foreign_key" => {
+            "
+            type: remove_foreign_key
+            table: <table>
+            foreign_key: <foreign key>
+            "
+        }
+        "create_enum" => {
+            "
+            type: create_enum
+            name: <enum>
+            values: [<value>]
+            "
+        }
+        "remove_enum" => {
+            "
+            type: remove_enum
+            enum: <enum>
+            "
+        }
+        "custom" => {
+            "
+            type: custom
+            start: <SQL to run when the migration is started>
+            complete: <SQL to run when the migration is completed>
+            abort: <SQL to run when the migration is aborted>
+            "
+        }
+        _ => {
             return Err(anyhow::anyhow!(
-                "unrecognized file extension '{}'",
-                extension
+                "unknown action '{}', expected one of: create_table, rename_table, remove_table, add_column, alter_column, remove_column, add_index, remove_index, add_foreign_key, remove_foreign_key, create_enum, remove_enum, custom",
+                action
             ))
         }
     };
 
-    Ok(migration)
+    let skeleton: serde_yaml::Value = serde_yaml::from_str(&unindent(skeleton))?;
+    serde_yaml::from_value::<Box<dyn Action>>(skeleton.clone())
+        .with_context(|| format!("skeleton for action {} is invalid", action))?;
+    Ok(skeleton)
+}
+
+fn unindent(text: &str) -> String {
+    let indent = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    text.lines()
+        .map(|line| line.get(indent..).unwrap_or_default())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// Migrations are numbered following the existing ones
+fn next_migration_number(migrations: &[Migration]) -> u64 {
+    migrations
+        .iter()
+        .filter_map(|migration| migration_prefix(&migration.name).parse::<u64>().ok())
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+fn migration_prefix(name: &str) -> &str {
+    name.split('_').next().unwrap_or_default()
+}
+
+fn is_number_prefix(prefix: &str) -> bool {
+    !prefix.is_empty() && prefix.chars().all(|c| c.is_ascii_digit())
+}
+
+// Timestamps added by `--timestamp` are formatted as YYYYMMDDHHMMSS
+fn is_timestamp_prefix(prefix: &str) -> bool {
+    prefix.len() == 14 && is_number_prefix(prefix)
+}
+
+// New migrations are written to the first of the migration directories
+fn migrations_dir(opts: &FindMigrationsOptions, config: &ProjectConfig) -> anyhow::Result<PathBuf> {
+    let dir = opts
+        .dirs(config)
+        .first()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("migrations"));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+// The current UTC time formatted as YYYYMMDDHHMMSS
+fn utc_timestamp() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Convert days since the epoch to a date in the proleptic Gregorian calendar,
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Ask whether a table or column has been renamed. Without a terminal to ask
+// on, hints must be added to the schema file instead.
+fn prompt_for_rename(rename: &PossibleRename, interactive: bool) -> anyhow::Result<Option<String>> {
+    let (kind, hint) = match &rename.table {
+        Some(table) => (
+            format!("Column {}.{}", table, rename.name),
+            format!(
+                "add `{} = \"<old name>\"` to `renamed_columns` of table {}",
+                rename.name, table
+            ),
+        ),
+        None => (
+            format!("Table {}", rename.name),
+            format!("add `renamed_from` to table {}", rename.name),
+        ),
+    };
+
+    if !interactive || !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "{} could be new or renamed from one of: {}. To mark it as renamed, {}",
+            kind,
+            rename.candidates.join(", "),
+            hint
+        ));
+    }
+
+    println!("{} is new or has been renamed. Was it renamed from:", kind);
+    for (index, candidate) in rename.candidates.iter().enumerate() {
+        println!("  {}) {}", index + 1, candidate);
+    }
+
+    loop {
+        print!("Enter a number, or leave empty if it's new: ");
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=rename.candidates.len()).contains(&number) => {
+                return Ok(Some(rename.candidates[number - 1].to_string()))
+            }
+            _ => println!("Invalid choice"),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct FileMigration {
-    name: Option<String>,
-    description: Option<String>,
-    actions: Vec<Box<dyn Action>>,
+fn find_migrations(
+    opts: &FindMigrationsOptions,
+    config: &ProjectConfig,
+) -> anyhow::Result<Vec<Migration>> {
+    reshape::load_migrations(&opts.dirs(config))
 }
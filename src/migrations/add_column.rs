@@ -1,9 +1,11 @@
-use super::{common, Action, Column, MigrationContext};
+use super::{backfill, Action, Column, MigrationContext, RemoveColumn};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::{bail, Context};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -11,6 +13,9 @@ pub struct AddColumn {
     pub table: String,
     pub column: Column,
     pub up: Option<Transformation>,
+
+    // Overrides the number of workers used to backfill existing rows
+    pub backfill_workers: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -63,6 +68,7 @@ impl AddColumn {
 }
 
 #[typetag::serde(name = "add_column")]
+#[async_trait]
 impl Action for AddColumn {
     fn describe(&self) -> String {
         format!(
@@ -71,13 +77,13 @@ impl Action for AddColumn {
         )
     }
 
-    fn run(
+    async fn run(
         &self,
         ctx: &MigrationContext,
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
+        let table = schema.get_table(db, &self.table).await?;
         let temp_column_name = self.temp_column_name(ctx);
 
         let mut definition_parts = vec![
@@ -101,10 +107,10 @@ impl Action for AddColumn {
 			ALTER TABLE "{table}"
             ADD COLUMN IF NOT EXISTS {definition};
 			"#,
-            table = self.table,
+            table = table.real_name,
             definition = definition_parts.join(" "),
         );
-        db.run(&query).context("failed to add column")?;
+        db.run(&query).await.context("failed to add column")?;
 
         let declarations: Vec<String> = table
             .columns
@@ -144,14 +150,23 @@ impl Action for AddColumn {
                     temp_column_name = temp_column_name,
                     trigger_name = self.trigger_name(ctx),
                     up = up,
-                    table = self.table,
+                    table = table.real_name,
                     declarations = declarations.join("\n"),
                 );
-                db.run(&query).context("failed to create up trigger")?;
+                db.run(&query)
+                    .await
+                    .context("failed to create up trigger")?;
 
                 // Backfill values in batches
-                common::batch_touch_rows(db, &table.real_name, Some(&temp_column_name))
-                    .context("failed to batch update existing rows")?;
+                backfill::batch_touch_rows(
+                    ctx,
+                    db,
+                    &table.real_name,
+                    Some(&temp_column_name),
+                    self.backfill_workers,
+                )
+                .await
+                .context("failed to batch update existing rows")?;
             }
 
             if let Transformation::Update {
@@ -165,7 +180,7 @@ impl Action for AddColumn {
                     None => bail!("can't use update without previous migration"),
                 };
 
-                let from_table = schema.get_table(db, &from_table)?;
+                let from_table = schema.get_table(db, from_table).await?;
 
                 let from_table_assignments: Vec<String> = from_table
                     .columns
@@ -218,7 +233,9 @@ impl Action for AddColumn {
                     // declarations = from_table_declarations.join("\n"),
                     temp_column_name = temp_column_name,
                 );
-                db.run(&query).context("failed to create up trigger")?;
+                db.run(&query)
+                    .await
+                    .context("failed to create up trigger")?;
 
                 let from_table_columns = from_table
                     .columns
@@ -283,11 +300,19 @@ impl Action for AddColumn {
                     // declarations = declarations.join("\n"),
                 );
                 db.run(&query)
+                    .await
                     .context("failed to create reverse up trigger")?;
 
                 // Backfill values in batches by touching the from table
-                common::batch_touch_rows(db, &from_table.real_name, None)
-                    .context("failed to batch update existing rows")?;
+                backfill::batch_touch_rows(
+                    ctx,
+                    db,
+                    &from_table.real_name,
+                    None,
+                    self.backfill_workers,
+                )
+                .await
+                .context("failed to batch update existing rows")?;
             }
         }
 
@@ -302,23 +327,27 @@ impl Action for AddColumn {
                  ADD CONSTRAINT "{constraint_name}"
                  CHECK ("{column}" IS NOT NULL) NOT VALID
                  "#,
-                table = self.table,
+                table = table.real_name,
                 constraint_name = self.not_null_constraint_name(ctx),
                 column = temp_column_name,
             );
             db.run(&query)
+                .await
                 .context("failed to add NOT NULL constraint")?;
         }
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
-        let mut transaction = db.transaction().context("failed to create transaction")?;
+        let mut transaction = db
+            .transaction()
+            .await
+            .context("failed to create transaction")?;
 
         // Remove triggers and procedures
         let query = format!(
@@ -331,6 +360,7 @@ impl Action for AddColumn {
         );
         transaction
             .run(&query)
+            .await
             .context("failed to drop up trigger")?;
 
         // Update column to be NOT NULL if necessary
@@ -347,6 +377,7 @@ impl Action for AddColumn {
             );
             transaction
                 .run(&query)
+                .await
                 .context("failed to validate NOT NULL constraint")?;
 
             // Update the column to be NOT NULL.
@@ -363,6 +394,7 @@ impl Action for AddColumn {
             );
             transaction
                 .run(&query)
+                .await
                 .context("failed to set column as NOT NULL")?;
 
             // Drop the temporary constraint
@@ -376,6 +408,7 @@ impl Action for AddColumn {
             );
             transaction
                 .run(&query)
+                .await
                 .context("failed to drop NOT NULL constraint")?;
         }
 
@@ -390,6 +423,7 @@ impl Action for AddColumn {
                 temp_column_name = self.temp_column_name(ctx),
                 column_name = self.column.name,
             ))
+            .await
             .context("failed to rename column to final name")?;
 
         Ok(Some(transaction))
@@ -403,17 +437,32 @@ impl Action for AddColumn {
         });
     }
 
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.add_column(&self.table, &self.column.name);
+
+        if let Some(Transformation::Update { table, .. }) = &self.up {
+            schema.require_table(table);
+        }
+    }
+
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table).await?;
+
         // Remove column
         let query = format!(
             r#"
             ALTER TABLE "{table}"
             DROP COLUMN IF EXISTS "{column}"
             "#,
-            table = self.table,
+            table = table.real_name,
             column = self.temp_column_name(ctx),
         );
-        db.run(&query).context("failed to drop column")?;
+        db.run(&query).await.context("failed to drop column")?;
 
         // Remove triggers and procedures
         let query = format!(
@@ -424,8 +473,16 @@ impl Action for AddColumn {
             trigger_name = self.trigger_name(ctx),
             reverse_trigger_name = self.reverse_trigger_name(ctx),
         );
-        db.run(&query).context("failed to drop up trigger")?;
+        db.run(&query).await.context("failed to drop up trigger")?;
 
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RemoveColumn {
+            table: self.table.to_string(),
+            column: self.column.name.to_string(),
+            down: None,
+        }))
+    }
 }
@@ -1,18 +1,21 @@
-use super::{common::ForeignKey, Action, MigrationContext};
+use super::{common::ForeignKey, Action, MigrationContext, RemoveForeignKey};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AddForeignKey {
     pub table: String,
-    foreign_key: ForeignKey,
+    pub foreign_key: ForeignKey,
 }
 
 #[typetag::serde(name = "add_foreign_key")]
+#[async_trait]
 impl Action for AddForeignKey {
     fn describe(&self) -> String {
         format!(
@@ -21,14 +24,16 @@ impl Action for AddForeignKey {
         )
     }
 
-    fn run(
+    async fn run(
         &self,
         ctx: &MigrationContext,
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
-        let referenced_table = schema.get_table(db, &self.foreign_key.referenced_table)?;
+        let table = schema.get_table(db, &self.table).await?;
+        let referenced_table = schema
+            .get_table(db, &self.foreign_key.referenced_table)
+            .await?;
 
         // Add quotes around all column names
         let columns: Vec<String> = table
@@ -57,6 +62,7 @@ impl Action for AddForeignKey {
             referenced_table = referenced_table.real_name,
             referenced_columns = referenced_columns.join(", "),
         ))
+        .await
         .context("failed to create foreign key")?;
 
         db.run(&format!(
@@ -67,12 +73,13 @@ impl Action for AddForeignKey {
             table = table.real_name,
             constraint_name = self.temp_constraint_name(ctx),
         ))
+        .await
         .context("failed to validate foreign key")?;
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -86,25 +93,50 @@ impl Action for AddForeignKey {
             temp_constraint_name = self.temp_constraint_name(ctx),
             constraint_name = self.final_constraint_name(),
         ))
+        .await
         .context("failed to rename temporary constraint")?;
         Ok(None)
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.require_columns(&self.table, &self.foreign_key.columns);
+        schema.require_columns(
+            &self.foreign_key.referenced_table,
+            &self.foreign_key.referenced_columns,
+        );
+        schema.add_foreign_key(&self.table, &self.final_constraint_name());
+    }
+
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table).await?;
+
         db.run(&format!(
             r#"
             ALTER TABLE "{table}"
             DROP CONSTRAINT IF EXISTS "{constraint_name}"
             "#,
-            table = self.table,
+            table = table.real_name,
             constraint_name = self.temp_constraint_name(ctx),
         ))
+        .await
         .context("failed to validate foreign key")?;
 
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RemoveForeignKey {
+            table: self.table.to_string(),
+            foreign_key: self.final_constraint_name(),
+        }))
+    }
 }
 
 impl AddForeignKey {
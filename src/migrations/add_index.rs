@@ -1,9 +1,11 @@
-use super::{Action, MigrationContext};
+use super::{Action, MigrationContext, RemoveIndex};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,6 +25,7 @@ pub struct Index {
 }
 
 #[typetag::serde(name = "add_index")]
+#[async_trait]
 impl Action for AddIndex {
     fn describe(&self) -> String {
         format!(
@@ -31,13 +34,13 @@ impl Action for AddIndex {
         )
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
+        let table = schema.get_table(db, &self.table).await?;
 
         let column_real_names: Vec<String> = table
             .columns
@@ -58,14 +61,15 @@ impl Action for AddIndex {
 			CREATE {unique} INDEX CONCURRENTLY "{name}" ON "{table}" {index_type_def} ({columns}) 
 			"#,
             name = self.index.name,
-            table = self.table,
+            table = table.real_name,
             columns = column_real_names.join(", "),
         ))
+        .await
         .context("failed to create index")?;
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         _db: &'a mut dyn Conn,
@@ -75,14 +79,30 @@ impl Action for AddIndex {
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.add_index(&self.table, &self.index.name, &self.index.columns);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         db.run(&format!(
             r#"
 			DROP INDEX CONCURRENTLY IF EXISTS "{name}"
 			"#,
             name = self.index.name,
         ))
+        .await
         .context("failed to drop index")?;
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RemoveIndex {
+            index: self.index.name.to_string(),
+        }))
+    }
 }
@@ -1,10 +1,12 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
-    migrations::common,
+    migrations::{backfill, common},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::{anyhow, Context};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,6 +17,9 @@ pub struct AlterColumn {
     pub down: Option<String>,
     #[serde(default)]
     pub changes: ColumnChanges,
+
+    // Overrides the number of workers used to backfill existing rows
+    pub backfill_workers: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -27,12 +32,13 @@ pub struct ColumnChanges {
 }
 
 #[typetag::serde(name = "alter_column")]
+#[async_trait]
 impl Action for AlterColumn {
     fn describe(&self) -> String {
         format!("Altering column \"{}\" on \"{}\"", self.column, self.table)
     }
 
-    fn run(
+    async fn run(
         &self,
         ctx: &MigrationContext,
         db: &mut dyn Conn,
@@ -45,7 +51,7 @@ impl Action for AlterColumn {
             return Ok(());
         }
 
-        let table = schema.get_table(db, &self.table)?;
+        let table = schema.get_table(db, &self.table).await?;
 
         let column = table
             .get_column(&self.column)
@@ -59,11 +65,7 @@ impl Action for AlterColumn {
             vec![&temporary_column_name, temporary_column_type];
 
         // Use either new default value or existing one if one exists
-        let default_value = self
-            .changes
-            .default
-            .as_ref()
-            .or_else(|| column.default.as_ref());
+        let default_value = self.changes.default.as_ref().or(column.default.as_ref());
         if let Some(default) = default_value {
             temp_column_definition_parts.push("DEFAULT");
             temp_column_definition_parts.push(default);
@@ -74,10 +76,12 @@ impl Action for AlterColumn {
 			ALTER TABLE "{table}"
             ADD COLUMN IF NOT EXISTS {temp_column_definition}
 			"#,
-            table = self.table,
+            table = table.real_name,
             temp_column_definition = temp_column_definition_parts.join(" "),
         );
-        db.run(&query).context("failed to add temporary column")?;
+        db.run(&query)
+            .await
+            .context("failed to add temporary column")?;
 
         // If up or down wasn't provided, we default to simply moving the value over.
         // This is the correct behaviour for example when only changing the default value.
@@ -141,22 +145,32 @@ impl Action for AlterColumn {
             temp_column = self.temporary_column_name(ctx),
             up = up,
             down = down,
-            table = self.table,
+            table = table.real_name,
             up_trigger = self.up_trigger_name(ctx),
             down_trigger = self.down_trigger_name(ctx),
             declarations = declarations.join("\n"),
         );
         db.run(&query)
+            .await
             .context("failed to create up and down triggers")?;
 
         // Backfill values in batches by touching the previous column
-        common::batch_touch_rows(db, &table.real_name, Some(&column.real_name))
-            .context("failed to batch update existing rows")?;
+        backfill::batch_touch_rows(
+            ctx,
+            db,
+            &table.real_name,
+            Some(&column.real_name),
+            self.backfill_workers,
+        )
+        .await
+        .context("failed to batch update existing rows")?;
 
         // Duplicate any indices to the temporary column
-        let indices = common::get_indices_for_column(db, &table.real_name, &column.real_name)?;
+        let indices =
+            common::get_indices_for_column(db, &table.real_name, &column.real_name).await?;
         for index in indices {
-            let index_columns: Vec<String> = common::get_index_columns(db, &index.name)?
+            let index_columns: Vec<String> = common::get_index_columns(db, &index.name)
+                .await?
                 .into_iter()
                 .map(|idx_column| {
                     // Replace column with temporary column for new index
@@ -179,7 +193,7 @@ impl Action for AlterColumn {
                 table = table.real_name,
                 columns = index_columns.join(", "),
                 index_type = index.index_type,
-            ))
+            )).await
             .context("failed to create temporary index")?;
         }
 
@@ -194,24 +208,30 @@ impl Action for AlterColumn {
                 ADD CONSTRAINT "{constraint_name}"
                 CHECK ("{column}" IS NOT NULL) NOT VALID
                 "#,
-                table = self.table,
+                table = table.real_name,
                 constraint_name = self.not_null_constraint_name(ctx),
                 column = self.temporary_column_name(ctx),
             );
             db.run(&query)
+                .await
                 .context("failed to add NOT NULL constraint")?;
         }
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
         if self.can_short_circuit() {
             if let Some(new_name) = &self.changes.name {
+                // The column has already been renamed if completion was interrupted
+                if !common::column_exists(db, &self.table, &self.column).await? {
+                    return Ok(None);
+                }
+
                 let query = format!(
                     r#"
 			        ALTER TABLE "{table}"
@@ -221,11 +241,21 @@ impl Action for AlterColumn {
                     existing_name = self.column,
                     new_name = new_name,
                 );
-                db.run(&query).context("failed to rename column")?;
+                db.run(&query).await.context("failed to rename column")?;
             }
             return Ok(None);
         }
 
+        // The temporary column has already replaced the old one if completion
+        // was interrupted after renaming it. Dropping the old column again
+        // would drop the new one, so only the triggers are left to remove.
+        let temp_column_exists =
+            common::column_exists(db, &self.table, &self.temporary_column_name(ctx)).await?;
+        if !temp_column_exists {
+            self.drop_triggers(ctx, db, &self.table).await?;
+            return Ok(None);
+        }
+
         // Update column to be NOT NULL if necessary
         let has_not_null_constraint = !db
             .query_with_params(
@@ -236,6 +266,7 @@ impl Action for AlterColumn {
                 ",
                 &[&self.not_null_constraint_name(ctx)],
             )
+            .await
             .context("failed to get any NOT NULL constraint")?
             .is_empty();
         if has_not_null_constraint {
@@ -250,6 +281,7 @@ impl Action for AlterColumn {
                 constraint_name = self.not_null_constraint_name(ctx),
             );
             db.run(&query)
+                .await
                 .context("failed to validate NOT NULL constraint")?;
 
             // Update the column to be NOT NULL.
@@ -264,7 +296,9 @@ impl Action for AlterColumn {
                 table = self.table,
                 column = self.temporary_column_name(ctx),
             );
-            db.run(&query).context("failed to set column as NOT NULL")?;
+            db.run(&query)
+                .await
+                .context("failed to set column as NOT NULL")?;
 
             // Drop the temporary constraint
             let query = format!(
@@ -276,11 +310,12 @@ impl Action for AlterColumn {
                 constraint_name = self.not_null_constraint_name(ctx),
             );
             db.run(&query)
+                .await
                 .context("failed to drop NOT NULL constraint")?;
         }
 
         // Replace old indices with the new temporary ones created for the temporary column
-        let indices = common::get_indices_for_column(db, &self.table, &self.column)?;
+        let indices = common::get_indices_for_column(db, &self.table, &self.column).await?;
         for current_index in indices {
             // To keep the index handling idempotent, we need to do the following:
             // 1. Add a prefix to the existing index
@@ -298,6 +333,7 @@ impl Action for AlterColumn {
                 current_name = target_index_name,
                 new_name = old_index_name,
             ))
+            .await
             .context("failed to rename old index")?;
 
             // Rename temporary index to real name
@@ -309,6 +345,7 @@ impl Action for AlterColumn {
                 temp_index_name = temp_index_name,
                 target_index_name = target_index_name,
             ))
+            .await
             .context("failed to rename temporary index")?;
 
             // Drop old index concurrently
@@ -318,6 +355,7 @@ impl Action for AlterColumn {
                 "#,
                 old_index_name = old_index_name,
             ))
+            .await
             .context("failed to drop old index")?;
         }
 
@@ -329,7 +367,7 @@ impl Action for AlterColumn {
             table = self.table,
             column = self.column,
         );
-        db.run(&query).context("failed to drop old column")?;
+        db.run(&query).await.context("failed to drop old column")?;
 
         // Rename temporary column
         let column_name = self.changes.name.as_deref().unwrap_or(&self.column);
@@ -342,23 +380,10 @@ impl Action for AlterColumn {
             name = column_name,
         );
         db.run(&query)
+            .await
             .context("failed to rename temporary column")?;
 
-        // Remove triggers and procedures
-        let query = format!(
-            r#"
-            DROP TRIGGER IF EXISTS "{up_trigger}" ON "{table}";
-            DROP FUNCTION IF EXISTS "{up_trigger}";
-
-            DROP TRIGGER IF EXISTS "{down_trigger}" ON "{table}";
-            DROP FUNCTION IF EXISTS "{down_trigger}";
-            "#,
-            table = self.table,
-            up_trigger = self.up_trigger_name(ctx),
-            down_trigger = self.down_trigger_name(ctx),
-        );
-        db.run(&query)
-            .context("failed to drop up and down triggers")?;
+        self.drop_triggers(ctx, db, &self.table).await?;
 
         Ok(None)
     }
@@ -381,14 +406,33 @@ impl Action for AlterColumn {
         schema.change_table(&self.table, |table_changes| {
             table_changes.change_column(&self.column, |column_changes| {
                 column_changes.set_column(&self.temporary_column_name(ctx));
+
+                if let Some(new_name) = &self.changes.name {
+                    column_changes.set_name(new_name);
+                }
             });
         });
     }
 
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        match &self.changes.name {
+            Some(new_name) => schema.rename_column(&self.table, &self.column, new_name),
+            None => schema.require_columns(&self.table, &[self.column.to_string()]),
+        }
+    }
+
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table).await?;
+
         // Safely remove any indices created for the temporary column
         let temp_column_name = self.temporary_column_name(ctx);
-        let indices = common::get_indices_for_column(db, &self.table, &temp_column_name)?;
+        let indices =
+            common::get_indices_for_column(db, &table.real_name, &temp_column_name).await?;
         for index in indices {
             let temp_index_name = self.temp_index_name(ctx, index.oid);
             db.query(&format!(
@@ -396,7 +440,8 @@ impl Action for AlterColumn {
                 DROP INDEX CONCURRENTLY IF EXISTS "{index_name}"
                 "#,
                 index_name = temp_index_name,
-            ))?;
+            ))
+            .await?;
         }
 
         // Drop temporary column
@@ -405,12 +450,54 @@ impl Action for AlterColumn {
 			ALTER TABLE "{table}"
             DROP COLUMN IF EXISTS "{temp_column}";
 			"#,
-            table = self.table,
+            table = table.real_name,
             temp_column = self.temporary_column_name(ctx),
         );
-        db.run(&query).context("failed to drop temporary column")?;
+        db.run(&query)
+            .await
+            .context("failed to drop temporary column")?;
+
+        self.drop_triggers(ctx, db, &table.real_name).await?;
+
+        Ok(())
+    }
+
+    // Only renames can be reversed. Other changes, like changing the type,
+    // would need to know what the column looked like before.
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        let is_rename_only = self.up.is_none()
+            && self.down.is_none()
+            && self.changes.data_type.is_none()
+            && self.changes.nullable.is_none()
+            && self.changes.default.is_none();
+        let new_name = self.changes.name.as_ref().filter(|_| is_rename_only)?;
+
+        Some(Box::new(AlterColumn {
+            table: self.table.to_string(),
+            column: new_name.to_string(),
+            up: None,
+            down: None,
+            changes: ColumnChanges {
+                name: Some(self.column.to_string()),
+                ..Default::default()
+            },
+            backfill_workers: None,
+        }))
+    }
+}
 
-        // Remove triggers and procedures
+impl AlterColumn {
+    fn temporary_column_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_new_{}", ctx.prefix(), self.column)
+    }
+
+    // Remove triggers and procedures
+    async fn drop_triggers(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        table: &str,
+    ) -> anyhow::Result<()> {
         let query = format!(
             r#"
             DROP TRIGGER IF EXISTS "{up_trigger}" ON "{table}";
@@ -419,21 +506,16 @@ impl Action for AlterColumn {
             DROP TRIGGER IF EXISTS "{down_trigger}" ON "{table}";
             DROP FUNCTION IF EXISTS "{down_trigger}";
             "#,
-            table = self.table,
+            table = table,
             up_trigger = self.up_trigger_name(ctx),
             down_trigger = self.down_trigger_name(ctx),
         );
         db.run(&query)
+            .await
             .context("failed to drop up and down triggers")?;
 
         Ok(())
     }
-}
-
-impl AlterColumn {
-    fn temporary_column_name(&self, ctx: &MigrationContext) -> String {
-        format!("{}_new_{}", ctx.prefix(), self.column)
-    }
 
     fn up_trigger_name(&self, ctx: &MigrationContext) -> String {
         format!("{}_alter_column_up_trigger", ctx.prefix())
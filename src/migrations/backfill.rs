@@ -0,0 +1,951 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use futures_util::future::join_all;
+use postgres::types::{FromSql, ToSql};
+use serde::{Deserialize, Serialize};
+
+use super::MigrationContext;
+use crate::{
+    db::{Conn, Connector},
+    output::{Event, Output},
+};
+
+// Settings for how existing rows are backfilled when an action needs to
+// touch every row of a table, for example when adding a column with an `up`.
+//
+// By default rows are touched 1000 at a time on a single connection without
+// any pause between batches. To reduce the load on the database, a delay
+// between batches and/or a target throughput can be set. To speed up
+// backfills of large tables, the table can instead be split into ranges
+// which are backfilled in parallel by several workers, each using its own
+// connection. The number of workers can also be set per action.
+#[derive(Clone)]
+pub struct BackfillOptions {
+    pub batch_size: u32,
+    pub batch_delay: Duration,
+    pub rows_per_second: Option<u32>,
+    pub workers: u32,
+    pub on_progress: Option<ProgressCallback>,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&BackfillProgress) + Send + Sync>;
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        BackfillOptions {
+            batch_size: 1000,
+            batch_delay: Duration::ZERO,
+            rows_per_second: None,
+            workers: 1,
+            on_progress: None,
+        }
+    }
+}
+
+impl BackfillOptions {
+    pub fn on_progress(mut self, f: impl Fn(&BackfillProgress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Arc::new(f));
+        self
+    }
+}
+
+// Passed to the progress callback after every batch. When backfilling in
+// parallel, the callback is called by each worker with the rows touched by
+// that worker as well as by all workers together.
+#[derive(Debug, Clone)]
+pub struct BackfillProgress {
+    pub table: String,
+    pub worker: usize,
+    pub worker_rows: u64,
+    pub rows: u64,
+    pub estimated_total_rows: Option<u64>,
+}
+
+// Progress of a backfill which is saved after every batch. If a migration is
+// interrupted, this lets a re-run of `migrate` continue from the last batch
+// rather than touching every row again.
+#[derive(Serialize, Deserialize)]
+struct SavedProgress {
+    cursor: Vec<u8>,
+    rows: u64,
+}
+
+// The part of a table handled by a single worker. Depending on how the table
+// is batched, the bounds are either encoded key values, where the start is
+// exclusive and the end inclusive, or page numbers, where the start is
+// inclusive and the end exclusive. A missing bound means the range is open.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Range {
+    start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct PostgresRawValue {
+    bytes: Vec<u8>,
+}
+
+impl<'a> FromSql<'a> for PostgresRawValue {
+    fn from_sql(
+        _ty: &postgres::types::Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(PostgresRawValue {
+            bytes: raw.to_vec(),
+        })
+    }
+
+    fn accepts(_ty: &postgres::types::Type) -> bool {
+        true
+    }
+}
+
+impl ToSql for PostgresRawValue {
+    fn to_sql(
+        &self,
+        _ty: &postgres::types::Type,
+        out: &mut postgres::types::private::BytesMut,
+    ) -> Result<postgres::types::IsNull, Box<dyn std::error::Error + Sync + Send>>
+    where
+        Self: Sized,
+    {
+        out.extend_from_slice(&self.bytes);
+        Ok(postgres::types::IsNull::No)
+    }
+
+    fn accepts(_ty: &postgres::types::Type) -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
+    postgres::types::to_sql_checked!();
+}
+
+// How rows are split into batches. Tables are preferably batched on their
+// primary key, falling back to a unique index on non-nullable columns. Tables
+// with neither are batched on ranges of physical pages using `ctid`.
+enum BatchKey {
+    Columns(Vec<String>),
+    Ctid,
+}
+
+// How often each worker reports its progress as an event. The progress
+// callback is still called after every batch.
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+// State shared by all workers backfilling a table
+struct Backfill<'a> {
+    options: &'a BackfillOptions,
+    output: &'a Output,
+    table: &'a str,
+    batch_key: BatchKey,
+    touched_column: String,
+    workers: usize,
+    rows: AtomicU64,
+    estimated_total_rows: Option<u64>,
+    failed: AtomicBool,
+}
+
+struct Worker<'a> {
+    backfill: &'a Backfill<'a>,
+    index: usize,
+    progress_key: String,
+    range: Range,
+    cursor: Option<Vec<u8>>,
+    rows: u64,
+    reported: Option<(Instant, u64)>,
+}
+
+impl Worker<'_> {
+    async fn run(&mut self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        let result = match &self.backfill.batch_key {
+            BatchKey::Columns(key_columns) => self.touch_rows_by_columns(db, key_columns).await,
+            BatchKey::Ctid => self.touch_rows_by_ctid(db).await,
+        };
+
+        // Report the last batches, which may have been skipped to keep the
+        // number of events down
+        if let Some((_, reported_rows)) = self.reported {
+            if reported_rows != self.rows {
+                self.report_progress(self.backfill.rows.load(Ordering::SeqCst));
+            }
+        }
+
+        // Stop the other workers early, they will continue from their saved progress
+        // if the migration is run again
+        if result.is_err() {
+            self.backfill.failed.store(true, Ordering::SeqCst);
+        }
+
+        result
+    }
+
+    fn should_stop(&self) -> bool {
+        self.backfill.failed.load(Ordering::SeqCst)
+    }
+
+    async fn batch_done(
+        &mut self,
+        db: &mut dyn Conn,
+        cursor: Vec<u8>,
+        count: u64,
+        elapsed: Duration,
+    ) -> anyhow::Result<()> {
+        self.rows += count;
+        let rows = self.backfill.rows.fetch_add(count, Ordering::SeqCst) + count;
+        save_progress(
+            db,
+            &self.progress_key,
+            &SavedProgress {
+                cursor,
+                rows: self.rows,
+            },
+        )
+        .await?;
+
+        if let Some(on_progress) = &self.backfill.options.on_progress {
+            on_progress(&BackfillProgress {
+                table: self.backfill.table.to_string(),
+                worker: self.index,
+                worker_rows: self.rows,
+                rows,
+                estimated_total_rows: self.backfill.estimated_total_rows,
+            });
+        }
+
+        let report_due = match self.reported {
+            Some((reported_at, _)) => reported_at.elapsed() >= PROGRESS_EVENT_INTERVAL,
+            None => true,
+        };
+        if report_due {
+            self.report_progress(rows);
+        }
+
+        throttle(self.backfill.options, self.backfill.workers, count, elapsed).await;
+        Ok(())
+    }
+
+    fn report_progress(&mut self, rows: u64) {
+        self.backfill.output.emit(Event::BackfillProgress {
+            table: self.backfill.table.to_string(),
+            worker: self.index,
+            worker_rows: self.rows,
+            rows,
+            estimated_total_rows: self.backfill.estimated_total_rows,
+        });
+        self.reported = Some((Instant::now(), self.rows));
+    }
+
+    async fn touch_rows_by_columns(
+        &mut self,
+        db: &mut dyn Conn,
+        key_columns: &[String],
+    ) -> anyhow::Result<()> {
+        let table = self.backfill.table;
+        let touched_column = &self.backfill.touched_column;
+
+        let mut cursor = self
+            .cursor
+            .take()
+            .or_else(|| self.range.start.clone())
+            .map(|bytes| decode_key(table, key_columns, bytes))
+            .transpose()?;
+        let end = self
+            .range
+            .end
+            .clone()
+            .map(|bytes| decode_key(table, key_columns, bytes))
+            .transpose()?;
+
+        let key_columns_list = key_columns
+            .iter()
+            .map(|column| format!("\"{}\"", column))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let key_where = key_columns
+            .iter()
+            .map(|column| {
+                format!(
+                    r#"
+                    "{table}"."{column}" = rows."{column}"
+                    "#,
+                    table = table,
+                    column = column,
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+
+        let returning_columns = key_columns
+            .iter()
+            .map(|column| format!("rows.\"{}\"", column))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let key_columns_descending = key_columns
+            .iter()
+            .map(|column| format!("\"{}\" DESC", column))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        while !self.should_stop() {
+            let batch_start = Instant::now();
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+            let mut conditions: Vec<String> = Vec::new();
+
+            if let Some(cursor) = &cursor {
+                let cursor = bind_key(&mut params, cursor);
+                conditions.push(format!("({}) > ({})", key_columns_list, cursor));
+            }
+
+            if let Some(end) = &end {
+                let end = bind_key(&mut params, end);
+                conditions.push(format!("({}) <= ({})", key_columns_list, end));
+            }
+
+            let range_where = if conditions.is_empty() {
+                "".to_string()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            };
+
+            let query = format!(
+                r#"
+                WITH rows AS (
+                    SELECT {key_columns_list}
+                    FROM public."{table}"
+                    {range_where}
+                    ORDER BY {key_columns_list}
+                    LIMIT {batch_size}
+                ), update AS (
+                    UPDATE public."{table}" "{table}"
+                    SET "{touched_column}" = "{table}"."{touched_column}"
+                    FROM rows
+                    WHERE {key_where}
+                    RETURNING {returning_columns}
+                )
+                SELECT {key_columns_list}, COUNT(*) OVER () AS count
+                FROM update
+                ORDER BY {key_columns_descending}
+                LIMIT 1
+                "#,
+                batch_size = self.backfill.options.batch_size,
+            );
+            let batch = db.query_with_params(&query, &params).await?.first().map(
+                |row| -> (Vec<PostgresRawValue>, i64) {
+                    let last_key = (0..key_columns.len()).map(|index| row.get(index)).collect();
+                    (last_key, row.get("count"))
+                },
+            );
+
+            let (last_key, count) = match batch {
+                Some((last_key, count)) => (last_key, count as u64),
+                None => break,
+            };
+
+            self.batch_done(db, encode_key(&last_key), count, batch_start.elapsed())
+                .await?;
+            cursor = Some(last_key);
+        }
+
+        Ok(())
+    }
+
+    // Touch rows one range of pages at a time. Updated rows may be moved to a
+    // later page and touched again, which is harmless, so we only need to make
+    // sure to stop at the end of the table as it was when the backfill started.
+    // Rows inserted after that are handled by the triggers.
+    async fn touch_rows_by_ctid(&mut self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        let table = self.backfill.table;
+        let touched_column = &self.backfill.touched_column;
+
+        // Without planner statistics we can't tell how many rows fit on a page,
+        // so we err on the side of small batches
+        let (total_pages, rows_per_page) = get_page_stats(db, table).await?;
+        let pages_per_batch = match rows_per_page {
+            Some(rows_per_page) if rows_per_page > 0 => {
+                (i64::from(self.backfill.options.batch_size) / rows_per_page).max(1)
+            }
+            _ => 1,
+        };
+
+        let mut start_page = match self.cursor.take().or_else(|| self.range.start.clone()) {
+            Some(bytes) => decode_page(table, bytes)?,
+            None => 0,
+        };
+        let end_page = match self.range.end.clone() {
+            Some(bytes) => decode_page(table, bytes)?,
+            None => total_pages,
+        };
+
+        while start_page < end_page && !self.should_stop() {
+            let batch_start = Instant::now();
+            let batch_end_page = (start_page + pages_per_batch).min(end_page);
+
+            let query = format!(
+                r#"
+                WITH update AS (
+                    UPDATE public."{table}"
+                    SET "{touched_column}" = "{touched_column}"
+                    WHERE ctid >= '({start_page},0)'::tid AND ctid < '({batch_end_page},0)'::tid
+                    RETURNING 1
+                )
+                SELECT COUNT(*) AS count
+                FROM update
+                "#,
+            );
+            let count: i64 = db
+                .query(&query)
+                .await?
+                .first()
+                .map(|row| row.get("count"))
+                .unwrap_or(0);
+
+            self.batch_done(
+                db,
+                encode_page(batch_end_page),
+                count as u64,
+                batch_start.elapsed(),
+            )
+            .await?;
+            start_page = batch_end_page;
+        }
+
+        Ok(())
+    }
+}
+
+// Touch every row of a table in batches, causing the update triggers set up
+// by an action to fill in any new columns. With more than one worker, the
+// table is split into ranges which are backfilled in parallel on separate
+// connections, while the advisory lock stays with the connection passed in.
+pub async fn batch_touch_rows(
+    ctx: &MigrationContext,
+    db: &mut dyn Conn,
+    table: &str,
+    column: Option<&str>,
+    workers: Option<u32>,
+) -> anyhow::Result<()> {
+    let options = &ctx.backfill;
+    let progress_key = format!("backfill{}_{}", ctx.prefix(), table);
+
+    let batch_key = get_batch_key(db, table).await?;
+
+    // If no column to touch is passed, we default to the first key column (just to make some "update")
+    let touched_column = match (column, &batch_key) {
+        (Some(column), _) => column.to_string(),
+        (None, BatchKey::Columns(key_columns)) => key_columns[0].to_string(),
+        (None, BatchKey::Ctid) => get_first_column_for_table(db, table).await?,
+    };
+
+    let estimated_total_rows = estimate_rows(db, table).await?;
+
+    // Workers can only be used if we are able to open more connections
+    let workers = match &ctx.connector {
+        Some(_) => workers.unwrap_or(options.workers).max(1) as usize,
+        None => 1,
+    };
+    let ranges = plan_ranges(
+        db,
+        &progress_key,
+        table,
+        &batch_key,
+        workers,
+        estimated_total_rows,
+    )
+    .await?;
+
+    let backfill = Backfill {
+        options,
+        output: ctx.output(),
+        table,
+        batch_key,
+        touched_column,
+        workers: ranges.len(),
+        rows: AtomicU64::new(0),
+        estimated_total_rows,
+        failed: AtomicBool::new(false),
+    };
+
+    let mut range_workers = Vec::new();
+    for (index, range) in ranges.into_iter().enumerate() {
+        let progress_key = if backfill.workers == 1 {
+            progress_key.to_string()
+        } else {
+            format!("{}/worker{}", progress_key, index)
+        };
+        let saved_progress = load_progress(db, &progress_key).await?;
+        let rows = saved_progress
+            .as_ref()
+            .map(|progress| progress.rows)
+            .unwrap_or(0);
+        backfill.rows.fetch_add(rows, Ordering::SeqCst);
+
+        range_workers.push(Worker {
+            backfill: &backfill,
+            index,
+            progress_key,
+            range,
+            cursor: saved_progress.map(|progress| progress.cursor),
+            rows,
+            reported: None,
+        });
+    }
+
+    match (&ctx.connector, range_workers.as_mut_slice()) {
+        (_, [worker]) => worker.run(db).await?,
+        (Some(connector), range_workers) => run_in_parallel(connector, range_workers).await?,
+        // Ranges saved by an earlier run can't be backfilled in parallel without
+        // a way to connect, so they are handled one after the other
+        (None, range_workers) => {
+            for worker in range_workers {
+                worker.run(db).await?;
+            }
+        }
+    }
+
+    db.query_with_params(
+        "DELETE FROM reshape.data WHERE key = $1 OR STARTS_WITH(key, $1 || '/')",
+        &[&progress_key],
+    )
+    .await
+    .context("failed to remove backfill progress")?;
+
+    Ok(())
+}
+
+// Run the workers concurrently, each on its own connection
+async fn run_in_parallel(connector: &Connector, workers: &mut [Worker<'_>]) -> anyhow::Result<()> {
+    let results = join_all(workers.iter_mut().map(|worker| async move {
+        let mut db = match connector.connect().await {
+            Ok(db) => db,
+            Err(err) => {
+                worker.backfill.failed.store(true, Ordering::SeqCst);
+                return Err(err).context("failed to connect backfill worker");
+            }
+        };
+        worker
+            .run(&mut db)
+            .await
+            .with_context(|| format!("backfill worker {} failed", worker.index))
+    }))
+    .await;
+
+    results.into_iter().collect()
+}
+
+// Decide how to split a table between workers. Ranges are saved so that an
+// interrupted backfill is resumed with the same ranges, even if the table or
+// the number of workers has changed since.
+async fn plan_ranges(
+    db: &mut dyn Conn,
+    progress_key: &str,
+    table: &str,
+    batch_key: &BatchKey,
+    workers: usize,
+    estimated_total_rows: Option<u64>,
+) -> anyhow::Result<Vec<Range>> {
+    let ranges_key = format!("{}/ranges", progress_key);
+    let saved_ranges = db
+        .query_with_params(
+            "SELECT value FROM reshape.data WHERE key = $1",
+            &[&ranges_key],
+        )
+        .await
+        .context("failed to load backfill ranges")?
+        .first()
+        .map(|row| serde_json::from_value::<Vec<Range>>(row.get("value")))
+        .transpose()?;
+    if let Some(ranges) = saved_ranges {
+        return Ok(ranges);
+    }
+
+    // A serial backfill which was interrupted is also continued serially
+    if workers == 1 || load_progress(db, progress_key).await?.is_some() {
+        return Ok(vec![Range::default()]);
+    }
+
+    let ranges = match batch_key {
+        BatchKey::Columns(key_columns) => {
+            split_key_range(db, table, key_columns, workers, estimated_total_rows).await?
+        }
+        BatchKey::Ctid => split_page_range(db, table, workers).await?,
+    };
+
+    if ranges.len() > 1 {
+        let json = serde_json::to_value(&ranges)?;
+        db.query_with_params(
+            "INSERT INTO reshape.data (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = $2",
+            &[&ranges_key, &json],
+        ).await
+        .context("failed to save backfill ranges")?;
+    }
+
+    Ok(ranges)
+}
+
+// Split the key space into ranges of roughly the same number of rows. The
+// boundaries are taken from a random sample of the table, sized using the
+// planner's estimate of the number of rows. Without an estimate, for
+// example when the table has never been analyzed, the table isn't split.
+async fn split_key_range(
+    db: &mut dyn Conn,
+    table: &str,
+    key_columns: &[String],
+    workers: usize,
+    estimated_total_rows: Option<u64>,
+) -> anyhow::Result<Vec<Range>> {
+    let estimated_total_rows = match estimated_total_rows {
+        Some(rows) if rows > 0 => rows,
+        _ => return Ok(vec![Range::default()]),
+    };
+
+    let sample_size = (workers * 100).max(1000);
+    let sample_percent =
+        (100.0 * sample_size as f64 / estimated_total_rows as f64).clamp(0.0001, 100.0);
+
+    let key_columns_list = key_columns
+        .iter()
+        .map(|column| format!("\"{}\"", column))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let key_columns_descending = key_columns
+        .iter()
+        .map(|column| format!("\"{}\" DESC", column))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let boundaries: Vec<Vec<u8>> = db
+        .query(&format!(
+            r#"
+            WITH sample AS (
+                SELECT {key_columns_list}
+                FROM public."{table}" TABLESAMPLE BERNOULLI ({sample_percent})
+            ), tiles AS (
+                SELECT {key_columns_list}, NTILE({workers}) OVER (ORDER BY {key_columns_list}) AS tile
+                FROM sample
+            )
+            SELECT DISTINCT ON (tile) {key_columns_list}
+            FROM tiles
+            WHERE tile < {workers}
+            ORDER BY tile, {key_columns_descending}
+            "#,
+        )).await
+        .context("failed to sample table")?
+        .iter()
+        .map(|row| {
+            let key: Vec<PostgresRawValue> =
+                (0..key_columns.len()).map(|index| row.get(index)).collect();
+            encode_key(&key)
+        })
+        .collect();
+
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for boundary in boundaries {
+        ranges.push(Range {
+            start,
+            end: Some(boundary.clone()),
+        });
+        start = Some(boundary);
+    }
+    ranges.push(Range { start, end: None });
+
+    Ok(ranges)
+}
+
+// Split the pages of a table evenly between workers
+async fn split_page_range(
+    db: &mut dyn Conn,
+    table: &str,
+    workers: usize,
+) -> anyhow::Result<Vec<Range>> {
+    let (total_pages, _) = get_page_stats(db, table).await?;
+    let pages_per_worker = (total_pages + workers as i64 - 1) / workers as i64;
+    if pages_per_worker == 0 {
+        return Ok(vec![Range::default()]);
+    }
+
+    let ranges = (0..total_pages)
+        .step_by(pages_per_worker as usize)
+        .map(|start| Range {
+            start: Some(encode_page(start)),
+            end: Some(encode_page((start + pages_per_worker).min(total_pages))),
+        })
+        .collect();
+
+    Ok(ranges)
+}
+
+// Keys are compared against one parameter per key column, as Postgres can't
+// infer the type of a single parameter compared against several columns.
+// Returns the placeholders for the bound values.
+fn bind_key<'a>(params: &mut Vec<&'a (dyn ToSql + Sync)>, key: &'a [PostgresRawValue]) -> String {
+    key.iter()
+        .map(|value| {
+            params.push(value);
+            format!("${}", params.len())
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Keys are saved as the raw value of every key column, each prefixed with
+// its length
+fn encode_key(key: &[PostgresRawValue]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in key {
+        bytes.extend_from_slice(&(value.bytes.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&value.bytes);
+    }
+    bytes
+}
+
+fn decode_key(
+    table: &str,
+    key_columns: &[String],
+    bytes: Vec<u8>,
+) -> anyhow::Result<Vec<PostgresRawValue>> {
+    let invalid = || anyhow!("invalid backfill progress for table {}", table);
+
+    let mut key = Vec::new();
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(invalid());
+        }
+        let (length, value) = rest.split_at(4);
+        let length = u32::from_be_bytes(length.try_into()?) as usize;
+        if value.len() < length {
+            return Err(invalid());
+        }
+        let (value, remaining) = value.split_at(length);
+        key.push(PostgresRawValue {
+            bytes: value.to_vec(),
+        });
+        rest = remaining;
+    }
+
+    if key.len() != key_columns.len() {
+        return Err(invalid());
+    }
+    Ok(key)
+}
+
+fn encode_page(page: i64) -> Vec<u8> {
+    page.to_be_bytes().to_vec()
+}
+
+fn decode_page(table: &str, bytes: Vec<u8>) -> anyhow::Result<i64> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| anyhow!("invalid backfill progress for table {}", table))?;
+    Ok(i64::from_be_bytes(bytes))
+}
+
+// Remove the saved progress of all backfills. This should be done whenever
+// a set of migrations is started from scratch, applied or aborted so that
+// stale progress is never resumed.
+pub async fn clear_progress(db: &mut dyn Conn) -> anyhow::Result<()> {
+    db.run("DELETE FROM reshape.data WHERE key LIKE 'backfill\\_%'")
+        .await
+        .context("failed to clear backfill progress")
+}
+
+async fn load_progress(db: &mut dyn Conn, key: &str) -> anyhow::Result<Option<SavedProgress>> {
+    let progress = db
+        .query_with_params("SELECT value FROM reshape.data WHERE key = $1", &[&key])
+        .await
+        .context("failed to load backfill progress")?
+        .first()
+        .map(|row| serde_json::from_value(row.get("value")))
+        .transpose()?;
+    Ok(progress)
+}
+
+async fn save_progress(
+    db: &mut dyn Conn,
+    key: &str,
+    progress: &SavedProgress,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_value(progress)?;
+    db.query_with_params(
+        "INSERT INTO reshape.data (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = $2",
+        &[&key, &json],
+    ).await
+    .context("failed to save backfill progress")?;
+    Ok(())
+}
+
+// Sleep between batches to honour the configured delay and throughput target.
+// The throughput target is shared evenly between all workers.
+async fn throttle(options: &BackfillOptions, workers: usize, rows: u64, elapsed: Duration) {
+    let mut wait_time = options.batch_delay;
+
+    if let Some(rows_per_second) = options.rows_per_second {
+        let worker_rows_per_second = f64::from(rows_per_second.max(1)) / workers as f64;
+        let target = Duration::from_secs_f64(rows as f64 / worker_rows_per_second);
+        wait_time = wait_time.max(target.saturating_sub(elapsed));
+    }
+
+    if !wait_time.is_zero() {
+        tokio::time::sleep(wait_time).await;
+    }
+}
+
+// Estimate the number of rows in a table using the planner statistics,
+// which avoids a full count of large tables
+async fn estimate_rows(db: &mut dyn Conn, table: &str) -> anyhow::Result<Option<u64>> {
+    let estimate = db
+        .query_with_params(
+            "SELECT reltuples::BIGINT AS estimate FROM pg_class WHERE oid = $1::TEXT::regclass",
+            &[&format!("public.\"{}\"", table)],
+        )
+        .await?
+        .first()
+        .map(|row| row.get::<'_, _, i64>("estimate"))
+        .filter(|estimate| *estimate >= 0)
+        .map(|estimate| estimate as u64);
+    Ok(estimate)
+}
+
+async fn get_batch_key(db: &mut dyn Conn, table: &str) -> anyhow::Result<BatchKey> {
+    let primary_key = get_primary_key_columns_for_table(db, table).await?;
+    if !primary_key.is_empty() {
+        return Ok(BatchKey::Columns(primary_key));
+    }
+
+    if let Some(columns) = get_unique_not_null_index_columns_for_table(db, table).await? {
+        return Ok(BatchKey::Columns(columns));
+    }
+
+    // Pages can only be scanned in ranges for plain tables. Partitioned tables
+    // have no storage of their own and their partitions' pages overlap.
+    let kind: Option<i8> = db
+        .query_with_params(
+            "SELECT relkind FROM pg_class WHERE oid = $1::TEXT::regclass",
+            &[&format!("public.\"{}\"", table)],
+        )
+        .await?
+        .first()
+        .map(|row| row.get("relkind"));
+    if kind == Some(b'r' as i8) {
+        return Ok(BatchKey::Ctid);
+    }
+
+    Err(anyhow!(
+        "unable to backfill table {}: it has no primary key or unique index on non-nullable columns \
+        and can't be batched by ctid as it isn't a plain table",
+        table
+    ))
+}
+
+// Find the unique index with the fewest columns where every column is non-nullable.
+// Partial and expression indices are skipped as they don't cover every row.
+async fn get_unique_not_null_index_columns_for_table(
+    db: &mut dyn Conn,
+    table: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let columns = db
+        .query_with_params(
+            "
+            SELECT ARRAY_AGG(a.attname::TEXT ORDER BY k.position) AS columns
+            FROM pg_index i
+            CROSS JOIN LATERAL UNNEST(i.indkey::SMALLINT[]) WITH ORDINALITY AS k(attnum, position)
+            JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+            WHERE i.indrelid = $1::TEXT::regclass
+            AND i.indisunique
+            AND i.indisvalid
+            AND i.indimmediate
+            AND i.indpred IS NULL
+            AND i.indexprs IS NULL
+            GROUP BY i.indexrelid
+            HAVING BOOL_AND(a.attnotnull)
+            ORDER BY COUNT(*), i.indexrelid
+            LIMIT 1
+            ",
+            &[&format!("public.\"{}\"", table)],
+        )
+        .await
+        .context("failed to find unique index")?
+        .first()
+        .map(|row| row.get("columns"));
+
+    Ok(columns)
+}
+
+// Number of pages in a table and the estimated number of rows per page
+async fn get_page_stats(db: &mut dyn Conn, table: &str) -> anyhow::Result<(i64, Option<i64>)> {
+    let row = db
+        .query_with_params(
+            "
+            SELECT
+                pg_relation_size(oid) / current_setting('block_size')::BIGINT AS pages,
+                CASE WHEN relpages > 0 AND reltuples >= 0 THEN (reltuples / relpages)::BIGINT END AS rows_per_page
+            FROM pg_class
+            WHERE oid = $1::TEXT::regclass
+            ",
+            &[&format!("public.\"{}\"", table)],
+        ).await
+        .context("failed to get table size")?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("table {} not found", table))?;
+
+    Ok((row.get("pages"), row.get("rows_per_page")))
+}
+
+async fn get_first_column_for_table(db: &mut dyn Conn, table: &str) -> anyhow::Result<String> {
+    db.query_with_params(
+        "
+        SELECT attname::TEXT AS column_name
+        FROM pg_attribute
+        WHERE attrelid = $1::TEXT::regclass AND attnum > 0 AND NOT attisdropped
+        ORDER BY attnum
+        LIMIT 1
+        ",
+        &[&format!("public.\"{}\"", table)],
+    )
+    .await?
+    .first()
+    .map(|row| row.get("column_name"))
+    .ok_or_else(|| anyhow!("table {} has no columns", table))
+}
+
+async fn get_primary_key_columns_for_table(
+    db: &mut dyn Conn,
+    table: &str,
+) -> anyhow::Result<Vec<String>> {
+    // Query from https://wiki.postgresql.org/wiki/Retrieve_primary_key_columns,
+    // ordered like the index so that saved progress is always read back in
+    // the same order
+    let primary_key_columns: Vec<String> = db
+        .query(&format!(
+            "
+            SELECT a.attname AS column_name
+            FROM   pg_index i
+            JOIN   pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+            WHERE  i.indrelid = '{table}'::regclass
+            AND    i.indisprimary
+            ORDER BY ARRAY_POSITION(i.indkey::SMALLINT[], a.attnum);
+            ",
+            table = table
+        ))
+        .await?
+        .iter()
+        .map(|row| row.get("column_name"))
+        .collect();
+
+    Ok(primary_key_columns)
+}
@@ -1,9 +1,11 @@
-use super::{Action, MigrationContext};
+use super::{Action, MigrationContext, RemoveEnum};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -13,12 +15,13 @@ pub struct CreateEnum {
 }
 
 #[typetag::serde(name = "create_enum")]
+#[async_trait]
 impl Action for CreateEnum {
     fn describe(&self) -> String {
         format!("Creating enum \"{}\"", self.name)
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         db: &mut dyn Conn,
@@ -35,7 +38,8 @@ impl Action for CreateEnum {
                 AND typname = '{name}'
                 ",
                 name = self.name,
-            ))?
+            ))
+            .await?
             .is_empty();
         if enum_exists {
             return Ok(());
@@ -54,12 +58,13 @@ impl Action for CreateEnum {
             name = self.name,
             values = values_def.join(", "),
         ))
+        .await
         .context("failed to create enum")?;
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         _db: &'a mut dyn Conn,
@@ -69,15 +74,31 @@ impl Action for CreateEnum {
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.add_enum(&self.name, &self.values);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         db.run(&format!(
             r#"
             DROP TYPE IF EXISTS {name}
             "#,
             name = self.name,
         ))
+        .await
         .context("failed to drop enum")?;
 
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RemoveEnum {
+            enum_name: self.name.to_string(),
+        }))
+    }
 }
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 
-use super::{common::ForeignKey, Action, Column, MigrationContext};
+use super::{common::ForeignKey, Action, Column, MigrationContext, RemoveTable};
 use crate::{
     db::{Conn, Transaction},
-    migrations::common,
+    migrations::backfill,
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,6 +21,9 @@ pub struct CreateTable {
     pub foreign_keys: Vec<ForeignKey>,
 
     pub up: Option<Transformation>,
+
+    // Overrides the number of workers used to backfill existing rows
+    pub backfill_workers: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -35,12 +40,13 @@ impl CreateTable {
 }
 
 #[typetag::serde(name = "create_table")]
+#[async_trait]
 impl Action for CreateTable {
     fn describe(&self) -> String {
         format!("Creating table \"{}\"", self.name)
     }
 
-    fn run(
+    async fn run(
         &self,
         ctx: &MigrationContext,
         db: &mut dyn Conn,
@@ -87,7 +93,7 @@ impl Action for CreateTable {
                 .map(|col| format!("\"{}\"", col))
                 .collect();
 
-            let referenced_table = schema.get_table(db, &foreign_key.referenced_table)?;
+            let referenced_table = schema.get_table(db, &foreign_key.referenced_table).await?;
             let referenced_columns: Vec<String> = referenced_table
                 .real_column_names(&foreign_key.referenced_columns)
                 .map(|col| format!("\"{}\"", col))
@@ -105,14 +111,14 @@ impl Action for CreateTable {
 
         let query = &format!(
             r#"
-            CREATE TABLE "{name}" (
+            CREATE TABLE IF NOT EXISTS "{name}" (
                 {definition}
             )
             "#,
             name = self.name,
             definition = definition_rows.join(",\n"),
         );
-        db.run(query).context("failed to create table")?;
+        db.run(query).await.context("failed to create table")?;
 
         if let Some(Transformation {
             table: from_table,
@@ -120,7 +126,7 @@ impl Action for CreateTable {
             upsert_constraint,
         }) = &self.up
         {
-            let from_table = schema.get_table(db, &from_table)?;
+            let from_table = schema.get_table(db, from_table).await?;
 
             let declarations: Vec<String> = from_table
                 .columns
@@ -184,17 +190,20 @@ impl Action for CreateTable {
                 values = insert_values.join(", "),
                 updates = update_set.join(",\n"),
             );
-            db.run(&query).context("failed to create up trigger")?;
+            db.run(&query)
+                .await
+                .context("failed to create up trigger")?;
 
             // Backfill values in batches by touching the from table
-            common::batch_touch_rows(db, &from_table.real_name, None)
+            backfill::batch_touch_rows(ctx, db, &from_table.real_name, None, self.backfill_workers)
+                .await
                 .context("failed to batch update existing rows")?;
         }
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -206,14 +215,56 @@ impl Action for CreateTable {
             "#,
             trigger_name = self.trigger_name(ctx),
         );
-        db.run(&query).context("failed to drop up trigger")?;
+        db.run(&query).await.context("failed to drop up trigger")?;
 
         Ok(None)
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| column.name.to_string())
+            .collect();
+        schema.add_table(&self.name, &columns);
+
+        if self.primary_key.is_empty() {
+            schema.problem(format!("table {} has no primary key", self.name));
+        }
+        for column in &self.primary_key {
+            if !columns.contains(column) {
+                schema.problem(format!(
+                    "primary key column {} doesn't exist in table {}",
+                    column, self.name
+                ));
+            }
+        }
+
+        for foreign_key in &self.foreign_keys {
+            schema.require_columns(&self.name, &foreign_key.columns);
+            schema.require_columns(
+                &foreign_key.referenced_table,
+                &foreign_key.referenced_columns,
+            );
+            schema.add_foreign_key(
+                &self.name,
+                &format!("{}_{}_fkey", self.name, foreign_key.columns.join("_")),
+            );
+        }
+
+        if let Some(up) = &self.up {
+            schema.require_table(&up.table);
+        }
+    }
+
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         // Remove triggers and procedures
         let query = format!(
             r#"
@@ -221,7 +272,7 @@ impl Action for CreateTable {
             "#,
             trigger_name = self.trigger_name(ctx),
         );
-        db.run(&query).context("failed to drop up trigger")?;
+        db.run(&query).await.context("failed to drop up trigger")?;
 
         db.run(&format!(
             r#"
@@ -229,8 +280,15 @@ impl Action for CreateTable {
             "#,
             name = self.name,
         ))
+        .await
         .context("failed to drop table")?;
 
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RemoveTable {
+            table: self.name.to_string(),
+        }))
+    }
 }
@@ -3,6 +3,7 @@ use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,32 +19,34 @@ pub struct Custom {
 }
 
 #[typetag::serde(name = "custom")]
+#[async_trait]
 impl Action for Custom {
     fn describe(&self) -> String {
         "Running custom migration".to_string()
     }
 
-    fn run(
+    async fn run(
         &self,
-        _ctx: &MigrationContext,
+        ctx: &MigrationContext,
         db: &mut dyn Conn,
         _schema: &Schema,
     ) -> anyhow::Result<()> {
         if let Some(start_query) = &self.start {
-            println!("Running query: {}", start_query);
-            db.run(start_query)?;
+            ctx.output()
+                .message(format!("Running query: {}", start_query));
+            db.run(start_query).await?;
         }
 
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
         if let Some(complete_query) = &self.complete {
-            db.run(complete_query)?;
+            db.run(complete_query).await?;
         }
 
         Ok(None)
@@ -51,9 +54,14 @@ impl Action for Custom {
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         if let Some(abort_query) = &self.abort {
-            db.run(abort_query)?;
+            db.run(abort_query).await?;
         }
 
         Ok(())
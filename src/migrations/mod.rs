@@ -1,13 +1,23 @@
 use crate::{
-    db::{Conn, Transaction},
+    db::{Conn, Connector, Transaction},
+    hooks::Hooks,
+    offline::OfflineSchema,
+    output::Output,
     schema::Schema,
 };
+use anyhow::anyhow;
+use async_trait::async_trait;
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 // Re-export migration types
 mod common;
-pub use common::Column;
+pub use common::{Column, ForeignKey};
+
+mod backfill;
+pub(crate) use backfill::clear_progress as clear_backfill_progress;
+pub use backfill::{BackfillOptions, BackfillProgress, ProgressCallback};
 
 mod create_table;
 pub use create_table::CreateTable;
@@ -53,6 +63,8 @@ pub struct Migration {
     pub name: String,
     pub description: Option<String>,
     pub actions: Vec<Box<dyn Action>>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
 }
 
 impl Migration {
@@ -61,6 +73,7 @@ impl Migration {
             name: name.into(),
             description,
             actions: vec![],
+            hooks: Hooks::default(),
         }
     }
 
@@ -68,6 +81,55 @@ impl Migration {
         self.actions.push(Box::new(action));
         self
     }
+
+    // Build a migration which undoes this one by running the inverse of every
+    // action in reverse order. Fails if any of the actions can't be reversed.
+    pub fn inverse(&self, name: impl Into<String>) -> anyhow::Result<Migration> {
+        let actions = self
+            .actions
+            .iter()
+            .rev()
+            .map(|action| {
+                action.inverse().ok_or_else(|| {
+                    anyhow!(
+                        "migration {} can't be reverted as the action \"{}\" can't be reversed",
+                        self.name,
+                        action.describe()
+                    )
+                })
+            })
+            .collect::<anyhow::Result<Vec<Box<dyn Action>>>>()?;
+
+        Ok(Migration {
+            name: name.into(),
+            description: Some(format!("Revert {}", self.name)),
+            actions,
+            hooks: Hooks::default(),
+        })
+    }
+
+    // Hash of the migration's actions, used to detect when an applied migration
+    // has been changed. The actions are serialized with sorted keys and without
+    // any unset fields so that formatting changes and new optional settings
+    // don't change the checksum.
+    pub fn checksum(&self) -> String {
+        let mut actions = serde_json::to_value(&self.actions).unwrap();
+        remove_nulls(&mut actions);
+
+        let digest = Sha256::digest(actions.to_string().as_bytes());
+        format!("{:x}", digest)
+    }
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
 }
 
 impl PartialEq for Migration {
@@ -89,6 +151,9 @@ pub struct MigrationContext {
     migration_index: usize,
     action_index: usize,
     existing_schema_name: Option<String>,
+    backfill: BackfillOptions,
+    connector: Option<Connector>,
+    output: Output,
 }
 
 impl MigrationContext {
@@ -101,9 +166,32 @@ impl MigrationContext {
             migration_index,
             action_index,
             existing_schema_name,
+            backfill: BackfillOptions::default(),
+            connector: None,
+            output: Output::default(),
         }
     }
 
+    pub fn with_backfill_options(mut self, options: BackfillOptions) -> Self {
+        self.backfill = options;
+        self
+    }
+
+    // Allow actions to open additional connections, for example to run backfills in parallel
+    pub(crate) fn with_connector(mut self, connector: Connector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    pub(crate) fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub(crate) fn output(&self) -> &Output {
+        &self.output
+    }
+
     fn prefix(&self) -> String {
         format!(
             "__reshape_{:0>4}_{:0>4}",
@@ -121,15 +209,40 @@ impl MigrationContext {
 }
 
 #[typetag::serde(tag = "type")]
-pub trait Action: Debug {
+#[async_trait]
+pub trait Action: Debug + Send + Sync {
     fn describe(&self) -> String;
-    fn run(&self, ctx: &MigrationContext, db: &mut dyn Conn, schema: &Schema)
-        -> anyhow::Result<()>;
-    fn complete<'a>(
+    async fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()>;
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>>;
     fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema);
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()>;
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()>;
+
+    // An action which undoes this one after it has been completed, used when
+    // reverting migrations. Actions which can't be undone without losing data
+    // or information return None.
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        None
+    }
+
+    // Replay the action on an offline model of the schema, reporting problems
+    // like references to tables and columns which don't exist. Actions which
+    // don't implement this, like custom actions, are assumed to have changed
+    // the schema in unknown ways.
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.set_opaque();
+    }
 }
@@ -1,9 +1,11 @@
 use super::{common, Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -63,6 +65,7 @@ impl RemoveColumn {
 }
 
 #[typetag::serde(name = "remove_column")]
+#[async_trait]
 impl Action for RemoveColumn {
     fn describe(&self) -> String {
         format!(
@@ -71,13 +74,13 @@ impl Action for RemoveColumn {
         )
     }
 
-    fn run(
+    async fn run(
         &self,
         ctx: &MigrationContext,
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
+        let table = schema.get_table(db, &self.table).await?;
         let column = table
             .get_column(&self.column)
             .ok_or_else(|| anyhow!("no such column {} exists", self.column))?;
@@ -107,7 +110,7 @@ impl Action for RemoveColumn {
                             DECLARE
                                 {declarations}
                             BEGIN
-                                NEW.{column_name} = {down};
+                                NEW."{column_real_name}" = {down};
                             END;
                         END IF;
                         RETURN NEW;
@@ -117,13 +120,15 @@ impl Action for RemoveColumn {
                     DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
                     CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
                     "#,
-                    column_name = self.column,
+                    column_real_name = column.real_name,
                     trigger_name = self.trigger_name(ctx),
                     down = down,
-                    table = self.table,
+                    table = table.real_name,
                     declarations = declarations.join("\n"),
                 );
-                db.run(&query).context("failed to create down trigger")?;
+                db.run(&query)
+                    .await
+                    .context("failed to create down trigger")?;
             }
 
             if let Transformation::Update {
@@ -137,7 +142,7 @@ impl Action for RemoveColumn {
                     None => bail!("can't use update without previous migration"),
                 };
 
-                let from_table = schema.get_table(db, &from_table)?;
+                let from_table = schema.get_table(db, from_table).await?;
 
                 let maybe_null_check = if !column.nullable {
                     // Replace NOT NULL constraint with a constraint trigger that only triggers on the old schema.
@@ -150,7 +155,7 @@ impl Action for RemoveColumn {
                         RETURNS TRIGGER AS $$
                         BEGIN
                             IF NOT reshape.is_new_schema() THEN
-                                IF NEW.{column} IS NULL THEN
+                                IF NEW."{column_real_name}" IS NULL THEN
                                     RAISE EXCEPTION '{column} can not be null';
                                 END IF;
                             END IF;
@@ -166,22 +171,25 @@ impl Action for RemoveColumn {
                             FOR EACH ROW
                             EXECUTE PROCEDURE {trigger_name}();
                         "#,
-                        table = self.table,
+                        table = table.real_name,
                         trigger_name = self.not_null_constraint_trigger_name(ctx),
                         column = self.column,
+                        column_real_name = column.real_name,
                     );
                     db.run(&query)
+                        .await
                         .context("failed to create null constraint trigger")?;
 
                     db.run(&format!(
                         r#"
-                        ALTER TABLE {table}
-                        ALTER COLUMN {column}
+                        ALTER TABLE "{table}"
+                        ALTER COLUMN "{column}"
                         DROP NOT NULL
                         "#,
-                        table = self.table,
-                        column = self.column
+                        table = table.real_name,
+                        column = column.real_name,
                     ))
+                    .await
                     .context("failed to remove column not null constraint")?;
 
                     format!(
@@ -247,7 +255,9 @@ impl Action for RemoveColumn {
                     column_name = self.column,
                     trigger_name = self.trigger_name(ctx),
                 );
-                db.run(&query).context("failed to create down trigger")?;
+                db.run(&query)
+                    .await
+                    .context("failed to create down trigger")?;
 
                 let changed_into_variables = table
                     .columns
@@ -315,6 +325,7 @@ impl Action for RemoveColumn {
                     // declarations = declarations.join("\n"),
                 );
                 db.run(&query)
+                    .await
                     .context("failed to create reverse down trigger")?;
             }
         }
@@ -322,12 +333,13 @@ impl Action for RemoveColumn {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
         let indices = common::get_indices_for_column(db, &self.table, &self.column)
+            .await
             .context("failed getting column indices")?;
 
         for index in indices {
@@ -337,6 +349,7 @@ impl Action for RemoveColumn {
                 ",
                 name = index.name,
             ))
+            .await
             .context("failed to drop index")?;
         }
 
@@ -357,6 +370,7 @@ impl Action for RemoveColumn {
             null_trigger_name = self.not_null_constraint_trigger_name(ctx),
         );
         db.run(&query)
+            .await
             .context("failed to drop column and down trigger")?;
 
         Ok(None)
@@ -370,7 +384,26 @@ impl Action for RemoveColumn {
         });
     }
 
-    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        if let Some(Transformation::Update { table, .. }) = &self.down {
+            schema.require_table(table);
+        }
+
+        schema.remove_column(&self.table, &self.column);
+    }
+
+    async fn abort(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table).await?;
+        let column = table
+            .get_column(&self.column)
+            .map(|column| column.real_name.to_string())
+            .unwrap_or_else(|| self.column.to_string());
+
         // We might have temporaily removed the NOT NULL check and have to reinstate it
         let has_not_null_function = !db
             .query_with_params(
@@ -382,6 +415,7 @@ impl Action for RemoveColumn {
                 ",
                 &[&self.not_null_constraint_trigger_name(ctx)],
             )
+            .await
             .context("failed to get any NOT NULL function")?
             .is_empty();
 
@@ -393,11 +427,12 @@ impl Action for RemoveColumn {
                  ADD CONSTRAINT "{constraint_name}"
                  CHECK ("{column}" IS NOT NULL) NOT VALID
                  "#,
-                table = self.table,
+                table = table.real_name,
                 constraint_name = self.not_null_constraint_name(ctx),
-                column = self.column,
+                column = column,
             );
             db.run(&query)
+                .await
                 .context("failed to add NOT NULL constraint")?;
 
             let query = format!(
@@ -405,10 +440,11 @@ impl Action for RemoveColumn {
                 ALTER TABLE "{table}"
                 VALIDATE CONSTRAINT "{constraint_name}"
                 "#,
-                table = self.table,
+                table = table.real_name,
                 constraint_name = self.not_null_constraint_name(ctx),
             );
             db.run(&query)
+                .await
                 .context("failed to validate NOT NULL constraint")?;
 
             // This ALTER TABLE call will not require any exclusive locks as it can use the validated constraint from above
@@ -418,9 +454,10 @@ impl Action for RemoveColumn {
                 ALTER COLUMN {column}
                 SET NOT NULL
                 "#,
-                table = self.table,
-                column = self.column
+                table = table.real_name,
+                column = column
             ))
+            .await
             .context("failed to reinstate column NOT NULL")?;
 
             // Drop the temporary constraint
@@ -429,10 +466,11 @@ impl Action for RemoveColumn {
                 ALTER TABLE "{table}"
                 DROP CONSTRAINT "{constraint_name}"
                 "#,
-                table = self.table,
+                table = table.real_name,
                 constraint_name = self.not_null_constraint_name(ctx),
             );
             db.run(&query)
+                .await
                 .context("failed to drop NOT NULL constraint")?;
         }
 
@@ -447,6 +485,7 @@ impl Action for RemoveColumn {
             reverse_trigger_name = self.reverse_trigger_name(ctx),
             null_trigger_name = self.not_null_constraint_trigger_name(ctx),
         ))
+        .await
         .context("failed to drop down trigger")?;
 
         Ok(())
@@ -1,9 +1,11 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -13,12 +15,13 @@ pub struct RemoveEnum {
 }
 
 #[typetag::serde(name = "remove_enum")]
+#[async_trait]
 impl Action for RemoveEnum {
     fn describe(&self) -> String {
         format!("Removing enum \"{}\"", self.enum_name)
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         _db: &mut dyn Conn,
@@ -27,7 +30,7 @@ impl Action for RemoveEnum {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -38,6 +41,7 @@ impl Action for RemoveEnum {
             "#,
             name = self.enum_name,
         ))
+        .await
         .context("failed to drop enum")?;
 
         Ok(None)
@@ -45,7 +49,16 @@ impl Action for RemoveEnum {
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.remove_enum(&self.enum_name);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
 }
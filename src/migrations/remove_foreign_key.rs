@@ -1,18 +1,21 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::{anyhow, Context};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RemoveForeignKey {
-    table: String,
-    foreign_key: String,
+    pub table: String,
+    pub foreign_key: String,
 }
 
 #[typetag::serde(name = "remove_foreign_key")]
+#[async_trait]
 impl Action for RemoveForeignKey {
     fn describe(&self) -> String {
         format!(
@@ -21,7 +24,7 @@ impl Action for RemoveForeignKey {
         )
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         db: &mut dyn Conn,
@@ -38,7 +41,7 @@ impl Action for RemoveForeignKey {
         //   the risk that it would no longer be valid.
 
         // Ensure foreign key exists
-        let table = schema.get_table(db, &self.table)?;
+        let table = schema.get_table(db, &self.table).await?;
         let fk_exists = !db
             .query(&format!(
                 r#"
@@ -52,6 +55,7 @@ impl Action for RemoveForeignKey {
                 table_name = table.real_name,
                 foreign_key = self.foreign_key,
             ))
+            .await
             .context("failed to check for foreign key")?
             .is_empty();
 
@@ -66,7 +70,7 @@ impl Action for RemoveForeignKey {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -79,13 +83,23 @@ impl Action for RemoveForeignKey {
             table = self.table,
             foreign_key = self.foreign_key,
         ))
+        .await
         .context("failed to remove foreign key")?;
         Ok(None)
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.remove_foreign_key(&self.table, &self.foreign_key);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
 }
@@ -1,9 +1,11 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,12 +14,13 @@ pub struct RemoveIndex {
 }
 
 #[typetag::serde(name = "remove_index")]
+#[async_trait]
 impl Action for RemoveIndex {
     fn describe(&self) -> String {
         format!("Removing index \"{}\"", self.index)
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         _db: &mut dyn Conn,
@@ -27,7 +30,7 @@ impl Action for RemoveIndex {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -38,6 +41,7 @@ impl Action for RemoveIndex {
             "#,
             name = self.index
         ))
+        .await
         .context("failed to drop index")?;
 
         Ok(None)
@@ -45,7 +49,16 @@ impl Action for RemoveIndex {
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
 
-    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.remove_index(&self.index);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
 }
@@ -1,9 +1,11 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,12 +14,13 @@ pub struct RemoveTable {
 }
 
 #[typetag::serde(name = "remove_table")]
+#[async_trait]
 impl Action for RemoveTable {
     fn describe(&self) -> String {
         format!("Removing table \"{}\"", self.table)
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         _db: &mut dyn Conn,
@@ -26,7 +29,7 @@ impl Action for RemoveTable {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -38,7 +41,7 @@ impl Action for RemoveTable {
             "#,
             table = self.table,
         );
-        db.run(&query).context("failed to drop table")?;
+        db.run(&query).await.context("failed to drop table")?;
 
         Ok(None)
     }
@@ -49,7 +52,16 @@ impl Action for RemoveTable {
         });
     }
 
-    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.remove_table(&self.table);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
 }
@@ -1,9 +1,11 @@
 use super::{Action, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
+    offline::OfflineSchema,
     schema::Schema,
 };
 use anyhow::Context;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -13,12 +15,13 @@ pub struct RenameTable {
 }
 
 #[typetag::serde(name = "rename_table")]
+#[async_trait]
 impl Action for RenameTable {
     fn describe(&self) -> String {
         format!("Renaming table \"{}\" to \"{}\"", self.table, self.new_name)
     }
 
-    fn run(
+    async fn run(
         &self,
         _ctx: &MigrationContext,
         _db: &mut dyn Conn,
@@ -27,7 +30,7 @@ impl Action for RenameTable {
         Ok(())
     }
 
-    fn complete<'a>(
+    async fn complete<'a>(
         &self,
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
@@ -41,7 +44,7 @@ impl Action for RenameTable {
             table = self.table,
             new_name = self.new_name,
         );
-        db.run(&query).context("failed to rename table")?;
+        db.run(&query).await.context("failed to rename table")?;
 
         Ok(None)
     }
@@ -52,7 +55,23 @@ impl Action for RenameTable {
         });
     }
 
-    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+    fn check(&self, schema: &mut OfflineSchema) {
+        schema.rename_table(&self.table, &self.new_name);
+    }
+
+    async fn abort(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn inverse(&self) -> Option<Box<dyn Action>> {
+        Some(Box::new(RenameTable {
+            table: self.new_name.to_string(),
+            new_name: self.table.to_string(),
+        }))
+    }
 }
@@ -0,0 +1,348 @@
+use std::{collections::HashSet, fmt};
+
+use serde::Serialize;
+
+use crate::migrations::Migration;
+
+// OfflineSchema is an in-memory model of the schema which is built by replaying
+// migrations without a database. It's the offline counterpart to `Schema` and
+// only tracks names: tables with their columns and foreign keys, indices and
+// enums.
+//
+// Actions update the model through `Action::check`, which also reports any
+// problems found, like references to tables or columns which don't exist.
+//
+// Actions with unknown effects, like custom actions, make the model opaque.
+// From then on, missing tables and columns are assumed to have been created by
+// them and are added to the model instead of being reported.
+#[derive(Debug, Default)]
+pub struct OfflineSchema {
+    tables: Vec<OfflineTable>,
+    indices: Vec<OfflineIndex>,
+    enums: Vec<String>,
+    opaque: bool,
+
+    location: Option<(String, usize, String)>,
+    problems: Vec<CheckProblem>,
+}
+
+#[derive(Debug)]
+struct OfflineTable {
+    name: String,
+    columns: Vec<String>,
+    foreign_keys: Vec<String>,
+    // Set for tables which were assumed to exist, in which case the columns
+    // and foreign keys aren't known
+    assumed: bool,
+}
+
+#[derive(Debug)]
+struct OfflineIndex {
+    name: String,
+    table: String,
+    columns: Vec<String>,
+}
+
+// A problem found when checking migrations, together with where it was found
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckProblem {
+    pub migration: String,
+    pub action_index: Option<usize>,
+    pub action: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for CheckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "migration {}", self.migration)?;
+        if let (Some(index), Some(action)) = (self.action_index, &self.action) {
+            write!(f, ", action {} ({})", index + 1, action)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// Replay all migrations into an offline schema and return any problems found
+pub fn check_migrations(migrations: &[Migration]) -> Vec<CheckProblem> {
+    let mut schema = OfflineSchema::default();
+    let mut names: HashSet<&str> = HashSet::new();
+
+    for migration in migrations {
+        if !names.insert(&migration.name) {
+            schema.problems.push(CheckProblem {
+                migration: migration.name.to_string(),
+                action_index: None,
+                action: None,
+                message: "another migration has the same name".to_string(),
+            });
+        }
+
+        for (action_index, action) in migration.actions.iter().enumerate() {
+            schema.location = Some((migration.name.to_string(), action_index, action.describe()));
+            action.check(&mut schema);
+        }
+    }
+
+    schema.problems
+}
+
+impl OfflineSchema {
+    pub fn problem(&mut self, message: impl Into<String>) {
+        let (migration, action_index, action) = self
+            .location
+            .clone()
+            .map(|(migration, index, action)| (migration, Some(index), Some(action)))
+            .unwrap_or_default();
+
+        self.problems.push(CheckProblem {
+            migration,
+            action_index,
+            action,
+            message: message.into(),
+        });
+    }
+
+    pub fn set_opaque(&mut self) {
+        self.opaque = true;
+    }
+
+    // Report a problem if the table doesn't exist. Returns whether the table
+    // can be used for further checks.
+    pub fn require_table(&mut self, table: &str) -> bool {
+        if self.table(table).is_some() {
+            return true;
+        }
+
+        if self.opaque {
+            self.tables.push(OfflineTable {
+                name: table.to_string(),
+                columns: Vec::new(),
+                foreign_keys: Vec::new(),
+                assumed: true,
+            });
+            return true;
+        }
+
+        self.problem(format!("table {} doesn't exist", table));
+        false
+    }
+
+    pub fn require_columns(&mut self, table: &str, columns: &[String]) {
+        if !self.require_table(table) {
+            return;
+        }
+
+        let opaque = self.opaque;
+        let offline_table = self.table_mut(table).unwrap();
+        let assumed = offline_table.assumed || opaque;
+
+        let mut missing = Vec::new();
+        for column in columns {
+            if offline_table.columns.contains(column) {
+                continue;
+            }
+
+            if assumed {
+                offline_table.columns.push(column.to_string());
+            } else {
+                missing.push(column.to_string());
+            }
+        }
+
+        for column in missing {
+            self.problem(format!("column {}.{} doesn't exist", table, column));
+        }
+    }
+
+    pub fn add_table(&mut self, table: &str, columns: &[String]) {
+        if self.table(table).is_some() {
+            self.problem(format!("table {} already exists", table));
+            return;
+        }
+
+        let mut unique_columns: Vec<String> = Vec::new();
+        for column in columns {
+            if unique_columns.contains(column) {
+                self.problem(format!(
+                    "column {} is defined more than once in table {}",
+                    column, table
+                ));
+            } else {
+                unique_columns.push(column.to_string());
+            }
+        }
+
+        self.tables.push(OfflineTable {
+            name: table.to_string(),
+            columns: unique_columns,
+            foreign_keys: Vec::new(),
+            assumed: false,
+        });
+    }
+
+    pub fn remove_table(&mut self, table: &str) {
+        if self.require_table(table) {
+            self.tables.retain(|t| t.name != table);
+            self.indices.retain(|index| index.table != table);
+        }
+    }
+
+    pub fn rename_table(&mut self, table: &str, new_name: &str) {
+        if !self.require_table(table) {
+            return;
+        }
+        if self.table(new_name).is_some() {
+            self.problem(format!("table {} already exists", new_name));
+            return;
+        }
+
+        self.table_mut(table).unwrap().name = new_name.to_string();
+        for index in self.indices.iter_mut().filter(|index| index.table == table) {
+            index.table = new_name.to_string();
+        }
+    }
+
+    pub fn add_column(&mut self, table: &str, column: &str) {
+        if !self.require_table(table) {
+            return;
+        }
+
+        let offline_table = self.table_mut(table).unwrap();
+        if offline_table.columns.iter().any(|c| c == column) {
+            self.problem(format!("column {}.{} already exists", table, column));
+        } else {
+            offline_table.columns.push(column.to_string());
+        }
+    }
+
+    // Removing a column also removes any indices which use it, like Postgres does
+    pub fn remove_column(&mut self, table: &str, column: &str) {
+        self.require_columns(table, &[column.to_string()]);
+        if let Some(offline_table) = self.table_mut(table) {
+            offline_table.columns.retain(|c| c != column);
+        }
+        self.indices
+            .retain(|index| !(index.table == table && index.columns.iter().any(|c| c == column)));
+    }
+
+    pub fn rename_column(&mut self, table: &str, column: &str, new_name: &str) {
+        self.require_columns(table, &[column.to_string()]);
+
+        let offline_table = match self.table_mut(table) {
+            Some(offline_table) => offline_table,
+            None => return,
+        };
+        if offline_table.columns.iter().any(|c| c == new_name) {
+            self.problem(format!("column {}.{} already exists", table, new_name));
+            return;
+        }
+
+        for c in offline_table.columns.iter_mut().filter(|c| *c == column) {
+            *c = new_name.to_string();
+        }
+        for index in self.indices.iter_mut().filter(|index| index.table == table) {
+            for c in index.columns.iter_mut().filter(|c| *c == column) {
+                *c = new_name.to_string();
+            }
+        }
+    }
+
+    pub fn add_index(&mut self, table: &str, index: &str, columns: &[String]) {
+        self.require_columns(table, columns);
+
+        if self.indices.iter().any(|i| i.name == index) {
+            self.problem(format!("index {} already exists", index));
+            return;
+        }
+
+        self.indices.push(OfflineIndex {
+            name: index.to_string(),
+            table: table.to_string(),
+            columns: columns.to_vec(),
+        });
+    }
+
+    pub fn remove_index(&mut self, index: &str) {
+        if self.indices.iter().any(|i| i.name == index) {
+            self.indices.retain(|i| i.name != index);
+        } else if !self.opaque {
+            self.problem(format!("index {} doesn't exist", index));
+        }
+    }
+
+    pub fn add_foreign_key(&mut self, table: &str, foreign_key: &str) {
+        if !self.require_table(table) {
+            return;
+        }
+
+        let offline_table = self.table_mut(table).unwrap();
+        if offline_table
+            .foreign_keys
+            .iter()
+            .any(|fk| fk == foreign_key)
+        {
+            self.problem(format!(
+                "foreign key {} already exists on table {}",
+                foreign_key, table
+            ));
+        } else {
+            offline_table.foreign_keys.push(foreign_key.to_string());
+        }
+    }
+
+    pub fn remove_foreign_key(&mut self, table: &str, foreign_key: &str) {
+        if !self.require_table(table) {
+            return;
+        }
+
+        let opaque = self.opaque;
+        let offline_table = self.table_mut(table).unwrap();
+        if offline_table
+            .foreign_keys
+            .iter()
+            .any(|fk| fk == foreign_key)
+        {
+            offline_table.foreign_keys.retain(|fk| fk != foreign_key);
+        } else if !offline_table.assumed && !opaque {
+            self.problem(format!(
+                "foreign key {} doesn't exist on table {}",
+                foreign_key, table
+            ));
+        }
+    }
+
+    pub fn add_enum(&mut self, name: &str, values: &[String]) {
+        if self.enums.iter().any(|e| e == name) {
+            self.problem(format!("enum {} already exists", name));
+            return;
+        }
+
+        let mut unique_values: HashSet<&str> = HashSet::new();
+        for value in values {
+            if !unique_values.insert(value) {
+                self.problem(format!(
+                    "value {} is defined more than once in enum {}",
+                    value, name
+                ));
+            }
+        }
+
+        self.enums.push(name.to_string());
+    }
+
+    pub fn remove_enum(&mut self, name: &str) {
+        if self.enums.iter().any(|e| e == name) {
+            self.enums.retain(|e| e != name);
+        } else if !self.opaque {
+            self.problem(format!("enum {} doesn't exist", name));
+        }
+    }
+
+    fn table(&self, name: &str) -> Option<&OfflineTable> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    fn table_mut(&mut self, name: &str) -> Option<&mut OfflineTable> {
+        self.tables.iter_mut().find(|table| table.name == name)
+    }
+}
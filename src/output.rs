@@ -0,0 +1,276 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use colored::*;
+use serde::Serialize;
+
+use crate::{db::Blocker, drain::ActiveSession};
+
+// Everything Reshape reports while starting, completing and aborting
+// migrations is sent as an event. By default, events are printed as text, but
+// a callback can be set using `Reshape::on_event` to receive them instead, for
+// example to output them as JSON. When a callback is set, nothing is printed.
+//
+// Events are serialized with an `event` field holding the variant's name in
+// snake case, for example `{"event": "action_started", ...}`. Fields are only
+// ever added to events, never removed or changed, so the serialized form can
+// be relied upon.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // A migration started being applied, completed or aborted
+    MigrationStarted {
+        phase: Phase,
+        migration: String,
+    },
+    // A migration finished being applied, completed or aborted. `error` is
+    // set if it failed.
+    MigrationFinished {
+        phase: Phase,
+        migration: String,
+        duration_ms: u64,
+        error: Option<String>,
+    },
+    ActionStarted {
+        phase: Phase,
+        migration: String,
+        action: String,
+    },
+    ActionFinished {
+        phase: Phase,
+        migration: String,
+        action: String,
+        duration_ms: u64,
+        error: Option<String>,
+    },
+    // A hook is about to be run
+    Hook {
+        hook: String,
+        description: String,
+    },
+    // Sessions which are using schemas that are about to be removed, see
+    // `DrainOptions`. Only sent when the sessions change.
+    WaitingForSessions {
+        schemas: Vec<String>,
+        sessions: Vec<ActiveSession>,
+    },
+    SessionTerminated {
+        pid: i32,
+    },
+    // A query timed out waiting for a lock on `relation`, held by `blockers`.
+    // It's retried unless it has run out of attempts.
+    LockTimeout {
+        attempt: u32,
+        retrying: bool,
+        relation: Option<String>,
+        blockers: Vec<Blocker>,
+    },
+    // Rows touched so far when backfilling `table`, sent at most once a second
+    // by each worker and when it finishes. `worker_rows` are the rows touched
+    // by `worker` and `rows` those touched by all workers together.
+    BackfillProgress {
+        table: String,
+        worker: usize,
+        worker_rows: u64,
+        rows: u64,
+        estimated_total_rows: Option<u64>,
+    },
+    // The query which applications should run to use the schema of a migration
+    SchemaQuery {
+        migration: String,
+        query: String,
+    },
+    // Any other information, such as there being no migrations to apply
+    Message {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Migrate,
+    Complete,
+    Abort,
+}
+
+pub type EventCallback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+// Where events are sent, passed to everything which reports progress
+#[derive(Clone, Default)]
+pub(crate) struct Output {
+    on_event: Option<EventCallback>,
+    line: Arc<Mutex<Line>>,
+}
+
+// Actions are printed on a single line which is finished with "done" once
+// they are. Events in between, such as lock timeouts and backfill progress,
+// have to break that line and are printed on lines of their own.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Line {
+    #[default]
+    Finished,
+    Started,
+    Interrupted,
+}
+
+impl Output {
+    pub(crate) fn new(on_event: Option<EventCallback>) -> Self {
+        Output {
+            on_event,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        match &self.on_event {
+            Some(on_event) => on_event(&event),
+            None => self.print_event(&event),
+        }
+    }
+
+    pub(crate) fn message(&self, message: impl Into<String>) {
+        self.emit(Event::Message {
+            message: message.into(),
+        });
+    }
+
+    // Whether events are printed as text rather than sent to a callback
+    pub(crate) fn is_text(&self) -> bool {
+        self.on_event.is_none()
+    }
+
+    // Print text which is only there to make the text output easier to read,
+    // such as blank lines between migrations. Not sent as an event.
+    pub(crate) fn text(&self, text: &str) {
+        if self.is_text() {
+            println!("{}", text);
+        }
+    }
+}
+
+pub(crate) fn duration_ms(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+impl Output {
+    // Start a line which is finished by a later event
+    fn start_line(&self, text: &str) {
+        print!("{}", text);
+        io::stdout().flush().ok();
+        *self.line.lock().unwrap() = Line::Started;
+    }
+
+    fn finish_line(&self, text: &str) {
+        let mut line = self.line.lock().unwrap();
+        match *line {
+            Line::Interrupted => println!("    {}", text),
+            _ => println!("{}", text),
+        }
+        *line = Line::Finished;
+    }
+
+    // Make sure the next line isn't printed after a started one
+    fn interrupt_line(&self) {
+        let mut line = self.line.lock().unwrap();
+        if *line == Line::Started {
+            println!();
+            *line = Line::Interrupted;
+        }
+    }
+
+    fn print_event(&self, event: &Event) {
+        match event {
+            Event::MigrationStarted { phase, migration } => match phase {
+                Phase::Migrate => println!("Migrating '{}':", migration),
+                Phase::Complete => println!("Completing '{}':", migration),
+                Phase::Abort => self.start_line(&format!("Aborting '{}' ", migration)),
+            },
+            Event::MigrationFinished { phase, error, .. } => match (phase, error) {
+                (Phase::Abort, None) => self.finish_line(&"done".green().to_string()),
+                (Phase::Abort, Some(_)) => self.finish_line(&"failed".red().to_string()),
+                (_, None) => println!(),
+                (_, Some(_)) => {}
+            },
+            // Actions are only listed when applying and completing, as aborting a
+            // migration only prints a single line per migration
+            Event::ActionStarted { phase, action, .. } => {
+                if *phase != Phase::Abort {
+                    self.start_line(&format!("  + {} ", action));
+                }
+            }
+            Event::ActionFinished { phase, error, .. } => {
+                if *phase != Phase::Abort {
+                    match error {
+                        None => self.finish_line(&"done".green().to_string()),
+                        Some(_) => self.finish_line(&"failed".red().to_string()),
+                    }
+                }
+            }
+            Event::Hook { hook, description } => {
+                println!("Running {} hook: {}", hook, description)
+            }
+            Event::WaitingForSessions { schemas, sessions } => {
+                println!(
+                    "Waiting for {} sessions using {} to end:",
+                    sessions.len(),
+                    schemas.join(", ")
+                );
+                for session in sessions {
+                    println!("  - {}", session.describe());
+                }
+            }
+            Event::SessionTerminated { pid } => println!("Terminated session with pid {}", pid),
+            Event::LockTimeout {
+                attempt,
+                retrying,
+                relation,
+                blockers,
+            } => {
+                self.interrupt_line();
+                let relation = relation
+                    .as_ref()
+                    .map(|relation| format!(" on {}", relation))
+                    .unwrap_or_default();
+                let retrying = if *retrying { ", retrying" } else { "" };
+                if blockers.is_empty() {
+                    println!(
+                        "    Timed out waiting for a lock{} (attempt {}){}",
+                        relation, attempt, retrying
+                    );
+                } else {
+                    println!(
+                        "    Timed out waiting for a lock{} (attempt {}){}, held by:",
+                        relation, attempt, retrying
+                    );
+                    for blocker in blockers {
+                        println!("      - {}", blocker.describe());
+                    }
+                }
+            }
+            Event::BackfillProgress {
+                table,
+                rows,
+                estimated_total_rows,
+                ..
+            } => {
+                self.interrupt_line();
+                match estimated_total_rows {
+                    Some(total) => println!(
+                        "    Backfilled {} of about {} rows in \"{}\"",
+                        rows, total, table
+                    ),
+                    None => println!("    Backfilled {} rows in \"{}\"", rows, table),
+                }
+            }
+            Event::SchemaQuery { query, .. } => println!(
+                "  - Run '{}' from your application to use the latest schema",
+                query
+            ),
+            Event::Message { message } => println!("{}", message),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Context;
+
+use crate::{
+    db::Conn, helpers, migrations::Migration, output::Output, schema_name_for_migration, state,
+};
+
+// Schemas of completed migrations can be kept around after a newer migration
+// has been completed, so that applications which haven't been updated yet keep
+// working. Retained schemas are read-only, as the triggers which keep the old
+// and new columns in sync are removed on completion.
+//
+// Views reference columns by their identity rather than their name, so they
+// keep working when tables and columns are renamed. A retained schema can't
+// outlive the columns it uses though, so any schema using a column which is
+// removed by the completing migrations is dropped before the migrations are
+// completed.
+
+// Called before completing `migrations`. Drops the schemas of previous
+// migrations which shouldn't be kept and makes the rest read-only.
+pub(crate) async fn retain_schemas(
+    db: &mut dyn Conn,
+    migrations: &[Migration],
+    keep: usize,
+    output: &Output,
+) -> anyhow::Result<()> {
+    let target_schema = schema_name_for_migration(&migrations.last().unwrap().name);
+    let batch: HashSet<&str> = migrations
+        .iter()
+        .map(|migration| migration.name.as_str())
+        .collect();
+
+    // The reverted migration is removed once completed, so its schema has no
+    // migration to belong to and is always dropped. A revert is named after
+    // the migration before the reverted one, whose schema it replaces.
+    let reverting_migration = state::reverting_migration(db).await?;
+    if let Some(reverting_migration) = &reverting_migration {
+        drop_schema(db, reverting_migration).await?;
+    }
+
+    // Newest first
+    let mut previous: Vec<String> = Vec::new();
+    for migration in state::completed_migrations(db).await?.into_iter().rev() {
+        if batch.contains(migration.as_str())
+            || Some(&migration) == reverting_migration.as_ref()
+            || !schema_exists(db, &migration).await?
+        {
+            continue;
+        }
+        previous.push(migration);
+    }
+
+    let retained: Vec<String> = previous.iter().take(keep).cloned().collect();
+    for migration in previous.iter().skip(keep) {
+        if keep > 0 {
+            output.message(format!(
+                "Removing schema for migration '{}' as only {} previous schemas are kept",
+                migration, keep
+            ));
+        }
+        drop_schema(db, migration).await?;
+    }
+
+    if retained.is_empty() {
+        return Ok(());
+    }
+
+    let target_columns = columns_used_by_schema(db, &target_schema).await?;
+    for migration in retained {
+        let schema_name = schema_name_for_migration(&migration);
+        let removed_columns: Vec<String> = columns_used_by_schema(db, &schema_name)
+            .await?
+            .into_iter()
+            .filter(|(column, _)| !target_columns.contains_key(column))
+            .map(|(_, name)| name)
+            .collect();
+
+        if !removed_columns.is_empty() {
+            output.message(format!(
+                "Removing schema for migration '{}' as it uses columns which are removed: {}",
+                migration,
+                removed_columns.join(", ")
+            ));
+            drop_schema(db, &migration).await?;
+            continue;
+        }
+
+        helpers::make_schema_read_only(
+            db,
+            &schema_name,
+            "a newer migration has been completed",
+            "Write through the schema of the latest migration instead",
+        )
+        .await
+        .with_context(|| format!("failed to make schema {} read-only", schema_name))?;
+    }
+
+    Ok(())
+}
+
+async fn schema_exists(db: &mut dyn Conn, migration: &str) -> anyhow::Result<bool> {
+    let rows = db
+        .query_with_params(
+            "SELECT 1 FROM pg_namespace WHERE nspname = $1",
+            &[&schema_name_for_migration(migration)],
+        )
+        .await?;
+    Ok(!rows.is_empty())
+}
+
+async fn drop_schema(db: &mut dyn Conn, migration: &str) -> anyhow::Result<()> {
+    let schema_name = schema_name_for_migration(migration);
+    db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema_name))
+        .await
+        .with_context(|| format!("failed to drop schema {}", schema_name))
+}
+
+// The real columns used by the views in a schema, identified by the table's
+// OID and the column's number, together with a readable name for the column
+async fn columns_used_by_schema(
+    db: &mut dyn Conn,
+    schema_name: &str,
+) -> anyhow::Result<BTreeMap<(u32, i16), String>> {
+    let rows = db
+        .query_with_params(
+            "
+            SELECT DISTINCT
+                d.refobjid AS table_oid,
+                d.refobjsubid::SMALLINT AS column_number,
+                t.relname::TEXT || '.' || a.attname::TEXT AS name
+            FROM pg_depend d
+            JOIN pg_rewrite r ON r.oid = d.objid
+            JOIN pg_class v ON v.oid = r.ev_class
+            JOIN pg_namespace n ON n.oid = v.relnamespace
+            JOIN pg_class t ON t.oid = d.refobjid
+            JOIN pg_attribute a ON a.attrelid = d.refobjid AND a.attnum = d.refobjsubid
+            WHERE d.classid = 'pg_rewrite'::REGCLASS
+            AND d.refclassid = 'pg_class'::REGCLASS
+            AND d.refobjsubid > 0
+            AND d.refobjid <> v.oid
+            AND n.nspname = $1
+            ",
+            &[&schema_name],
+        )
+        .await
+        .with_context(|| format!("failed to get columns used by schema {}", schema_name))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                (row.get("table_oid"), row.get("column_number")),
+                row.get("name"),
+            )
+        })
+        .collect())
+}
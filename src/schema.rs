@@ -155,17 +155,22 @@ pub struct Column {
 }
 
 impl Schema {
-    pub fn get_tables(&self, db: &mut dyn Conn) -> anyhow::Result<Vec<Table>> {
-        db.query(
-            "
-            SELECT table_name
-            FROM information_schema.tables
-            WHERE table_schema = 'public'
-            ",
-        )?
-        .iter()
-        .map(|row| row.get::<'_, _, String>("table_name"))
-        .filter_map(|real_name| {
+    pub async fn get_tables(&self, db: &mut dyn Conn) -> anyhow::Result<Vec<Table>> {
+        let real_names: Vec<String> = db
+            .query(
+                "
+                SELECT table_name
+                FROM information_schema.tables
+                WHERE table_schema = 'public'
+                ",
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("table_name"))
+            .collect();
+
+        let mut tables = Vec::new();
+        for real_name in real_names {
             let table_changes = self
                 .table_changes
                 .iter()
@@ -174,16 +179,17 @@ impl Schema {
             // Skip table if it has been removed
             if let Some(changes) = table_changes {
                 if changes.removed {
-                    return None;
+                    continue;
                 }
             }
 
-            Some(self.get_table_by_real_name(db, &real_name))
-        })
-        .collect()
+            tables.push(self.get_table_by_real_name(db, &real_name).await?);
+        }
+
+        Ok(tables)
     }
 
-    pub fn get_table(&self, db: &mut dyn Conn, table_name: &str) -> anyhow::Result<Table> {
+    pub async fn get_table(&self, db: &mut dyn Conn, table_name: &str) -> anyhow::Result<Table> {
         let table_changes = self
             .table_changes
             .iter()
@@ -193,10 +199,10 @@ impl Schema {
             .map(|changes| changes.real_name.to_string())
             .unwrap_or_else(|| table_name.to_string());
 
-        self.get_table_by_real_name(db, &real_table_name)
+        self.get_table_by_real_name(db, &real_table_name).await
     }
 
-    fn get_table_by_real_name(
+    async fn get_table_by_real_name(
         &self,
         db: &mut dyn Conn,
         real_table_name: &str,
@@ -206,22 +212,30 @@ impl Schema {
             .iter()
             .find(|changes| changes.real_name == real_table_name);
 
+        // Types are formatted with `format_type` to keep modifiers like the length of a VARCHAR
         let real_columns: Vec<(String, String, bool, Option<String>)> = db
-            .query(&format!(
+            .query_with_params(
                 "
-                SELECT column_name, CASE WHEN data_type = 'USER-DEFINED' THEN udt_name ELSE data_type END, is_nullable, column_default
-                FROM information_schema.columns
-                WHERE table_name = '{table}' AND table_schema = 'public'
-                ORDER BY ordinal_position
+                SELECT
+                    a.attname::TEXT AS column_name,
+                    FORMAT_TYPE(a.atttypid, a.atttypmod) AS data_type,
+                    NOT a.attnotnull AS nullable,
+                    PG_GET_EXPR(d.adbin, d.adrelid) AS column_default
+                FROM pg_attribute a
+                JOIN pg_class c ON c.oid = a.attrelid
+                JOIN pg_namespace n ON n.oid = c.relnamespace
+                LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+                WHERE c.relname = $1 AND n.nspname = 'public' AND a.attnum > 0 AND NOT a.attisdropped
+                ORDER BY a.attnum
                 ",
-                table = real_table_name,
-            ))?
+                &[&real_table_name],
+            ).await?
             .iter()
             .map(|row| {
                 (
                     row.get("column_name"),
                     row.get("data_type"),
-                    row.get::<'_, _, String>("is_nullable") == "YES",
+                    row.get("nullable"),
                     row.get("column_default"),
                 )
             })
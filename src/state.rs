@@ -1,13 +1,18 @@
-use crate::{db::Conn, migrations::Migration};
+use crate::{
+    db::Conn,
+    hooks::Hooks,
+    migrations::{Action, Migration},
+};
 use anyhow::anyhow;
 
 use serde::{Deserialize, Serialize};
 use version::version;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(tag = "state")]
 pub enum State {
     #[serde(rename = "idle")]
+    #[default]
     Idle,
 
     #[serde(rename = "applying")]
@@ -16,30 +21,51 @@ pub enum State {
     #[serde(rename = "in_progress")]
     InProgress { migrations: Vec<Migration> },
 
+    // Completion continues from the action at `next_action_index` of the
+    // migration at `next_migration_index`
     #[serde(rename = "completing")]
     Completing {
         migrations: Vec<Migration>,
-        current_migration_index: usize,
-        current_action_index: usize,
+        next_migration_index: usize,
+        next_action_index: usize,
     },
 
+    // Migrations are aborted in reverse order. Only the first `migrations_left`
+    // migrations are left to abort, and only the first `actions_left` actions
+    // of the last of them. Both are usize::MAX before anything has been aborted.
     #[serde(rename = "aborting")]
     Aborting {
         migrations: Vec<Migration>,
-        last_migration_index: usize,
-        last_action_index: usize,
+        migrations_left: usize,
+        actions_left: usize,
     },
 }
 
 impl State {
-    pub fn load(db: &mut impl Conn) -> anyhow::Result<State> {
-        Self::ensure_schema_and_table(db)?;
+    pub async fn load(db: &mut dyn Conn) -> anyhow::Result<State> {
+        Self::ensure_schema_and_table(db).await?;
+        Self::load_saved(db).await
+    }
+
+    // Load the state without creating or updating Reshape's tables, for
+    // commands which don't take the lock and must not change anything.
+    // Before Reshape has created its tables, nothing has happened yet.
+    pub async fn load_read_only(db: &mut dyn Conn) -> anyhow::Result<State> {
+        if !is_set_up(db).await? {
+            return Ok(Self::Idle);
+        }
+        Self::load_saved(db).await
+    }
 
-        let results = db.query("SELECT value FROM reshape.data WHERE key = 'state'")?;
+    async fn load_saved(db: &mut dyn Conn) -> anyhow::Result<State> {
+        let results = db
+            .query("SELECT value FROM reshape.data WHERE key = 'state'")
+            .await?;
 
         let state = match results.first() {
             Some(row) => {
-                let json: serde_json::Value = row.get(0);
+                let mut json: serde_json::Value = row.get(0);
+                upgrade_legacy_position(&mut json);
                 serde_json::from_value(json)?
             }
             None => Default::default(),
@@ -47,19 +73,30 @@ impl State {
         Ok(state)
     }
 
-    pub fn save(&self, db: &mut impl Conn) -> anyhow::Result<()> {
-        Self::ensure_schema_and_table(db)?;
+    // The name of the state as it's stored in the database
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Applying { .. } => "applying",
+            State::InProgress { .. } => "in_progress",
+            State::Completing { .. } => "completing",
+            State::Aborting { .. } => "aborting",
+        }
+    }
+
+    pub async fn save(&self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        Self::ensure_schema_and_table(db).await?;
 
         let json = serde_json::to_value(self)?;
         db.query_with_params(
             "INSERT INTO reshape.data (key, value) VALUES ('state', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
             &[&json]
-        )?;
+        ).await?;
         Ok(())
     }
 
-    pub fn clear(&mut self, db: &mut impl Conn) -> anyhow::Result<()> {
-        db.run("DROP SCHEMA reshape CASCADE")?;
+    pub async fn clear(&mut self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        db.run("DROP SCHEMA reshape CASCADE").await?;
 
         *self = Self::default();
 
@@ -67,16 +104,21 @@ impl State {
     }
 
     // Complete will change the state from Completing to Idle
-    pub fn complete(&mut self, db: &mut impl Conn) -> anyhow::Result<()> {
+    pub async fn complete(&mut self, db: &mut dyn Conn) -> anyhow::Result<()> {
         let current_state = std::mem::replace(self, Self::Idle);
 
         match current_state {
             Self::Completing { migrations, .. } => {
                 // Add migrations and update state in a transaction to ensure atomicity
-                let mut transaction = db.transaction()?;
-                save_migrations(&mut transaction, &migrations)?;
-                self.save(&mut transaction)?;
-                transaction.commit()?;
+                let mut transaction = db.transaction().await?;
+                match reverting_migration(&mut transaction).await? {
+                    Some(reverted) => {
+                        remove_reverted_migration(&mut transaction, &reverted).await?
+                    }
+                    None => save_migrations(&mut transaction, &migrations).await?,
+                }
+                self.save(&mut transaction).await?;
+                transaction.commit().await?;
             }
             _ => {
                 // Move old state back
@@ -106,35 +148,36 @@ impl State {
     pub fn completing(
         &mut self,
         migrations: Vec<Migration>,
-        current_migration_index: usize,
-        current_action_index: usize,
+        next_migration_index: usize,
+        next_action_index: usize,
     ) {
         *self = Self::Completing {
             migrations,
-            current_migration_index,
-            current_action_index,
+            next_migration_index,
+            next_action_index,
         }
     }
 
     pub fn aborting(
         &mut self,
         migrations: Vec<Migration>,
-        last_migration_index: usize,
-        last_action_index: usize,
+        migrations_left: usize,
+        actions_left: usize,
     ) {
         *self = Self::Aborting {
             migrations,
-            last_migration_index,
-            last_action_index,
+            migrations_left,
+            actions_left,
         }
     }
 
-    fn ensure_schema_and_table(db: &mut impl Conn) -> anyhow::Result<()> {
-        db.run("CREATE SCHEMA IF NOT EXISTS reshape")?;
+    async fn ensure_schema_and_table(db: &mut dyn Conn) -> anyhow::Result<()> {
+        db.run("CREATE SCHEMA IF NOT EXISTS reshape").await?;
 
         // Create data table which will be a key-value table containing
         // the version and current state.
-        db.run("CREATE TABLE IF NOT EXISTS reshape.data (key TEXT PRIMARY KEY, value JSONB)")?;
+        db.run("CREATE TABLE IF NOT EXISTS reshape.data (key TEXT PRIMARY KEY, value JSONB)")
+            .await?;
 
         // Create migrations table which will store all completed migrations
         db.run(
@@ -147,7 +190,12 @@ impl State {
                 completed_at TIMESTAMP DEFAULT NOW()
             )
             ",
-        )?;
+        )
+        .await?;
+
+        // Checksums were added later so the column is added separately for existing installations
+        db.run("ALTER TABLE reshape.migrations ADD COLUMN IF NOT EXISTS checksum TEXT")
+            .await?;
 
         // Update the current version
         let encoded_version = serde_json::to_value(version!().to_string())?;
@@ -158,19 +206,106 @@ impl State {
             ON CONFLICT (key) DO UPDATE SET value = $1
             ",
             &[&encoded_version],
-        )?;
+        )
+        .await?;
 
         Ok(())
     }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::Idle
+// Whether the action at `action_index` of the migration at `migration_index`
+// is left to abort, given the position saved in the Aborting state
+pub fn is_left_to_abort(
+    migrations_left: usize,
+    actions_left: usize,
+    migration_index: usize,
+    action_index: usize,
+) -> bool {
+    migration_index < migrations_left
+        && (migration_index + 1 < migrations_left || action_index < actions_left)
+}
+
+// Older versions saved the position to continue completing or aborting from
+// differently, which made them skip actions when resuming. They only ever
+// saved the starting position, (0, 0), or the position right after an action,
+// so states they saved can be converted exactly, except for an abort which was
+// interrupted right after aborting its last action. That abort is started over,
+// which is safe as aborting an action again does nothing.
+fn upgrade_legacy_position(json: &mut serde_json::Value) {
+    let object = match json.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+    let state = object
+        .get("state")
+        .and_then(|state| state.as_str())
+        .unwrap_or_default()
+        .to_string();
+    match state.as_str() {
+        "completing" if object.contains_key("current_migration_index") => {
+            // Saved as (migration_index + 1, action_index + 1) after an action
+            let (next_migration_index, next_action_index) = match (
+                take_index(object, "current_migration_index"),
+                take_index(object, "current_action_index"),
+            ) {
+                (Some(migration_index), Some(action_index)) if migration_index > 0 => {
+                    (migration_index - 1, action_index)
+                }
+                _ => (0, 0),
+            };
+            object.insert(
+                "next_migration_index".to_string(),
+                next_migration_index.into(),
+            );
+            object.insert("next_action_index".to_string(), next_action_index.into());
+        }
+        "aborting" if object.contains_key("last_migration_index") => {
+            // Saved as (migration_index, action_index) after an action
+            let (migrations_left, actions_left) = match (
+                take_index(object, "last_migration_index"),
+                take_index(object, "last_action_index"),
+            ) {
+                (Some(0), Some(0)) | (None, _) | (_, None) => (usize::MAX, usize::MAX),
+                (Some(migration_index), Some(action_index)) => (migration_index + 1, action_index),
+            };
+            object.insert("migrations_left".to_string(), migrations_left.into());
+            object.insert("actions_left".to_string(), actions_left.into());
+        }
+        _ => {}
     }
 }
 
-pub fn current_migration(db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
+fn take_index(object: &mut serde_json::Map<String, serde_json::Value>, key: &str) -> Option<usize> {
+    object
+        .remove(key)
+        .and_then(|value| value.as_u64())
+        .map(|value| value as usize)
+}
+
+// Whether Reshape has created its tables, which happens the first time it
+// takes the lock
+pub async fn is_set_up(db: &mut dyn Conn) -> anyhow::Result<bool> {
+    let set_up = db
+        .query(
+            "
+            SELECT TO_REGCLASS('reshape.data') IS NOT NULL
+                AND TO_REGCLASS('reshape.migrations') IS NOT NULL
+            ",
+        )
+        .await?
+        .first()
+        .map(|row| row.get(0))
+        .unwrap_or(false);
+    Ok(set_up)
+}
+
+// The most recently completed migration, if any. Also used by read-only
+// commands, so there may not be any tables yet.
+pub async fn current_migration(db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
+    if !is_set_up(db).await? {
+        return Ok(None);
+    }
+
     let name: Option<String> = db
         .query(
             "
@@ -179,27 +314,111 @@ pub fn current_migration(db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
             ORDER BY index DESC
             LIMIT 1
             ",
-        )?
+        )
+        .await?
         .first()
         .map(|row| row.get("name"));
     Ok(name)
 }
 
-pub fn remaining_migrations(
-    db: &mut impl Conn,
+// Names of all completed migrations, oldest first
+pub async fn completed_migrations(db: &mut dyn Conn) -> anyhow::Result<Vec<String>> {
+    let names = db
+        .query("SELECT name FROM reshape.migrations ORDER BY index ASC")
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    Ok(names)
+}
+
+// The most recently completed migration together with the name of the
+// migration completed before it, if any
+pub async fn last_migration(
+    db: &mut dyn Conn,
+) -> anyhow::Result<Option<(Migration, Option<String>)>> {
+    let rows = db
+        .query(
+            "
+        SELECT name, description, actions
+        FROM reshape.migrations
+        ORDER BY index DESC
+        LIMIT 2
+        ",
+        )
+        .await?;
+
+    let last = match rows.first() {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let actions: Vec<Box<dyn Action>> = serde_json::from_value(last.get("actions"))?;
+    let migration = Migration {
+        name: last.get("name"),
+        description: last.get("description"),
+        actions,
+        hooks: Hooks::default(),
+    };
+    let previous = rows.get(1).map(|row| row.get("name"));
+
+    Ok(Some((migration, previous)))
+}
+
+// While a migration is being reverted, its name is stored so that completing
+// the revert removes it from the applied migrations
+pub async fn reverting_migration(db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
+    let name = db
+        .query("SELECT value FROM reshape.data WHERE key = 'reverting'")
+        .await?
+        .first()
+        .map(|row| serde_json::from_value(row.get("value")))
+        .transpose()?;
+    Ok(name)
+}
+
+pub async fn set_reverting_migration(db: &mut dyn Conn, name: &str) -> anyhow::Result<()> {
+    let json = serde_json::to_value(name)?;
+    db.query_with_params(
+        "INSERT INTO reshape.data (key, value) VALUES ('reverting', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+        &[&json],
+    ).await?;
+    Ok(())
+}
+
+pub async fn clear_reverting_migration(db: &mut dyn Conn) -> anyhow::Result<()> {
+    db.run("DELETE FROM reshape.data WHERE key = 'reverting'")
+        .await
+}
+
+async fn remove_reverted_migration(db: &mut dyn Conn, name: &str) -> anyhow::Result<()> {
+    db.query_with_params(
+        "
+        DELETE FROM reshape.migrations
+        WHERE index = (SELECT MAX(index) FROM reshape.migrations) AND name = $1
+        ",
+        &[&name],
+    )
+    .await?;
+    clear_reverting_migration(db).await
+}
+
+pub async fn remaining_migrations(
+    db: &mut dyn Conn,
     new_migrations: impl IntoIterator<Item = Migration>,
 ) -> anyhow::Result<Vec<Migration>> {
     let mut new_iter = new_migrations.into_iter();
+    let mut missing_checksums: Vec<(i32, String)> = Vec::new();
 
     // Ensure the new migrations match up with the existing ones
     let mut highest_index: Option<i32> = None;
     loop {
-        let migrations = get_migrations(db, highest_index)?;
+        let migrations = get_migrations(db, highest_index).await?;
         if migrations.is_empty() {
             break;
         }
 
-        for (index, existing) in migrations {
+        for (index, existing, existing_checksum) in migrations {
             highest_index = Some(index);
 
             let new = match new_iter.next() {
@@ -219,53 +438,131 @@ pub fn remaining_migrations(
                     new.name
                 ));
             }
+
+            // Migrations applied before checksums were introduced have no checksum
+            // and are baselined using the local migration
+            let checksum = new.checksum();
+            match existing_checksum {
+                Some(existing_checksum) if existing_checksum != checksum => {
+                    return Err(anyhow!(
+                        "migration {} has been changed since it was applied. If this is intentional, run `reshape migration start --rebaseline` to accept the local version",
+                        existing
+                    ));
+                }
+                Some(_) => {}
+                None => missing_checksums.push((index, checksum)),
+            }
         }
     }
 
+    update_checksums(db, &missing_checksums).await?;
+
     // Return the remaining migrations
     let items: Vec<Migration> = new_iter.collect();
     Ok(items)
 }
 
-fn get_migrations(
-    db: &mut impl Conn,
+// Replace the stored checksums of applied migrations with the checksums of
+// the local migrations. This accepts any changes made to migrations which
+// have already been applied.
+pub async fn rebaseline_migrations(
+    db: &mut dyn Conn,
+    new_migrations: impl IntoIterator<Item = Migration>,
+) -> anyhow::Result<()> {
+    let mut new_iter = new_migrations.into_iter();
+    let mut checksums: Vec<(i32, String)> = Vec::new();
+
+    let mut highest_index: Option<i32> = None;
+    loop {
+        let migrations = get_migrations(db, highest_index).await?;
+        if migrations.is_empty() {
+            break;
+        }
+
+        for (index, existing, _) in migrations {
+            highest_index = Some(index);
+
+            match new_iter.next() {
+                Some(new) if new.name == existing => checksums.push((index, new.checksum())),
+                Some(new) => {
+                    return Err(anyhow!(
+                        "existing migration {} does not match new migration {}",
+                        existing,
+                        new.name
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "existing migration {} doesn't exist in local migrations",
+                        existing
+                    ))
+                }
+            }
+        }
+    }
+
+    update_checksums(db, &checksums).await
+}
+
+async fn update_checksums(db: &mut dyn Conn, checksums: &[(i32, String)]) -> anyhow::Result<()> {
+    for (index, checksum) in checksums {
+        db.query_with_params(
+            "UPDATE reshape.migrations SET checksum = $1 WHERE index = $2",
+            &[checksum, index],
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn get_migrations(
+    db: &mut dyn Conn,
     index_larger_than: Option<i32>,
-) -> anyhow::Result<Vec<(i32, String)>> {
+) -> anyhow::Result<Vec<(i32, String, Option<String>)>> {
     let rows = if let Some(index_larger_than) = index_larger_than {
         db.query_with_params(
             "
-            SELECT index, name
+            SELECT index, name, checksum
             FROM reshape.migrations
             WHERE index > $1
             ORDER BY index ASC
             LIMIT 100
             ",
             &[&index_larger_than],
-        )?
+        )
+        .await?
     } else {
         db.query(
             "
-            SELECT index, name
+            SELECT index, name, checksum
             FROM reshape.migrations
+            ORDER BY index ASC
             LIMIT 100
             ",
-        )?
+        )
+        .await?
     };
 
     let migrations = rows
         .iter()
-        .map(|row| (row.get("index"), row.get("name")))
+        .map(|row| (row.get("index"), row.get("name"), row.get("checksum")))
         .collect();
     Ok(migrations)
 }
 
-fn save_migrations(db: &mut impl Conn, migrations: &[Migration]) -> anyhow::Result<()> {
+async fn save_migrations(db: &mut dyn Conn, migrations: &[Migration]) -> anyhow::Result<()> {
     for migration in migrations {
         let encoded_actions = serde_json::to_value(&migration.actions)?;
         db.query_with_params(
-            "INSERT INTO reshape.migrations(name, description, actions) VALUES ($1, $2, $3)",
-            &[&migration.name, &migration.description, &encoded_actions],
-        )?;
+            "INSERT INTO reshape.migrations(name, description, actions, checksum) VALUES ($1, $2, $3, $4)",
+            &[
+                &migration.name,
+                &migration.description,
+                &encoded_actions,
+                &migration.checksum(),
+            ],
+        ).await?;
     }
 
     Ok(())
@@ -0,0 +1,253 @@
+use std::fmt;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{db::Conn, schema_name_for_migration, state, State};
+
+// A snapshot of what Reshape is currently doing, as returned by `Reshape::status`
+#[derive(Serialize, Debug, Clone)]
+pub struct Status {
+    pub state: String,
+    pub pending_migrations: Vec<String>,
+    // Where completion or abortion will continue from if it was interrupted
+    pub cursor: Option<StatusCursor>,
+    pub current_schema: Option<String>,
+    pub target_schema: Option<String>,
+    pub helpers_installed: bool,
+    // Whether another instance of Reshape currently holds the lock
+    pub locked: bool,
+    // Temporary objects created by in-progress migrations. These are expected
+    // while a migration is in progress but are left over from an earlier
+    // failure if there is no migration in progress.
+    pub temporary_objects: Vec<TemporaryObject>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StatusCursor {
+    pub migration_index: usize,
+    pub action_index: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TemporaryObject {
+    pub kind: String,
+    pub name: String,
+}
+
+pub(crate) async fn status(db: &mut dyn Conn, lock_key: i64) -> anyhow::Result<Status> {
+    let state = State::load_read_only(db).await?;
+
+    let (pending_migrations, cursor) = match &state {
+        State::Idle => (&[][..], None),
+        State::Applying { migrations } | State::InProgress { migrations } => {
+            (&migrations[..], None)
+        }
+        State::Completing {
+            migrations,
+            next_migration_index,
+            next_action_index,
+        } => (
+            &migrations[..],
+            Some(StatusCursor {
+                migration_index: *next_migration_index,
+                action_index: *next_action_index,
+            }),
+        ),
+        State::Aborting {
+            migrations,
+            migrations_left,
+            actions_left,
+        } => {
+            // Actions are aborted in reverse, so abortion continues from the
+            // last action which is left to abort
+            let cursor = migrations
+                .iter()
+                .enumerate()
+                .flat_map(|(migration_index, migration)| {
+                    (0..migration.actions.len())
+                        .map(move |action_index| (migration_index, action_index))
+                })
+                .rev()
+                .find(|(migration_index, action_index)| {
+                    state::is_left_to_abort(
+                        *migrations_left,
+                        *actions_left,
+                        *migration_index,
+                        *action_index,
+                    )
+                })
+                .map(|(migration_index, action_index)| StatusCursor {
+                    migration_index,
+                    action_index,
+                });
+            (&migrations[..], cursor)
+        }
+    };
+    let pending_migrations: Vec<String> = pending_migrations
+        .iter()
+        .map(|migration| migration.name.to_string())
+        .collect();
+
+    let current_schema = state::current_migration(db)
+        .await?
+        .map(|migration| schema_name_for_migration(&migration));
+    let target_schema = pending_migrations
+        .last()
+        .map(|migration| schema_name_for_migration(migration));
+
+    let helpers_installed = !db
+        .query(
+            "
+            SELECT p.proname
+            FROM pg_proc p
+            JOIN pg_namespace n ON n.oid = p.pronamespace
+            WHERE n.nspname = 'reshape' AND p.proname = 'is_new_schema'
+            ",
+        )
+        .await
+        .context("failed to check for helper functions")?
+        .is_empty();
+
+    // Advisory locks taken with a single 64-bit key are split into two 32-bit halves in pg_locks
+    let locked = !db
+        .query_with_params(
+            "
+            SELECT pid
+            FROM pg_locks
+            WHERE locktype = 'advisory'
+            AND classid = $1::BIGINT::OID
+            AND objid = $2::BIGINT::OID
+            AND objsubid = 1
+            AND pid != pg_backend_pid()
+            ",
+            &[&(lock_key >> 32), &(lock_key & 0xffff_ffff)],
+        )
+        .await
+        .context("failed to check for lock")?
+        .is_empty();
+
+    let temporary_objects = get_temporary_objects(db).await?;
+
+    Ok(Status {
+        state: state.name().to_string(),
+        pending_migrations,
+        cursor,
+        current_schema,
+        target_schema,
+        helpers_installed,
+        locked,
+        temporary_objects,
+    })
+}
+
+async fn get_temporary_objects(db: &mut dyn Conn) -> anyhow::Result<Vec<TemporaryObject>> {
+    let objects = db
+        .query(
+            r#"
+            SELECT 'column' AS kind, table_name || '.' || column_name AS name
+            FROM information_schema.columns
+            WHERE table_schema = 'public' AND column_name LIKE '\_\_reshape\_%'
+
+            UNION ALL
+
+            SELECT 'trigger', c.relname || '.' || t.tgname
+            FROM pg_trigger t
+            JOIN pg_class c ON c.oid = t.tgrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = 'public' AND NOT t.tgisinternal AND t.tgname LIKE '\_\_reshape\_%'
+
+            UNION ALL
+
+            SELECT 'function', p.proname
+            FROM pg_proc p
+            JOIN pg_namespace n ON n.oid = p.pronamespace
+            WHERE n.nspname = 'public' AND p.proname LIKE '\_\_reshape\_%'
+
+            UNION ALL
+
+            SELECT 'constraint', c.relname || '.' || con.conname
+            FROM pg_constraint con
+            JOIN pg_class c ON c.oid = con.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = 'public' AND con.conname LIKE '\_\_reshape\_%'
+
+            UNION ALL
+
+            SELECT 'index', c.relname
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = 'public' AND c.relkind = 'i' AND c.relname LIKE '\_\_reshape\_%'
+
+            ORDER BY kind, name
+            "#,
+        )
+        .await
+        .context("failed to find temporary objects")?
+        .iter()
+        .map(|row| TemporaryObject {
+            kind: row.get("kind"),
+            name: row.get("name"),
+        })
+        .collect();
+
+    Ok(objects)
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "State: {}", self.state)?;
+
+        if self.pending_migrations.is_empty() {
+            writeln!(f, "Pending migrations: none")?;
+        } else {
+            writeln!(f, "Pending migrations:")?;
+            for migration in &self.pending_migrations {
+                writeln!(f, "  - {}", migration)?;
+            }
+        }
+
+        if let Some(cursor) = &self.cursor {
+            writeln!(
+                f,
+                "Continuing from: migration {}, action {}",
+                cursor.migration_index, cursor.action_index
+            )?;
+        }
+
+        writeln!(
+            f,
+            "Current schema: {}",
+            self.current_schema.as_deref().unwrap_or("none")
+        )?;
+        if let Some(target_schema) = &self.target_schema {
+            writeln!(f, "Target schema: {}", target_schema)?;
+        }
+
+        writeln!(
+            f,
+            "Helper functions: {}",
+            if self.helpers_installed {
+                "installed"
+            } else {
+                "not installed"
+            }
+        )?;
+        writeln!(
+            f,
+            "Locked by another instance: {}",
+            if self.locked { "yes" } else { "no" }
+        )?;
+
+        if self.temporary_objects.is_empty() {
+            write!(f, "Temporary objects: none")?;
+        } else {
+            write!(f, "Temporary objects:")?;
+            for object in &self.temporary_objects {
+                write!(f, "\n  - {} {}", object.kind, object.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use percent_encoding::percent_decode_str;
+use postgres::config::SslMode;
+
+// TLS settings for the database connection. These follow the libpq
+// parameters of the same names and can either be passed in the connection
+// string (`sslmode`, `sslrootcert`, `sslcert` and `sslkey`) or set explicitly.
+// Explicitly set values take precedence over the connection string.
+//
+// Connecting with TLS requires Reshape to be built with the `tls` feature.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub mode: Option<TlsMode>,
+    pub root_cert: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsMode {
+    // Never use TLS
+    Disable,
+    // Use TLS if the server supports it, without verifying the certificate
+    Prefer,
+    // Always use TLS. The certificate is only verified if a root certificate is set.
+    Require,
+    // Always use TLS and verify that the certificate is signed by a trusted CA
+    VerifyCa,
+    // Like `VerifyCa` but also verify that the hostname matches the certificate
+    VerifyFull,
+}
+
+impl std::str::FromStr for TlsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(TlsMode::Disable),
+            "prefer" => Ok(TlsMode::Prefer),
+            "require" => Ok(TlsMode::Require),
+            "verify-ca" => Ok(TlsMode::VerifyCa),
+            "verify-full" => Ok(TlsMode::VerifyFull),
+            mode => Err(anyhow!("invalid sslmode '{}'", mode)),
+        }
+    }
+}
+
+impl TlsOptions {
+    // Fill in any settings which haven't been set explicitly from `other`
+    pub fn or(self, other: TlsOptions) -> TlsOptions {
+        TlsOptions {
+            mode: self.mode.or(other.mode),
+            root_cert: self.root_cert.or(other.root_cert),
+            cert: self.cert.or(other.cert),
+            key: self.key.or(other.key),
+        }
+    }
+
+    fn mode(&self) -> TlsMode {
+        self.mode.unwrap_or(TlsMode::Prefer)
+    }
+
+    fn ssl_mode(&self) -> SslMode {
+        match self.mode() {
+            TlsMode::Disable => SslMode::Disable,
+            TlsMode::Prefer => SslMode::Prefer,
+            TlsMode::Require | TlsMode::VerifyCa | TlsMode::VerifyFull => SslMode::Require,
+        }
+    }
+}
+
+// Split a connection string into the part understood by the Postgres client
+// and the TLS settings, which the client doesn't support parsing itself.
+// Both URLs and key-value connection strings are supported.
+pub(crate) fn parse_connection_string(
+    connection_string: &str,
+) -> anyhow::Result<(String, TlsOptions)> {
+    let mut tls = TlsOptions::default();
+
+    let remaining = if connection_string.starts_with("postgres://")
+        || connection_string.starts_with("postgresql://")
+    {
+        let (base, query) = match connection_string.split_once('?') {
+            Some((base, query)) => (base, query),
+            None => return Ok((connection_string.to_string(), tls)),
+        };
+
+        let mut remaining_params = Vec::new();
+        for param in query.split('&') {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode_str(value)
+                .decode_utf8()
+                .with_context(|| format!("invalid value for {}", key))?;
+
+            if !set_tls_param(&mut tls, key, &value)? {
+                remaining_params.push(param);
+            }
+        }
+
+        if remaining_params.is_empty() {
+            base.to_string()
+        } else {
+            format!("{}?{}", base, remaining_params.join("&"))
+        }
+    } else {
+        let mut remaining_params = Vec::new();
+        for (param, key, value) in key_value_params(connection_string)? {
+            if !set_tls_param(&mut tls, key, &value)? {
+                remaining_params.push(param);
+            }
+        }
+
+        remaining_params.join(" ")
+    };
+
+    Ok((remaining, tls))
+}
+
+// Returns whether the parameter was a TLS parameter
+fn set_tls_param(tls: &mut TlsOptions, key: &str, value: &str) -> anyhow::Result<bool> {
+    match key {
+        "sslmode" => tls.mode = Some(value.parse()?),
+        "sslrootcert" => tls.root_cert = Some(value.into()),
+        "sslcert" => tls.cert = Some(value.into()),
+        "sslkey" => tls.key = Some(value.into()),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// Split a key-value connection string, e.g. `host=localhost sslmode='require'`,
+// into its parameters. Each parameter is returned as its raw text together
+// with its key and unquoted value.
+fn key_value_params(s: &str) -> anyhow::Result<Vec<(&str, &str, String)>> {
+    let mut params = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    loop {
+        // Skip whitespace between parameters
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        let start = match chars.peek() {
+            Some((index, _)) => *index,
+            None => break,
+        };
+
+        let mut key_end = start;
+        while let Some((index, c)) = chars.next_if(|(_, c)| *c != '=' && !c.is_whitespace()) {
+            key_end = index + c.len_utf8();
+        }
+        let key = &s[start..key_end];
+
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        if chars.next_if(|(_, c)| *c == '=').is_none() {
+            return Err(anyhow!("expected '=' after '{}' in connection string", key));
+        }
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        let end;
+        if chars.next_if(|(_, c)| *c == '\'').is_some() {
+            loop {
+                match chars.next() {
+                    Some((index, '\'')) => {
+                        end = index + 1;
+                        break;
+                    }
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => value.push(c),
+                        None => return Err(anyhow!("unterminated quoted connection parameter")),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err(anyhow!("unterminated quoted connection parameter")),
+                }
+            }
+        } else {
+            while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                if c == '\\' {
+                    if let Some((_, c)) = chars.next() {
+                        value.push(c);
+                    }
+                } else {
+                    value.push(c);
+                }
+            }
+            end = chars.peek().map(|(index, _)| *index).unwrap_or(s.len());
+        }
+
+        params.push((&s[start..end], key, value));
+    }
+
+    Ok(params)
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn connect(
+    config: &postgres::Config,
+    tls: &TlsOptions,
+) -> anyhow::Result<postgres::Client> {
+    ensure_tls_not_required(tls)?;
+
+    let mut config = config.clone();
+    config.ssl_mode(tls.ssl_mode());
+    Ok(config.connect(postgres::NoTls)?)
+}
+
+#[cfg(feature = "tls")]
+pub(crate) fn connect(
+    config: &postgres::Config,
+    tls: &TlsOptions,
+) -> anyhow::Result<postgres::Client> {
+    let mut config = config.clone();
+    config.ssl_mode(tls.ssl_mode());
+
+    match make_tls_connector(tls)? {
+        Some(connector) => Ok(config.connect(connector)?),
+        None => Ok(config.connect(postgres::NoTls)?),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) async fn connect_async(
+    config: &tokio_postgres::Config,
+    tls: &TlsOptions,
+) -> anyhow::Result<tokio_postgres::Client> {
+    ensure_tls_not_required(tls)?;
+
+    let mut config = config.clone();
+    config.ssl_mode(tls.ssl_mode());
+    let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+    spawn_connection(connection);
+    Ok(client)
+}
+
+#[cfg(feature = "tls")]
+pub(crate) async fn connect_async(
+    config: &tokio_postgres::Config,
+    tls: &TlsOptions,
+) -> anyhow::Result<tokio_postgres::Client> {
+    let mut config = config.clone();
+    config.ssl_mode(tls.ssl_mode());
+
+    let client = match make_tls_connector(tls)? {
+        Some(connector) => {
+            let (client, connection) = config.connect(connector).await?;
+            spawn_connection(connection);
+            client
+        }
+        None => {
+            let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+            spawn_connection(connection);
+            client
+        }
+    };
+
+    Ok(client)
+}
+
+// The connection object performs the actual communication with the database
+// and must be polled for the client to make progress
+fn spawn_connection<F, E>(connection: F)
+where
+    F: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Display,
+{
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            eprintln!("connection error: {}", err);
+        }
+    });
+}
+
+#[cfg(not(feature = "tls"))]
+fn ensure_tls_not_required(tls: &TlsOptions) -> anyhow::Result<()> {
+    match tls.mode() {
+        TlsMode::Disable | TlsMode::Prefer => Ok(()),
+        _ => Err(anyhow!(
+            "connecting with TLS requires Reshape to be built with the `tls` feature"
+        )),
+    }
+}
+
+// Build a TLS connector for the given options, or None if TLS is disabled.
+//
+// Certificate verification follows libpq: `prefer` and `require` don't verify
+// the server certificate (unless a root certificate is passed to `require`),
+// `verify-ca` checks the certificate chain and `verify-full` also checks the hostname.
+#[cfg(feature = "tls")]
+fn make_tls_connector(
+    tls: &TlsOptions,
+) -> anyhow::Result<Option<postgres_native_tls::MakeTlsConnector>> {
+    use native_tls::{Certificate, Identity, TlsConnector};
+
+    let mode = match tls.mode() {
+        TlsMode::Disable => return Ok(None),
+        TlsMode::Require if tls.root_cert.is_some() => TlsMode::VerifyCa,
+        mode => mode,
+    };
+
+    let mut builder = TlsConnector::builder();
+
+    match mode {
+        TlsMode::Prefer | TlsMode::Require => {
+            builder.danger_accept_invalid_certs(true);
+        }
+        TlsMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        _ => {}
+    }
+
+    if let Some(path) = &tls.root_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("failed to read sslrootcert {}", path.display()))?;
+        let certificate = Certificate::from_pem(&pem)
+            .with_context(|| format!("failed to parse sslrootcert {}", path.display()))?;
+        builder.add_root_certificate(certificate);
+    }
+
+    match (&tls.cert, &tls.key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = std::fs::read(cert_path)
+                .with_context(|| format!("failed to read sslcert {}", cert_path.display()))?;
+            let key = std::fs::read(key_path)
+                .with_context(|| format!("failed to read sslkey {}", key_path.display()))?;
+            let identity = Identity::from_pkcs8(&cert, &key)
+                .context("failed to load client certificate, sslkey must be a PKCS#8 PEM key")?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err(anyhow!("sslcert and sslkey must be set together")),
+    }
+
+    let connector = builder.build().context("failed to set up TLS")?;
+    Ok(Some(postgres_native_tls::MakeTlsConnector::new(connector)))
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{assert_columns, assert_rows, Test};
 
 #[test]
 fn add_column() {
@@ -170,7 +169,7 @@ fn add_column_nullable() {
             .unwrap();
         let name: Option<String> = new_db
             .query_one("SELECT name from users WHERE id = 3", &[])
-            .map(|row| (row.get("name")))
+            .map(|row| row.get("name"))
             .unwrap();
         assert_eq!(None, name);
 
@@ -359,3 +358,76 @@ fn add_column_with_complex_up() {
 
     test.run();
 }
+
+#[test]
+fn add_column_after_rename_table() {
+    let mut test = Test::new("Add column after renaming table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    // The table keeps its old name until the migration is completed, so the
+    // column has to be added to the table using its real name
+    test.second_migration(
+        r#"
+        name = "rename_users_and_add_name"
+
+        [[actions]]
+        type = "rename_table"
+        table = "users"
+        new_name = "customers"
+
+        [[actions]]
+        type = "add_column"
+        table = "customers"
+        up = "'TEST_UP_VALUE'"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Existing rows are backfilled and new rows get the up value
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+        assert_rows(
+            new_db,
+            "SELECT id, name FROM customers ORDER BY id",
+            &[
+                &[Some("1"), Some("TEST_UP_VALUE")],
+                &[Some("2"), Some("TEST_UP_VALUE")],
+            ],
+        );
+    });
+
+    test.after_completion(|db| {
+        assert_columns(db, "customers", &["id", "name"]);
+    });
+
+    test.after_abort(|db| {
+        assert_columns(db, "users", &["id"]);
+    });
+
+    test.run();
+}
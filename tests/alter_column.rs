@@ -1,5 +1,7 @@
-mod common;
-use common::Test;
+use reshape::{
+    testing::{assert_columns, assert_rows, Test},
+    Phase,
+};
 
 #[test]
 fn alter_column_data() {
@@ -402,7 +404,7 @@ fn alter_column_multiple() {
             .unwrap()
             .iter()
             .map(|row| row.get("counter"))
-            .nth(0)
+            .next()
             .unwrap();
         assert_eq!(52, result);
 
@@ -415,7 +417,7 @@ fn alter_column_multiple() {
             .unwrap()
             .iter()
             .map(|row| row.get("counter"))
-            .nth(0)
+            .next()
             .unwrap();
         assert_eq!(48, result);
     });
@@ -732,3 +734,184 @@ fn alter_column_with_hash_index() {
 
     test.run();
 }
+
+#[test]
+fn alter_column_rename_and_change_type() {
+    let mut test = Test::new("Rename column and change its type");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "VARCHAR(10)"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rename_and_widen_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+            type = "VARCHAR(100)"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // The old schema still uses the old name while the new schema uses the new one
+        let result = old_db
+            .query_one("SELECT name FROM users WHERE id = 1", &[])
+            .unwrap();
+        assert_eq!("John", result.get::<_, &str>("name"));
+
+        let result = new_db
+            .query_one("SELECT full_name FROM users WHERE id = 1", &[])
+            .unwrap();
+        assert_eq!("John", result.get::<_, &str>("full_name"));
+    });
+
+    test.after_completion(|db| {
+        let result = db
+            .query_one("SELECT full_name FROM users WHERE id = 1", &[])
+            .unwrap();
+        assert_eq!("John", result.get::<_, &str>("full_name"));
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_rename_interrupted() {
+    let mut test = Test::new("Rename column interrupted");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rename_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+        "#,
+    );
+
+    // Completing again finds the column already renamed
+    test.interrupt_after(Phase::Complete, 1);
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John Doe')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        assert_rows(
+            db,
+            "SELECT id, full_name FROM users",
+            &[&[Some("1"), Some("John Doe")]],
+        );
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_change_type_interrupted() {
+    let mut test = Test::new("Change column type interrupted");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "VARCHAR(10)"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rename_and_widen_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+            type = "VARCHAR(100)"
+        "#,
+    );
+
+    // Completing again finds the temporary column already in place of the
+    // old one, which must not be dropped a second time
+    test.interrupt_after(Phase::Complete, 1);
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        assert_columns(db, "users", &["id", "full_name"]);
+        assert_rows(
+            db,
+            "SELECT id, full_name FROM users",
+            &[&[Some("1"), Some("John")]],
+        );
+    });
+
+    test.run();
+}
@@ -0,0 +1,201 @@
+#![cfg(feature = "async")]
+
+use reshape::{
+    asynchronous::Reshape,
+    migrations::{BackfillOptions, Migration},
+    testing::{connection_string, parse_migration},
+};
+use tokio_postgres::NoTls;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_migrate_complete_and_abort() {
+    let connection_string = connection_string();
+
+    let mut reshape = Reshape::new(&connection_string).await.unwrap();
+    reshape.remove().await.unwrap();
+
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_first_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+        up = "(STRING_TO_ARRAY(name, ' '))[1]"
+
+            [actions.column]
+            name = "first"
+            type = "TEXT"
+            nullable = false
+        "#,
+    )
+    .unwrap();
+
+    reshape
+        .migrate(vec![first_migration.clone()])
+        .await
+        .unwrap();
+    reshape.complete().await.unwrap();
+
+    let (db, connection) = tokio_postgres::connect(&connection_string, NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    db.simple_query(&reshape::schema_query_for_migration(&first_migration.name))
+        .await
+        .unwrap();
+    db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John Doe')")
+        .await
+        .unwrap();
+
+    // Start the second migration and abort it, which should remove the new schema
+    reshape
+        .migrate(vec![first_migration.clone(), second_migration.clone()])
+        .await
+        .unwrap();
+    reshape.abort().await.unwrap();
+
+    let new_schema_exists = !db
+        .query(
+            "SELECT schema_name FROM information_schema.schemata WHERE schema_name = $1",
+            &[&"migration_add_first_name_column"],
+        )
+        .await
+        .unwrap()
+        .is_empty();
+    assert!(!new_schema_exists);
+
+    // Apply the second migration again and complete it, which uses a transaction
+    reshape
+        .migrate(vec![first_migration, second_migration.clone()])
+        .await
+        .unwrap();
+    reshape.complete().await.unwrap();
+
+    db.simple_query(&reshape::schema_query_for_migration(&second_migration.name))
+        .await
+        .unwrap();
+    let first: String = db
+        .query_one("SELECT first FROM users WHERE id = 1", &[])
+        .await
+        .unwrap()
+        .get("first");
+    assert_eq!("John", first);
+}
+
+// Queries run on the runtime the methods are awaited on, so a current-thread
+// runtime works too, including for backfills which use several connections at
+// once. The futures can also be spawned onto the runtime.
+#[tokio::test]
+async fn async_on_current_thread_runtime() {
+    let connection_string = connection_string();
+
+    let mut reshape = Reshape::new(&connection_string)
+        .await
+        .unwrap()
+        .with_backfill_options(BackfillOptions {
+            batch_size: 100,
+            workers: 4,
+            ..BackfillOptions::default()
+        });
+    reshape.remove().await.unwrap();
+
+    let first_migration = parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+    let second_migration = parse_migration(
+        r#"
+        name = "add_first_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+        up = "(STRING_TO_ARRAY(name, ' '))[1]"
+
+            [actions.column]
+            name = "first"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    reshape
+        .migrate(vec![first_migration.clone()])
+        .await
+        .unwrap();
+    reshape.complete().await.unwrap();
+
+    let (db, connection) = tokio_postgres::connect(&connection_string, NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    db.simple_query(
+        "INSERT INTO users (id, name) SELECT i, 'John Doe' FROM generate_series(1, 1000) AS i; ANALYZE users",
+    )
+    .await
+    .unwrap();
+
+    let mut reshape = tokio::spawn(async move {
+        reshape
+            .migrate(vec![first_migration, second_migration])
+            .await
+            .unwrap();
+        reshape.complete().await.unwrap();
+        reshape
+    })
+    .await
+    .unwrap();
+
+    db.simple_query(&reshape::schema_query_for_migration(
+        "add_first_name_column",
+    ))
+    .await
+    .unwrap();
+    let johns: i64 = db
+        .query_one("SELECT COUNT(*) FROM users WHERE first = 'John'", &[])
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(1000, johns);
+
+    reshape.remove().await.unwrap();
+}
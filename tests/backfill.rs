@@ -0,0 +1,539 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+use postgres::{Client, NoTls};
+use reshape::{
+    migrations::{BackfillOptions, BackfillProgress, Migration},
+    testing::{connection_string, Test},
+    Event, Reshape,
+};
+
+#[test]
+fn backfill_resumes_after_interruption() {
+    let connection_string = connection_string();
+
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_first_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+        up = "(STRING_TO_ARRAY(name, ' '))[1]"
+
+            [actions.column]
+            name = "first"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let mut reshape = Reshape::new(&connection_string).unwrap();
+    reshape.remove().unwrap();
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    let mut db = Client::connect(&connection_string, NoTls).unwrap();
+    db.simple_query(&reshape::schema_query_for_migration(&first_migration.name))
+        .unwrap();
+    db.simple_query(
+        "INSERT INTO users (id, name) SELECT id, 'User ' || id FROM generate_series(1, 10) AS id",
+    )
+    .unwrap();
+
+    // Interrupt the backfill after two batches of two rows, leaving the migration in
+    // the applying state as if the process had been killed
+    let interrupted = BackfillOptions {
+        batch_size: 2,
+        ..Default::default()
+    }
+    .on_progress(|progress| {
+        if progress.rows >= 4 {
+            panic!("simulated interruption");
+        }
+    });
+    let mut reshape = Reshape::new(&connection_string)
+        .unwrap()
+        .with_backfill_options(interrupted);
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        reshape.migrate(vec![first_migration.clone(), second_migration.clone()])
+    }));
+    assert!(result.is_err());
+    drop(reshape);
+
+    // Running migrate again should continue from the last saved batch
+    let reported_rows = Arc::new(Mutex::new(Vec::new()));
+    let resumed = BackfillOptions {
+        batch_size: 2,
+        ..Default::default()
+    }
+    .on_progress({
+        let reported_rows = reported_rows.clone();
+        move |progress| reported_rows.lock().unwrap().push(progress.rows)
+    });
+    let mut reshape = Reshape::new(&connection_string)
+        .unwrap()
+        .with_backfill_options(resumed);
+    reshape
+        .migrate(vec![first_migration.clone(), second_migration.clone()])
+        .unwrap();
+
+    assert_eq!(vec![6, 8, 10], *reported_rows.lock().unwrap());
+
+    // The saved progress should be removed once the migration has been applied
+    let saved_progress: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM reshape.data WHERE key LIKE 'backfill%'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(0, saved_progress);
+
+    reshape.complete().unwrap();
+
+    db.simple_query(&reshape::schema_query_for_migration(&second_migration.name))
+        .unwrap();
+    let missing: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM users WHERE first IS DISTINCT FROM 'User'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(0, missing);
+}
+
+#[test]
+fn backfill_table_with_unique_index() {
+    let mut test = Test::new("Backfill table with unique index");
+
+    test.first_migration(
+        r#"
+        name = "create_logs_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE logs (
+                id INTEGER NOT NULL UNIQUE,
+                message TEXT
+            )
+        """
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_level_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "logs"
+
+        up = "'info: ' || message"
+
+            [actions.column]
+            name = "level"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO logs (id, message) SELECT id, 'Message ' || id FROM generate_series(1, 2500) AS id",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|_, new_db| {
+        let missing: i64 = new_db
+            .query_one(
+                "SELECT COUNT(*) FROM logs WHERE level IS DISTINCT FROM 'info: ' || message",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, missing);
+    });
+
+    test.run();
+}
+
+#[test]
+fn backfill_table_with_composite_unique_index() {
+    let mut test = Test::new("Backfill table with composite unique index");
+
+    test.first_migration(
+        r#"
+        name = "create_logs_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE logs (
+                source TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                message TEXT,
+                UNIQUE (source, id)
+            )
+        """
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_level_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "logs"
+
+        up = "'info: ' || message"
+
+            [actions.column]
+            name = "level"
+            type = "TEXT"
+        "#,
+    );
+
+    // More rows than fit in a batch, so later batches continue from the last
+    // key of the one before
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO logs (source, id, message) SELECT 'source ' || (id % 3), id / 3, 'Message ' || id FROM generate_series(1, 2500) AS id",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|_, new_db| {
+        let missing: i64 = new_db
+            .query_one(
+                "SELECT COUNT(*) FROM logs WHERE level IS DISTINCT FROM 'info: ' || message",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, missing);
+    });
+
+    test.run();
+}
+
+#[test]
+fn backfill_table_without_keys() {
+    let mut test = Test::new("Backfill table without keys");
+
+    test.first_migration(
+        r#"
+        name = "create_logs_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE logs (
+                message TEXT
+            )
+        """
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_level_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "logs"
+
+        up = "'info: ' || message"
+
+            [actions.column]
+            name = "level"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        // Insert duplicate rows spanning several pages
+        db.simple_query(
+            "INSERT INTO logs (message) SELECT 'Message ' || (id % 100) FROM generate_series(1, 5000) AS id",
+        )
+        .unwrap();
+        db.simple_query("ANALYZE logs").unwrap();
+    });
+
+    test.intermediate(|_, new_db| {
+        let (total, missing): (i64, i64) = new_db
+            .query_one(
+                "SELECT COUNT(*), COUNT(*) FILTER (WHERE level IS DISTINCT FROM 'info: ' || message) FROM logs",
+                &[],
+            )
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert_eq!(5000, total);
+        assert_eq!(0, missing);
+    });
+
+    test.run();
+}
+
+#[test]
+fn backfill_partitioned_table_without_keys_fails() {
+    let mut test = Test::new("Backfill partitioned table without keys");
+
+    test.first_migration(
+        r#"
+        name = "create_logs_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE logs (
+                created_at DATE NOT NULL,
+                message TEXT
+            ) PARTITION BY RANGE (created_at);
+
+            CREATE TABLE logs_default PARTITION OF logs DEFAULT;
+        """
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_level_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "logs"
+
+        up = "'info: ' || message"
+
+            [actions.column]
+            name = "level"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO logs (created_at, message) VALUES (NOW(), 'Message')")
+            .unwrap();
+    });
+
+    test.expect_failure();
+    test.run();
+}
+
+// Returns the progress passed to the callback and the progress sent as events
+fn run_parallel_backfill(
+    create_table: &str,
+    workers: Option<u32>,
+) -> (Vec<BackfillProgress>, Vec<BackfillProgress>) {
+    let connection_string = connection_string();
+
+    let first_migration: Migration = toml::from_str(&format!(
+        r#"
+        name = "create_events_table"
+
+        [[actions]]
+        type = "custom"
+        start = """{}"""
+        "#,
+        create_table
+    ))
+    .unwrap();
+
+    let mut second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_kind_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "events"
+
+        up = "'event ' || payload"
+
+            [actions.column]
+            name = "kind"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    if let Some(workers) = workers {
+        let mut action: serde_json::Value =
+            serde_json::to_value(&second_migration.actions[0]).unwrap();
+        action["backfill_workers"] = workers.into();
+        second_migration.actions[0] = serde_json::from_value(action).unwrap();
+    }
+
+    let mut reshape = Reshape::new(&connection_string).unwrap();
+    reshape.remove().unwrap();
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    let mut db = Client::connect(&connection_string, NoTls).unwrap();
+    db.simple_query(
+        "INSERT INTO events (payload) SELECT id::TEXT FROM generate_series(1, 20000) AS id; ANALYZE events",
+    )
+    .unwrap();
+
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let options = BackfillOptions {
+        batch_size: 500,
+        workers: 2,
+        ..Default::default()
+    }
+    .on_progress({
+        let reported = reported.clone();
+        move |progress| reported.lock().unwrap().push(progress.clone())
+    });
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut reshape = Reshape::new(&connection_string)
+        .unwrap()
+        .with_backfill_options(options)
+        .on_event({
+            let events = events.clone();
+            move |event| {
+                if let Event::BackfillProgress {
+                    table,
+                    worker,
+                    worker_rows,
+                    rows,
+                    estimated_total_rows,
+                } = event
+                {
+                    events.lock().unwrap().push(BackfillProgress {
+                        table: table.clone(),
+                        worker: *worker,
+                        worker_rows: *worker_rows,
+                        rows: *rows,
+                        estimated_total_rows: *estimated_total_rows,
+                    });
+                }
+            }
+        });
+    reshape
+        .migrate(vec![first_migration, second_migration.clone()])
+        .unwrap();
+
+    db.simple_query(&reshape::schema_query_for_migration(&second_migration.name))
+        .unwrap();
+    let missing: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM events WHERE kind IS DISTINCT FROM 'event ' || payload",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(0, missing);
+
+    reshape.complete().unwrap();
+
+    let reported = reported.lock().unwrap().clone();
+    let events = events.lock().unwrap().clone();
+    (reported, events)
+}
+
+// Returns the total number of rows touched by all workers
+fn assert_workers_completed(progress: &[BackfillProgress], workers: usize) -> u64 {
+    let worker_rows: Vec<u64> = (0..workers)
+        .map(|worker| {
+            progress
+                .iter()
+                .filter(|progress| progress.worker == worker)
+                .map(|progress| progress.worker_rows)
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    assert!(worker_rows.iter().all(|rows| *rows > 0));
+    assert_eq!(
+        Some(worker_rows.iter().sum::<u64>()),
+        progress.iter().map(|progress| progress.rows).max()
+    );
+
+    worker_rows.iter().sum()
+}
+
+#[test]
+fn backfill_in_parallel_by_primary_key() {
+    let (progress, events) = run_parallel_backfill(
+        "CREATE TABLE events (id SERIAL PRIMARY KEY, payload TEXT)",
+        Some(4),
+    );
+    assert_eq!(20000, assert_workers_completed(&progress, 4));
+
+    // Events are sent less often than the callback is called, but always
+    // include the last batch of each worker
+    assert_eq!(20000, assert_workers_completed(&events, 4));
+    assert!(events.len() <= progress.len());
+    assert!(events.iter().all(|event| event.table == "events"));
+}
+
+#[test]
+fn backfill_in_parallel_by_composite_primary_key() {
+    let (progress, _) = run_parallel_backfill(
+        "
+        CREATE TABLE events (
+            id SERIAL,
+            source INTEGER NOT NULL DEFAULT FLOOR(RANDOM() * 3),
+            payload TEXT,
+            PRIMARY KEY (source, id)
+        )
+        ",
+        Some(4),
+    );
+    assert_eq!(20000, assert_workers_completed(&progress, 4));
+}
+
+#[test]
+fn backfill_in_parallel_by_composite_unique_index() {
+    let (progress, _) = run_parallel_backfill(
+        "
+        CREATE TABLE events (
+            id SERIAL,
+            source TEXT NOT NULL DEFAULT 'source ' || FLOOR(RANDOM() * 3),
+            payload TEXT,
+            UNIQUE (source, id)
+        )
+        ",
+        Some(4),
+    );
+    assert_eq!(20000, assert_workers_completed(&progress, 4));
+}
+
+#[test]
+fn backfill_in_parallel_by_ctid() {
+    let (progress, events) = run_parallel_backfill("CREATE TABLE events (payload TEXT)", None);
+
+    // Rows moved to pages which haven't been backfilled yet are touched again
+    assert!(assert_workers_completed(&progress, 2) >= 20000);
+    assert_eq!(
+        assert_workers_completed(&progress, 2),
+        assert_workers_completed(&events, 2)
+    );
+}
@@ -0,0 +1,246 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing::connection_string, Event, Reshape, Timeouts};
+
+fn migrations() -> Vec<Migration> {
+    vec![
+        toml::from_str(
+            r#"
+            name = "create_users_table"
+
+            [[actions]]
+            type = "create_table"
+            name = "users"
+            primary_key = ["id"]
+
+                [[actions.columns]]
+                name = "id"
+                type = "INTEGER"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "add_name_column"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "name"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+    ]
+}
+
+// Connect like an application which has started a transaction reading from
+// the users table and then gone idle, holding a lock on the table
+fn connect_idle_in_transaction() -> (Client, i32) {
+    let mut client = Client::connect(&connection_string(), NoTls).unwrap();
+    let pid = client
+        .query_one("SELECT pg_backend_pid()", &[])
+        .unwrap()
+        .get(0);
+    client.batch_execute("BEGIN; SELECT * FROM users").unwrap();
+    (client, pid)
+}
+
+fn reshape_with_events(timeouts: Timeouts) -> (Reshape, Arc<Mutex<Vec<Event>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let collected = events.clone();
+    let reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .on_event(move |event| collected.lock().unwrap().push(event.clone()))
+        .with_timeouts(timeouts)
+        .unwrap();
+    (reshape, events)
+}
+
+#[test]
+fn report_blockers_on_lock_timeout() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+
+    let (mut reshape, events) = reshape_with_events(Timeouts::default());
+
+    // The blocking transaction ends after a few attempts, after which the
+    // migration goes through
+    let (mut client, blocker_pid) = connect_idle_in_transaction();
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(1500));
+        client.batch_execute("COMMIT").unwrap();
+    });
+    reshape.migrate(migrations.clone()).unwrap();
+    handle.join().unwrap();
+
+    let events = events.lock().unwrap().clone();
+    let lock_timeouts: Vec<&Event> = events
+        .iter()
+        .filter(|event| matches!(event, Event::LockTimeout { .. }))
+        .collect();
+    assert!(!lock_timeouts.is_empty());
+
+    match lock_timeouts[0] {
+        Event::LockTimeout {
+            attempt,
+            retrying,
+            relation,
+            blockers,
+        } => {
+            assert_eq!(1, *attempt);
+            assert!(retrying);
+            assert_eq!(Some("users"), relation.as_deref());
+            assert_eq!(1, blockers.len());
+            assert_eq!(blocker_pid, blockers[0].pid);
+            assert_eq!(Some("idle in transaction"), blockers[0].state.as_deref());
+            assert_eq!(
+                Some("BEGIN; SELECT * FROM users"),
+                blockers[0].query.as_deref()
+            );
+            assert!(blockers[0].transaction_age_ms.is_some());
+        }
+        _ => unreachable!(),
+    }
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn terminate_idle_blockers() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+
+    let (mut reshape, events) = reshape_with_events(Timeouts {
+        lock_timeout: Duration::from_millis(200),
+        terminate_idle_blockers_after: Some(Duration::ZERO),
+        ..Timeouts::default()
+    });
+
+    // The blocker is terminated while waiting for the lock
+    let (mut client, blocker_pid) = connect_idle_in_transaction();
+    reshape.migrate(migrations).unwrap();
+    assert!(client.simple_query("SELECT 1").is_err());
+
+    assert!(events
+        .lock()
+        .unwrap()
+        .contains(&Event::SessionTerminated { pid: blocker_pid }));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn watch_parallel_backfill_with_one_connection() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    drop(reshape);
+
+    // Updates are slowed down so that every batch takes much longer than the
+    // lock timeout, without waiting for any locks
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    db.batch_execute(
+        "
+        INSERT INTO users (id) SELECT generate_series(1, 1000);
+        ANALYZE users;
+        CREATE OR REPLACE FUNCTION slow_update() RETURNS TRIGGER AS $$
+        BEGIN
+            PERFORM pg_sleep(0.002);
+            RETURN NEW;
+        END
+        $$ LANGUAGE plpgsql;
+        CREATE TRIGGER slow_update BEFORE UPDATE ON users FOR EACH ROW EXECUTE PROCEDURE slow_update();
+        ",
+    )
+    .unwrap();
+
+    let add_column: Migration = toml::from_str(
+        r#"
+        name = "add_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'name ' || id"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let (reshape, events) = reshape_with_events(Timeouts {
+        lock_timeout: Duration::from_millis(50),
+        ..Timeouts::default()
+    });
+    let mut reshape = reshape.with_backfill_options(reshape::migrations::BackfillOptions {
+        batch_size: 125,
+        workers: 4,
+        ..Default::default()
+    });
+
+    // Count the sessions while the backfill is running, other than this
+    // test's own
+    let done = Arc::new(Mutex::new(false));
+    let monitor = {
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut client = Client::connect(&connection_string(), NoTls).unwrap();
+            let mut max_sessions = 0;
+            while !*done.lock().unwrap() {
+                let sessions: i64 = client
+                    .query_one(
+                        "
+                        SELECT COUNT(*)
+                        FROM pg_stat_activity
+                        WHERE datname = current_database()
+                        AND backend_type = 'client backend'
+                        AND pid <> pg_backend_pid()
+                        ",
+                        &[],
+                    )
+                    .unwrap()
+                    .get(0);
+                max_sessions = max_sessions.max(sessions);
+                thread::sleep(Duration::from_millis(20));
+            }
+            max_sessions
+        })
+    };
+
+    reshape
+        .migrate(vec![migrations[0].clone(), add_column])
+        .unwrap();
+    *done.lock().unwrap() = true;
+    let max_sessions = monitor.join().unwrap();
+
+    // The test's connection, the lock, four workers and a single connection
+    // used to watch all of their queries
+    assert!(max_sessions <= 7, "{} sessions", max_sessions);
+    assert!(!events
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|event| matches!(event, Event::LockTimeout { .. })));
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+    db.batch_execute("DROP FUNCTION slow_update").unwrap();
+}
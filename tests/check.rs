@@ -0,0 +1,250 @@
+use reshape::{check_migrations, migrations::Migration, testing::parse_migration};
+
+fn create_users_table() -> Migration {
+    parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+        "#,
+    )
+}
+
+fn problem_messages(migrations: &[Migration]) -> Vec<String> {
+    check_migrations(migrations)
+        .into_iter()
+        .map(|problem| problem.message)
+        .collect()
+}
+
+#[test]
+fn check_valid_migrations() {
+    let second_migration = parse_migration(
+        r#"
+        name = "rename_users"
+
+        [[actions]]
+        type = "rename_table"
+        table = "users"
+        new_name = "customers"
+
+        [[actions]]
+        type = "alter_column"
+        table = "customers"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+
+        [[actions]]
+        type = "remove_index"
+        index = "users_name_idx"
+        "#,
+    );
+
+    assert!(check_migrations(&[create_users_table(), second_migration]).is_empty());
+}
+
+#[test]
+fn check_missing_references() {
+    let second_migration = parse_migration(
+        r#"
+        name = "broken_references"
+
+        [[actions]]
+        type = "add_column"
+        table = "user"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "nmae"
+
+            [actions.changes]
+            nullable = false
+
+        [[actions]]
+        type = "remove_index"
+        index = "users_email_idx"
+
+        [[actions]]
+        type = "remove_foreign_key"
+        table = "users"
+        foreign_key = "users_account_id_fkey"
+
+        [[actions]]
+        type = "remove_enum"
+        enum = "mood"
+
+        [[actions]]
+        type = "add_foreign_key"
+        table = "users"
+
+            [actions.foreign_key]
+            columns = ["id"]
+            referenced_table = "accounts"
+            referenced_columns = ["id"]
+        "#,
+    );
+
+    let problems = check_migrations(&[create_users_table(), second_migration]);
+    let messages: Vec<&str> = problems
+        .iter()
+        .map(|problem| problem.message.as_str())
+        .collect();
+    assert_eq!(
+        vec![
+            "table user doesn't exist",
+            "column users.nmae doesn't exist",
+            "index users_email_idx doesn't exist",
+            "foreign key users_account_id_fkey doesn't exist on table users",
+            "enum mood doesn't exist",
+            "table accounts doesn't exist",
+        ],
+        messages
+    );
+
+    // Problems point to the migration and action they were found in
+    assert_eq!("broken_references", problems[1].migration);
+    assert_eq!(Some(1), problems[1].action_index);
+    assert_eq!(
+        "migration broken_references, action 2 (Altering column \"nmae\" on \"users\"): column users.nmae doesn't exist",
+        problems[1].to_string()
+    );
+}
+
+#[test]
+fn check_duplicates() {
+    let second_migration = parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "create_table"
+        name = "accounts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "id"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_index"
+        table = "accounts"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["id"]
+
+        [[actions]]
+        type = "create_enum"
+        name = "mood"
+        values = ["happy", "happy"]
+        "#,
+    );
+
+    assert_eq!(
+        vec![
+            "another migration has the same name",
+            "table users already exists",
+            "column id is defined more than once in table accounts",
+            "index users_name_idx already exists",
+            "value happy is defined more than once in enum mood",
+        ],
+        problem_messages(&[create_users_table(), second_migration])
+    );
+}
+
+#[test]
+fn check_missing_primary_key_column() {
+    let migration = parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["user_id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    assert_eq!(
+        vec!["primary key column user_id doesn't exist in table users"],
+        problem_messages(&[migration])
+    );
+}
+
+#[test]
+fn check_after_custom_action() {
+    // Tables and columns which may have been created by a custom action aren't reported
+    let migration = parse_migration(
+        r#"
+        name = "custom_users_table"
+
+        [[actions]]
+        type = "custom"
+        start = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+        "#,
+    );
+
+    assert_eq!(
+        vec!["index users_name_idx already exists"],
+        problem_messages(&[migration])
+    );
+}
@@ -0,0 +1,108 @@
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing::connection_string, Reshape};
+
+fn create_users_table(name_type: &str) -> Migration {
+    toml::from_str(&format!(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "{}"
+        "#,
+        name_type
+    ))
+    .unwrap()
+}
+
+fn add_email_column() -> Migration {
+    toml::from_str(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn changed_migration_is_rejected() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_users_table("TEXT")]).unwrap();
+    reshape.complete().unwrap();
+
+    // Changing an applied migration should stop further migrations
+    let changed = vec![create_users_table("VARCHAR(100)"), add_email_column()];
+    let error = reshape.migrate(changed.clone()).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("migration create_users_table has been changed since it was applied"));
+
+    // Formatting changes don't affect the checksum
+    let reformatted: Migration =
+        serde_json::from_str(&serde_json::to_string_pretty(&create_users_table("TEXT")).unwrap())
+            .unwrap();
+    reshape
+        .migrate(vec![reformatted, add_email_column()])
+        .unwrap();
+    reshape.abort().unwrap();
+
+    // After re-baselining, the changed migration is accepted
+    reshape.rebaseline(changed.clone()).unwrap();
+    reshape.migrate(changed).unwrap();
+    reshape.complete().unwrap();
+
+    // The original migration no longer matches
+    let error = reshape
+        .migrate(vec![create_users_table("TEXT"), add_email_column()])
+        .unwrap_err();
+    assert!(error.to_string().contains("has been changed"));
+}
+
+#[test]
+fn migrations_without_checksums_are_baselined() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_users_table("TEXT")]).unwrap();
+    reshape.complete().unwrap();
+
+    // Simulate a migration applied before checksums were stored
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    db.simple_query("UPDATE reshape.migrations SET checksum = NULL")
+        .unwrap();
+
+    reshape
+        .migrate(vec![create_users_table("VARCHAR(100)")])
+        .unwrap();
+
+    let checksum: Option<String> = db
+        .query_one(
+            "SELECT checksum FROM reshape.migrations WHERE name = 'create_users_table'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(
+        Some(create_users_table("VARCHAR(100)").checksum()),
+        checksum
+    );
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn move_column_between_tables() {
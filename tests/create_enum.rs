@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn create_enum() {
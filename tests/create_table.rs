@@ -1,5 +1,7 @@
-mod common;
-use common::Test;
+use reshape::{
+    testing::{assert_columns, Test},
+    Phase,
+};
 
 #[test]
 fn create_table() {
@@ -198,3 +200,47 @@ fn create_table_with_foreign_keys() {
 
     test.run();
 }
+
+#[test]
+fn create_table_interrupted() {
+    let mut test = Test::new("Create table interrupted");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    // Applying the migration again creates the table a second time
+    test.interrupt_after(Phase::Migrate, 1);
+
+    test.intermediate(|_, new_db| {
+        assert_columns(new_db, "items", &["id"]);
+    });
+
+    test.run();
+}
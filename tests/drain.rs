@@ -0,0 +1,220 @@
+use std::{thread, time::Duration};
+
+use postgres::{Client, NoTls};
+use reshape::{
+    migrations::Migration, testing::connection_string, DrainOptions, Hook, Hooks, Reshape,
+};
+
+fn migrations() -> Vec<Migration> {
+    vec![
+        toml::from_str(
+            r#"
+            name = "create_users_table"
+
+            [[actions]]
+            type = "create_table"
+            name = "users"
+            primary_key = ["id"]
+
+                [[actions.columns]]
+                name = "id"
+                type = "INTEGER"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "add_name_column"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "name"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "add_email_column"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "email"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+    ]
+}
+
+// Connect like an application using the schema of `migration`, which names
+// the schema in its application_name so that it can be found while draining
+fn connect_to_schema(migration: &str) -> Client {
+    let mut config: postgres::Config = connection_string().parse().unwrap();
+    config.application_name(&format!(
+        "app/{}",
+        reshape::schema_name_for_migration(migration)
+    ));
+    let mut client = config.connect(NoTls).unwrap();
+    client
+        .simple_query(&reshape::schema_query_for_migration(migration))
+        .unwrap();
+    client
+}
+
+#[test]
+fn wait_for_drain() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations[..2].to_vec()).unwrap();
+
+    let old_client = connect_to_schema("create_users_table");
+    let mut new_client = connect_to_schema("add_name_column");
+
+    // Sessions using the old schema block completion until the timeout
+    let mut reshape = reshape.with_drain(Some(DrainOptions {
+        timeout: Duration::from_millis(500),
+        poll_interval: Duration::from_millis(100),
+        terminate: false,
+    }));
+    assert!(reshape.complete().is_err());
+    assert_eq!("in_progress", reshape.status().unwrap().state);
+
+    // Completion proceeds once the sessions have ended
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        drop(old_client);
+    });
+    let mut reshape = reshape.with_drain(Some(DrainOptions {
+        timeout: Duration::from_secs(10),
+        poll_interval: Duration::from_millis(100),
+        terminate: false,
+    }));
+    reshape.complete().unwrap();
+    handle.join().unwrap();
+
+    // Sessions left after the timeout are terminated if enabled
+    reshape.migrate(migrations.clone()).unwrap();
+    let mut reshape = reshape.with_drain(Some(DrainOptions {
+        timeout: Duration::ZERO,
+        poll_interval: Duration::from_millis(100),
+        terminate: true,
+    }));
+    reshape.complete().unwrap();
+    assert!(new_client.simple_query("SELECT 1").is_err());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn drain_before_pre_complete_hooks() {
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    db.simple_query("DROP TABLE IF EXISTS hook_log; CREATE TABLE hook_log (hook TEXT)")
+        .unwrap();
+
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations[..2].to_vec()).unwrap();
+
+    let _old_client = connect_to_schema("create_users_table");
+
+    // The hooks don't run when completion times out waiting for sessions
+    let mut reshape = reshape
+        .with_hooks(Hooks {
+            pre_complete: vec![Hook::Sql {
+                sql: "INSERT INTO hook_log (hook) VALUES ('pre_complete')".to_string(),
+            }],
+            ..Default::default()
+        })
+        .with_drain(Some(DrainOptions {
+            timeout: Duration::from_millis(300),
+            poll_interval: Duration::from_millis(100),
+            terminate: false,
+        }));
+    assert!(reshape.complete().is_err());
+
+    let hooks_run: i64 = db
+        .query_one("SELECT COUNT(*) FROM hook_log", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!(0, hooks_run);
+
+    reshape.with_drain(None).complete().unwrap();
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn drain_schema_with_regex_characters() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    // `$` is allowed in schema names but would anchor an unescaped regex
+    let mut migrations = migrations();
+    migrations[0].name = "create$users_table".to_string();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations[..2].to_vec()).unwrap();
+
+    let old_client = connect_to_schema("create$users_table");
+
+    let mut reshape = reshape.with_drain(Some(DrainOptions {
+        timeout: Duration::from_millis(300),
+        poll_interval: Duration::from_millis(100),
+        terminate: false,
+    }));
+    assert!(reshape.complete().is_err());
+
+    drop(old_client);
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn drain_pooled_sessions() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations[..2].to_vec()).unwrap();
+
+    // A pooled connection which has run other queries since setting its
+    // search_path, and holds no locks while idle
+    let mut old_client = connect_to_schema("create_users_table");
+    old_client.simple_query("SELECT 1").unwrap();
+
+    // A session which only mentions the schema in a query isn't using it
+    let mut other_client = Client::connect(&connection_string(), NoTls).unwrap();
+    other_client
+        .simple_query("SELECT 'migration_create_users_table'")
+        .unwrap();
+
+    let mut reshape = reshape.with_drain(Some(DrainOptions {
+        timeout: Duration::from_millis(300),
+        poll_interval: Duration::from_millis(100),
+        terminate: false,
+    }));
+    assert_eq!(
+        "timed out waiting for 1 sessions using the previous schema to end",
+        reshape.complete().unwrap_err().to_string()
+    );
+
+    drop(old_client);
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
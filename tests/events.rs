@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+
+use reshape::{migrations::Migration, testing::connection_string, Event, Phase, Reshape};
+
+fn migration(data: &str) -> Migration {
+    toml::from_str(data).unwrap()
+}
+
+#[test]
+fn events() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let collected = events.clone();
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .on_event(move |event| collected.lock().unwrap().push(event.clone()));
+    reshape.remove().unwrap();
+    events.lock().unwrap().clear();
+
+    let create_table = migration(
+        r#"
+        name = "create_events_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "events"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+    reshape.migrate(vec![create_table.clone()]).unwrap();
+
+    let events_so_far: Vec<Event> = events
+        .lock()
+        .unwrap()
+        .drain(..)
+        .filter(|event| !matches!(event, Event::Message { .. }))
+        .collect();
+    assert!(matches!(
+        &events_so_far[0],
+        Event::MigrationStarted { phase: Phase::Migrate, migration } if migration == "create_events_table"
+    ));
+    assert!(matches!(
+        &events_so_far[1],
+        Event::ActionStarted {
+            phase: Phase::Migrate,
+            ..
+        }
+    ));
+    assert!(matches!(
+        &events_so_far[2],
+        Event::ActionFinished {
+            phase: Phase::Migrate,
+            error: None,
+            ..
+        }
+    ));
+    assert!(events_so_far.iter().any(|event| matches!(
+        event,
+        Event::SchemaQuery { migration, .. } if migration == "create_events_table"
+    )));
+
+    // Events serialize with the event type as a field
+    let json = serde_json::to_value(&events_so_far[0]).unwrap();
+    assert_eq!("migration_started", json["event"]);
+    assert_eq!("migrate", json["phase"]);
+
+    reshape.complete().unwrap();
+    assert!(events.lock().unwrap().iter().any(|event| matches!(
+        event,
+        Event::MigrationFinished {
+            phase: Phase::Complete,
+            error: None,
+            ..
+        }
+    )));
+    events.lock().unwrap().clear();
+
+    // A failing action is reported with its error
+    let failing = migration(
+        r#"
+        name = "add_invalid_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "events"
+
+            [actions.column]
+            name = "invalid"
+            type = "NOT_A_TYPE"
+        "#,
+    );
+    assert!(reshape.migrate(vec![create_table, failing]).is_err());
+
+    let events_so_far: Vec<Event> = events.lock().unwrap().drain(..).collect();
+    assert!(events_so_far.iter().any(|event| matches!(
+        event,
+        Event::ActionFinished {
+            phase: Phase::Migrate,
+            error: Some(_),
+            ..
+        }
+    )));
+    assert!(events_so_far.iter().any(|event| matches!(
+        event,
+        Event::MigrationFinished {
+            phase: Phase::Migrate,
+            migration,
+            error: Some(_),
+            ..
+        } if migration == "add_invalid_column"
+    )));
+
+    reshape.remove().unwrap();
+}
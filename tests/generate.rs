@@ -0,0 +1,184 @@
+use reshape::{
+    migrations::{Action, Migration},
+    testing::connection_string,
+    DeclarativeSchema, PossibleRename, Reshape,
+};
+
+fn parse_schema(schema: &str) -> DeclarativeSchema {
+    toml::from_str(schema).unwrap()
+}
+
+fn action_types(actions: &[Box<dyn Action>]) -> Vec<String> {
+    actions
+        .iter()
+        .map(|action| {
+            serde_json::to_value(action).unwrap()["type"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+}
+
+fn no_renames(rename: &PossibleRename) -> anyhow::Result<Option<String>> {
+    panic!("unexpected possible rename of {}", rename.name)
+}
+
+#[test]
+fn generate_migrations() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let first_schema = parse_schema(
+        r#"
+        [[enums]]
+        name = "mood"
+        values = ["happy", "sad"]
+
+        [[tables]]
+        name = "users"
+        primary_key = ["id"]
+
+            [[tables.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[tables.columns]]
+            name = "name"
+            type = "VARCHAR(100)"
+            nullable = false
+
+            [[tables.indices]]
+            name = "users_name_idx"
+            columns = ["name"]
+
+        [[tables]]
+        name = "posts"
+        primary_key = ["id"]
+
+            [[tables.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[tables.columns]]
+            name = "user_id"
+            type = "INTEGER"
+
+            [[tables.foreign_keys]]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+        "#,
+    );
+
+    // Everything is created when no migrations have been applied
+    let actions = reshape
+        .generate_migration(&first_schema, no_renames)
+        .unwrap();
+    assert_eq!(
+        vec!["create_enum", "create_table", "add_index", "create_table"],
+        action_types(&actions)
+    );
+
+    let first_migration = Migration {
+        name: "first".to_string(),
+        description: None,
+        actions,
+        hooks: Default::default(),
+    };
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    // Once applied, there are no differences left. Types and defaults are
+    // compared the way Postgres formats them.
+    let actions = reshape
+        .generate_migration(&first_schema, no_renames)
+        .unwrap();
+    assert!(actions.is_empty());
+
+    // Renamed columns are marked with hints while renamed tables are resolved.
+    // Existing tables are changed before they are renamed.
+    let second_schema = parse_schema(
+        r#"
+        [[tables]]
+        name = "accounts"
+        primary_key = ["id"]
+        renamed_columns = { full_name = "name" }
+
+            [[tables.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[tables.columns]]
+            name = "full_name"
+            type = "VARCHAR(200)"
+            nullable = false
+
+            [[tables.columns]]
+            name = "email"
+            type = "TEXT"
+
+            [[tables.indices]]
+            name = "users_name_idx"
+            columns = ["full_name"]
+        "#,
+    );
+
+    let mut possible_renames = Vec::new();
+    let actions = reshape
+        .generate_migration(&second_schema, |rename| {
+            possible_renames.push(rename.clone());
+            Ok(Some("users".to_string()))
+        })
+        .unwrap();
+    assert_eq!(1, possible_renames.len());
+    assert_eq!(None, possible_renames[0].table);
+    assert_eq!("accounts", possible_renames[0].name);
+    assert_eq!(vec!["posts", "users"], possible_renames[0].candidates);
+    assert_eq!(
+        vec![
+            "alter_column",
+            "add_column",
+            "rename_table",
+            "remove_table",
+            "remove_enum"
+        ],
+        action_types(&actions)
+    );
+
+    let second_migration = Migration {
+        name: "second".to_string(),
+        description: None,
+        actions,
+        hooks: Default::default(),
+    };
+    reshape
+        .migrate(vec![first_migration.clone(), second_migration.clone()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    let schema = reshape.dump_schema(None).unwrap().remove(0);
+    let accounts = &schema.tables[0];
+    assert_eq!("accounts", accounts.name);
+    let columns: Vec<(&str, &str)> = accounts
+        .columns
+        .iter()
+        .map(|column| (column.name.as_str(), column.data_type.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("id", "integer"),
+            ("full_name", "character varying(200)"),
+            ("email", "text")
+        ],
+        columns
+    );
+
+    // Hints which have already been applied are ignored
+    let actions = reshape
+        .generate_migration(&second_schema, no_renames)
+        .unwrap();
+    assert!(actions.is_empty());
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,101 @@
+use reshape::{
+    testing::{connection_string, parse_migration},
+    Reshape,
+};
+
+#[test]
+fn history() {
+    let first_migration = parse_migration(
+        r#"
+        name = "create_users_table"
+        description = "Add a table for users"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+    let second_migration = parse_migration(
+        r#"
+        name = "add_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+    let third_migration = parse_migration(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let history = reshape.history(vec![first_migration.clone()]).unwrap();
+    assert!(history.applied.is_empty());
+    assert_eq!(vec!["create_users_table"], history.not_applied);
+
+    reshape
+        .migrate(vec![first_migration.clone(), second_migration.clone()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // The second migration has been removed locally and the third hasn't been applied yet
+    let history = reshape
+        .history(vec![first_migration.clone(), third_migration.clone()])
+        .unwrap();
+
+    let applied: Vec<(&str, bool)> = history
+        .applied
+        .iter()
+        .map(|migration| (migration.name.as_str(), migration.missing_locally))
+        .collect();
+    assert_eq!(
+        vec![("create_users_table", false), ("add_name_column", true)],
+        applied
+    );
+    assert_eq!(vec!["add_email_column"], history.not_applied);
+
+    let first = &history.applied[0];
+    assert_eq!(Some("Add a table for users"), first.description.as_deref());
+    assert!(first.completed_at.is_some());
+    assert_eq!(
+        first_migration
+            .actions
+            .iter()
+            .map(|action| action.describe())
+            .collect::<Vec<_>>(),
+        first.actions
+    );
+
+    let output = history.to_string();
+    assert!(output.contains("add_name_column (missing locally)"));
+    assert!(output.contains("Not yet applied:\n  add_email_column"));
+    assert!(!output.contains("Add a table for users"));
+
+    // Actions and descriptions are only included in the verbose output
+    let verbose = format!("{:#}", history);
+    assert!(verbose.contains("Add a table for users"));
+    assert!(verbose.contains(&format!("- {}", first.actions[0])));
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,196 @@
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing::connection_string, Hook, Hooks, Reshape};
+
+fn create_users_table() -> Migration {
+    toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        # Check that the new schema works once the migration has been applied
+        [[hooks.post_migrate]]
+        sql = "INSERT INTO hook_log (hook) SELECT 'migration post_migrate' FROM (SELECT COUNT(*) FROM migration_create_users_table.users) AS users"
+
+        [[hooks.pre_complete]]
+        sql = "INSERT INTO hook_log (hook) VALUES ('migration pre_complete')"
+        "#,
+    )
+    .unwrap()
+}
+
+fn add_email_column() -> Migration {
+    toml::from_str(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap()
+}
+
+fn sql(query: &str) -> Hook {
+    Hook::Sql {
+        sql: query.to_string(),
+    }
+}
+
+fn log_hook(name: &str) -> Hook {
+    sql(&format!("INSERT INTO hook_log (hook) VALUES ('{}')", name))
+}
+
+fn hook_log(db: &mut Client) -> Vec<String> {
+    db.query("SELECT hook FROM hook_log ORDER BY id", &[])
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect()
+}
+
+fn state(reshape: &mut Reshape) -> String {
+    reshape.status().unwrap().state
+}
+
+#[test]
+fn hooks() {
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    db.simple_query(
+        "
+        DROP TABLE IF EXISTS hook_log;
+        CREATE TABLE hook_log (id SERIAL PRIMARY KEY, hook TEXT);
+        ",
+    )
+    .unwrap();
+
+    let project_hooks = Hooks {
+        pre_migrate: vec![log_hook("pre_migrate")],
+        post_migrate: vec![log_hook("project post_migrate")],
+        pre_complete: vec![log_hook("project pre_complete")],
+        post_complete: vec![log_hook("post_complete")],
+        ..Default::default()
+    };
+    let mut reshape = reshape.with_hooks(project_hooks.clone());
+
+    // Project hooks run before the hooks of the migrations
+    reshape.migrate(vec![create_users_table()]).unwrap();
+    assert_eq!(
+        vec![
+            "pre_migrate",
+            "project post_migrate",
+            "migration post_migrate"
+        ],
+        hook_log(&mut db)
+    );
+    db.simple_query("DELETE FROM hook_log").unwrap();
+
+    // Hooks of migrations are kept in the state, so they still run when
+    // completing from a different instance
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .with_hooks(project_hooks);
+    reshape.complete().unwrap();
+    assert_eq!(
+        vec![
+            "project pre_complete",
+            "migration pre_complete",
+            "post_complete"
+        ],
+        hook_log(&mut db)
+    );
+
+    // A failing pre hook stops the phase before anything is changed
+    let mut reshape = reshape.with_hooks(Hooks {
+        pre_migrate: vec![sql("SELECT 1 / 0")],
+        ..Default::default()
+    });
+    let migrations = vec![create_users_table(), add_email_column()];
+    assert!(reshape.migrate(migrations.clone()).is_err());
+    assert_eq!("idle", state(&mut reshape));
+
+    let mut reshape = reshape.with_hooks(Hooks {
+        pre_complete: vec![sql("SELECT 1 / 0")],
+        pre_abort: vec![sql("SELECT 1 / 0")],
+        ..Default::default()
+    });
+    reshape.migrate(migrations.clone()).unwrap();
+    assert!(reshape.complete().is_err());
+    assert_eq!("in_progress", state(&mut reshape));
+    assert!(reshape.abort().is_err());
+    assert_eq!("in_progress", state(&mut reshape));
+
+    let mut reshape = reshape.with_hooks(Hooks {
+        post_abort: vec![log_hook("post_abort")],
+        ..Default::default()
+    });
+    reshape.abort().unwrap();
+    assert_eq!("idle", state(&mut reshape));
+    assert_eq!(Some("post_abort".to_string()), hook_log(&mut db).pop());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn shell_hooks() {
+    let output = std::env::temp_dir().join("reshape_shell_hook_test");
+    let _ = std::fs::remove_file(&output);
+
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .with_hooks(Hooks {
+            pre_migrate: vec![Hook::Shell {
+                command: format!(
+                    "echo \"$RESHAPE_HOOK $RESHAPE_MIGRATIONS $RESHAPE_SCHEMA\" > {}",
+                    output.display()
+                ),
+            }],
+            pre_complete: vec![Hook::Shell {
+                command: "exit 1".to_string(),
+            }],
+            ..Default::default()
+        });
+    reshape.remove().unwrap();
+
+    let migration: Migration = toml::from_str(
+        r#"
+        name = "create_accounts_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "accounts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    reshape.migrate(vec![migration]).unwrap();
+    assert_eq!(
+        "pre_migrate create_accounts_table migration_create_accounts_table\n",
+        std::fs::read_to_string(&output).unwrap()
+    );
+
+    // A command exiting with a non-zero status fails the hook
+    assert!(reshape.complete().is_err());
+    assert_eq!("in_progress", state(&mut reshape));
+
+    let _ = std::fs::remove_file(&output);
+    reshape.remove().unwrap();
+}
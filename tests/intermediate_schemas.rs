@@ -0,0 +1,179 @@
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing::connection_string, Reshape};
+
+fn migrations() -> Vec<Migration> {
+    vec![
+        toml::from_str(
+            r#"
+            name = "create_users_table"
+
+            [[actions]]
+            type = "create_table"
+            name = "users"
+            primary_key = ["id"]
+
+                [[actions.columns]]
+                name = "id"
+                type = "INTEGER"
+
+                [[actions.columns]]
+                name = "name"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "add_email_column"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "email"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "rename_name_column"
+
+            [[actions]]
+            type = "alter_column"
+            table = "users"
+            column = "name"
+
+                [actions.changes]
+                name = "full_name"
+            "#,
+        )
+        .unwrap(),
+    ]
+}
+
+fn schema_exists(db: &mut Client, migration: &str) -> bool {
+    db.query_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)",
+        &[&format!("migration_{}", migration)],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn view_columns(db: &mut Client, migration: &str) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = $1 AND table_name = 'users'
+        ORDER BY column_name
+        ",
+        &[&format!("migration_{}", migration)],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn intermediate_schemas() {
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .with_intermediate_schemas(true);
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+
+    // Every migration in the batch has a schema exposing its version of the table
+    assert_eq!(
+        vec!["id", "name"],
+        view_columns(&mut db, "create_users_table")
+    );
+    assert_eq!(
+        vec!["email", "id", "name"],
+        view_columns(&mut db, "add_email_column")
+    );
+    assert_eq!(
+        vec!["email", "full_name", "id"],
+        view_columns(&mut db, "rename_name_column")
+    );
+
+    // Writes made through the schema of the last migration can be read
+    // through the intermediate schemas
+    db.simple_query(&reshape::schema_query_for_migration("rename_name_column"))
+        .unwrap();
+    db.simple_query(
+        "INSERT INTO users (id, full_name, email) VALUES (1, 'Test', 'test@example.com')",
+    )
+    .unwrap();
+    db.simple_query(&reshape::schema_query_for_migration("add_email_column"))
+        .unwrap();
+    let (name, email): (String, String) = db
+        .query_one("SELECT name, email FROM users WHERE id = 1", &[])
+        .map(|row| (row.get(0), row.get(1)))
+        .unwrap();
+    assert_eq!("Test", name);
+    assert_eq!("test@example.com", email);
+
+    // The intermediate schemas are read-only, as the triggers would handle
+    // writes made through them like writes made through the old schema
+    for query in [
+        "INSERT INTO users (id, name, email) VALUES (2, 'Other', 'other@example.com')",
+        "UPDATE users SET name = 'Changed', email = 'changed@example.com' WHERE id = 1",
+        "DELETE FROM users WHERE id = 1",
+    ] {
+        let error = db.simple_query(query).unwrap_err();
+        assert!(error
+            .as_db_error()
+            .unwrap()
+            .message()
+            .contains("is read-only as it belongs to an intermediate migration"));
+    }
+
+    // None of the rejected writes were stored
+    db.simple_query(&reshape::schema_query_for_migration("rename_name_column"))
+        .unwrap();
+    let rows: Vec<(i32, String, String)> = db
+        .query("SELECT id, full_name, email FROM users ORDER BY id", &[])
+        .unwrap()
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    assert_eq!(
+        vec![(1, "Test".to_string(), "test@example.com".to_string())],
+        rows
+    );
+
+    // Only the schema of the last migration remains after completing
+    reshape.complete().unwrap();
+    assert!(!schema_exists(&mut db, "create_users_table"));
+    assert!(!schema_exists(&mut db, "add_email_column"));
+    assert!(schema_exists(&mut db, "rename_name_column"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn intermediate_schemas_abort() {
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .with_intermediate_schemas(true);
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+    assert!(schema_exists(&mut db, "create_users_table"));
+    assert!(schema_exists(&mut db, "add_email_column"));
+
+    // Aborting removes the schemas of all migrations in the batch
+    reshape.abort().unwrap();
+    for migration in migrations() {
+        assert!(!schema_exists(&mut db, &migration.name));
+    }
+
+    reshape.remove().unwrap();
+}
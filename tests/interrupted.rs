@@ -0,0 +1,263 @@
+use postgres::{Client, NoTls};
+use reshape::{
+    migrations::Migration,
+    testing::{connection_string, run_interrupted, Test},
+    Phase, Reshape, StatusCursor,
+};
+
+const CREATE_USERS_TABLE: &str = r#"
+    name = "create_users_table"
+
+    [[actions]]
+    type = "create_table"
+    name = "users"
+    primary_key = ["id"]
+
+        [[actions.columns]]
+        name = "id"
+        type = "INTEGER"
+
+        [[actions.columns]]
+        name = "first"
+        type = "TEXT"
+
+        [[actions.columns]]
+        name = "second"
+        type = "TEXT"
+
+        [[actions.columns]]
+        name = "third"
+        type = "TEXT"
+    "#;
+
+const REMOVE_COLUMNS: &str = r#"
+    name = "remove_columns"
+
+    [[actions]]
+    type = "remove_column"
+    table = "users"
+    column = "first"
+
+    [[actions]]
+    type = "remove_column"
+    table = "users"
+    column = "second"
+
+    [[actions]]
+    type = "remove_column"
+    table = "users"
+    column = "third"
+    "#;
+
+const ADD_COLUMNS: &str = r#"
+    name = "add_columns"
+
+    [[actions]]
+    type = "add_column"
+    table = "users"
+
+        [actions.column]
+        name = "fourth"
+        type = "TEXT"
+
+    [[actions]]
+    type = "add_column"
+    table = "users"
+
+        [actions.column]
+        name = "fifth"
+        type = "TEXT"
+
+    [[actions]]
+    type = "add_column"
+    table = "users"
+
+        [actions.column]
+        name = "sixth"
+        type = "TEXT"
+    "#;
+
+#[test]
+fn complete_interrupted() {
+    let mut test = Test::new("Complete interrupted");
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(REMOVE_COLUMNS);
+
+    // Only the first action has been saved as completed, so the rest of the
+    // migration must be completed when resuming
+    test.interrupt_after(Phase::Complete, 2);
+
+    test.after_completion(|db| {
+        assert_eq!(vec!["id"], physical_columns(db));
+    });
+
+    test.run();
+}
+
+#[test]
+fn abort_interrupted() {
+    let mut test = Test::new("Abort interrupted");
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(ADD_COLUMNS);
+
+    // Actions are aborted in reverse and only the last one has been saved as
+    // aborted, so the first two must be aborted when resuming
+    test.interrupt_after(Phase::Abort, 2);
+
+    test.after_abort(|db| {
+        assert_eq!(vec!["id", "first", "second", "third"], physical_columns(db));
+    });
+
+    test.run();
+}
+
+#[test]
+fn abort_interrupted_before_saving_progress() {
+    let mut test = Test::new("Abort interrupted before saving progress");
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(ADD_COLUMNS);
+
+    // Nothing has been saved as aborted, so everything must be aborted when resuming
+    test.interrupt_after(Phase::Abort, 1);
+
+    test.after_abort(|db| {
+        assert_eq!(vec!["id", "first", "second", "third"], physical_columns(db));
+    });
+
+    test.run();
+}
+
+// Set up the users table and start applying `migration`, returning a client
+// using the public schema where the actual tables are
+fn apply(reshape: &mut Reshape, migration: &str) -> Client {
+    let first: Migration = toml::from_str(CREATE_USERS_TABLE).unwrap();
+    let second: Migration = toml::from_str(migration).unwrap();
+
+    reshape.remove().unwrap();
+    reshape.migrate(vec![first.clone()]).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(vec![first, second]).unwrap();
+
+    Client::connect(&connection_string(), NoTls).unwrap()
+}
+
+// Replace the position in the saved state with one stored like older versions did
+fn set_legacy_position(db: &mut Client, position: &str) {
+    db.simple_query(&format!(
+        "
+        UPDATE reshape.data
+        SET value = value
+            - 'next_migration_index' - 'next_action_index'
+            - 'migrations_left' - 'actions_left'
+            || '{}'::JSONB
+        WHERE key = 'state'
+        ",
+        position
+    ))
+    .unwrap();
+}
+
+// The columns of the actual users table, including any which are hidden from
+// the views
+fn physical_columns(db: &mut Client) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = 'users'
+        ORDER BY ordinal_position
+        ",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn resume_legacy_complete() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = apply(&mut reshape, REMOVE_COLUMNS);
+
+    assert!(run_interrupted(
+        &connection_string(),
+        Phase::Complete,
+        2,
+        Reshape::complete
+    ));
+
+    // Older versions saved the position after the first action as (1, 1)
+    set_legacy_position(
+        &mut db,
+        r#"{"current_migration_index": 1, "current_action_index": 1}"#,
+    );
+    reshape.complete().unwrap();
+
+    assert_eq!(vec!["id"], physical_columns(&mut db));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn resume_legacy_abort() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = apply(&mut reshape, ADD_COLUMNS);
+
+    assert!(run_interrupted(
+        &connection_string(),
+        Phase::Abort,
+        2,
+        Reshape::abort
+    ));
+
+    // The last action has been aborted, so abortion continues from the one before it
+    assert_eq!(
+        Some(StatusCursor {
+            migration_index: 0,
+            action_index: 1
+        }),
+        reshape.status().unwrap().cursor
+    );
+
+    // Older versions saved the position after aborting the last action as (0, 2)
+    set_legacy_position(
+        &mut db,
+        r#"{"last_migration_index": 0, "last_action_index": 2}"#,
+    );
+    reshape.abort().unwrap();
+
+    assert_eq!(
+        vec!["id", "first", "second", "third"],
+        physical_columns(&mut db)
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn resume_legacy_abort_from_start() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = apply(&mut reshape, ADD_COLUMNS);
+
+    assert!(run_interrupted(
+        &connection_string(),
+        Phase::Abort,
+        1,
+        Reshape::abort
+    ));
+
+    // Older versions saved the position before anything was aborted as (0, 0)
+    set_legacy_position(
+        &mut db,
+        r#"{"last_migration_index": 0, "last_action_index": 0}"#,
+    );
+    reshape.abort().unwrap();
+
+    assert_eq!(
+        vec!["id", "first", "second", "third"],
+        physical_columns(&mut db)
+    );
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,110 @@
+use reshape::{migrations::Migration, testing::connection_string, Reshape};
+
+fn names(migrations: &[Migration]) -> Vec<&str> {
+    migrations
+        .iter()
+        .map(|migration| migration.name.as_str())
+        .collect()
+}
+
+#[test]
+fn load_migrations() {
+    let migrations =
+        reshape::load_migrations(&["tests/fixtures/migrations", "does_not_exist"]).unwrap();
+
+    // Files are sorted naturally and named after the file unless a name is set
+    assert_eq!(
+        vec![
+            "1_create_users_table",
+            "2_add_name_column",
+            "add_email_column"
+        ],
+        names(&migrations)
+    );
+    assert_eq!(
+        Some("Create the users table".to_string()),
+        migrations[0].description
+    );
+
+    let error = reshape::parse_migration("1_invalid.txt", "").unwrap_err();
+    assert_eq!("unrecognized file extension 'txt'", error.to_string());
+}
+
+#[test]
+fn embed_migrations() {
+    let embedded = reshape::embed_migrations!("tests/fixtures/migrations").unwrap();
+    let loaded = reshape::load_migrations(&["tests/fixtures/migrations"]).unwrap();
+    assert_eq!(names(&loaded), names(&embedded));
+
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    reshape.migrate(embedded).unwrap();
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn parse_yaml_migrations() {
+    let yaml = r#"
+description: >
+  Add a column holding
+  the user's full name
+actions:
+  - type: add_column
+    table: users
+    up: |
+      CASE
+        WHEN first_name IS NULL THEN last_name
+        ELSE first_name || ' ' || last_name
+      END
+    column:
+      name: full_name
+      type: TEXT
+"#;
+
+    // Both extensions are supported and block scalars keep their line breaks
+    for path in ["3_add_full_name.yaml", "3_add_full_name.yml"] {
+        let migration = reshape::parse_migration(path, yaml).unwrap();
+        assert_eq!("3_add_full_name", migration.name);
+        assert_eq!(
+            Some("Add a column holding the user's full name\n".to_string()),
+            migration.description
+        );
+
+        let action = serde_json::to_value(&migration.actions[0]).unwrap();
+        assert_eq!("add_column", action["type"]);
+        assert_eq!(
+            "CASE\n  WHEN first_name IS NULL THEN last_name\n  ELSE first_name || ' ' || last_name\nEND\n",
+            action["up"]
+        );
+        assert_eq!("full_name", action["column"]["name"]);
+    }
+}
+
+#[test]
+fn yaml_errors_point_at_nested_field() {
+    let yaml = r#"
+actions:
+  - type: add_column
+    table: users
+    column:
+      name: full_name
+      type: TEXT
+      nullable: maybe
+"#;
+
+    // The path and location are repeated for every level of nesting by the
+    // YAML parser, but only the innermost ones are kept
+    let error = reshape::parse_migration("3_add_full_name.yaml", yaml).unwrap_err();
+    assert_eq!(
+        "actions[0].column.nullable: invalid type: string \"maybe\", expected a boolean at line 8 column 17",
+        error.to_string()
+    );
+
+    // Errors outside of actions aren't nested and are left as they are
+    let error = reshape::parse_migration("3_add_full_name.yaml", "actions: 5").unwrap_err();
+    assert_eq!(
+        "actions: invalid type: integer `5`, expected a sequence at line 1 column 10",
+        error.to_string()
+    );
+}
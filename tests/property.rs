@@ -0,0 +1,427 @@
+// Property tests applying random sequences of migrations, with random aborts
+// and interruptions, checking that:
+//
+// - The views of the old and new schemas match the expected logical schema
+//   while a batch of migrations is in progress
+// - Aborting leaves no temporary objects behind
+// - Completing converges to the same physical schema as applying and
+//   completing every batch straight away
+//
+// Every case runs against the database, so the number of cases is kept low.
+// Set PROPTEST_CASES to run more.
+
+use std::collections::BTreeMap;
+
+use postgres::{Client, NoTls};
+use proptest::prelude::*;
+use reshape::{
+    migrations::Migration,
+    testing::{assert_cleaned_up, connection_string, run_interrupted},
+    Phase, Reshape,
+};
+use serde_json::{json, Value};
+
+const TYPES: [&str; 3] = ["INTEGER", "BIGINT", "TEXT"];
+
+// Tables with their columns and types, as the views of a schema should show them
+type LogicalSchema = BTreeMap<String, BTreeMap<String, String>>;
+
+// A random action, turned into a valid one for the schema at that point by
+// `Generator::action`. Values are used to pick tables, columns and types.
+#[derive(Clone, Debug)]
+struct ActionSeed {
+    kind: u8,
+    a: usize,
+    b: usize,
+    c: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Complete,
+    // Abort the batch and then apply and complete it again
+    AbortAndRetry,
+    // Interrupt the phase after a number of actions, as if the process had
+    // been killed, then run the phase again to resume it
+    Interrupt(Phase, usize),
+}
+
+#[derive(Clone, Debug)]
+struct BatchSeed {
+    migrations: Vec<Vec<ActionSeed>>,
+    outcome: Outcome,
+}
+
+fn action_seed() -> impl Strategy<Value = ActionSeed> {
+    (0u8..7, any::<usize>(), any::<usize>(), any::<usize>())
+        .prop_map(|(kind, a, b, c)| ActionSeed { kind, a, b, c })
+}
+
+fn outcome() -> impl Strategy<Value = Outcome> {
+    let phase = prop_oneof![
+        Just(Phase::Migrate),
+        Just(Phase::Complete),
+        Just(Phase::Abort)
+    ];
+    prop_oneof![
+        2 => Just(Outcome::Complete),
+        1 => Just(Outcome::AbortAndRetry),
+        2 => (phase, 1usize..5).prop_map(|(phase, after)| Outcome::Interrupt(phase, after)),
+    ]
+}
+
+fn batch_seed() -> impl Strategy<Value = BatchSeed> {
+    (
+        prop::collection::vec(prop::collection::vec(action_seed(), 1..4), 1..4),
+        outcome(),
+    )
+        .prop_map(|(migrations, outcome)| BatchSeed {
+            migrations,
+            outcome,
+        })
+}
+
+// Builds valid migrations from seeds, keeping track of the logical schema.
+// New tables and columns always get fresh names.
+#[derive(Default)]
+struct Generator {
+    schema: LogicalSchema,
+    next_name: usize,
+}
+
+impl Generator {
+    fn fresh_name(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn pick_table(&self, index: usize) -> Option<String> {
+        let tables: Vec<&String> = self.schema.keys().collect();
+        (!tables.is_empty()).then(|| tables[index % tables.len()].to_string())
+    }
+
+    // Any column but the primary key, which is never changed
+    fn pick_column(&self, table_index: usize, column_index: usize) -> Option<(String, String)> {
+        let table = self.pick_table(table_index)?;
+        let columns: Vec<&String> = self.schema[&table]
+            .keys()
+            .filter(|column| *column != "id")
+            .collect();
+        (!columns.is_empty()).then(|| (table, columns[column_index % columns.len()].to_string()))
+    }
+
+    fn migration(&mut self, name: &str, seeds: &[ActionSeed]) -> Migration {
+        let actions: Vec<Value> = seeds.iter().map(|seed| self.action(seed)).collect();
+        serde_json::from_value(json!({ "name": name, "actions": actions })).unwrap()
+    }
+
+    fn action(&mut self, seed: &ActionSeed) -> Value {
+        match seed.kind {
+            1 => self.rename_table(seed),
+            2 => self.remove_table(seed),
+            3 => self.add_column(seed),
+            4 => self.rename_column(seed),
+            5 => self.change_column_type(seed),
+            6 => self.remove_column(seed),
+            _ => self.create_table(seed),
+        }
+    }
+
+    fn create_table(&mut self, seed: &ActionSeed) -> Value {
+        let table = self.fresh_name("table");
+        let mut columns = BTreeMap::from([("id".to_string(), "INTEGER".to_string())]);
+        let mut column_definitions = vec![json!({ "name": "id", "type": "INTEGER" })];
+        for index in 0..seed.b % 3 {
+            let column = self.fresh_name("column");
+            let data_type = TYPES[(seed.c + index) % TYPES.len()];
+            column_definitions.push(json!({ "name": column, "type": data_type }));
+            columns.insert(column, data_type.to_string());
+        }
+
+        self.schema.insert(table.clone(), columns);
+        json!({
+            "type": "create_table",
+            "name": table,
+            "primary_key": ["id"],
+            "columns": column_definitions,
+        })
+    }
+
+    fn rename_table(&mut self, seed: &ActionSeed) -> Value {
+        let Some(table) = self.pick_table(seed.a) else {
+            return self.create_table(seed);
+        };
+
+        let new_name = self.fresh_name("table");
+        let columns = self.schema.remove(&table).unwrap();
+        self.schema.insert(new_name.clone(), columns);
+        json!({ "type": "rename_table", "table": table, "new_name": new_name })
+    }
+
+    fn remove_table(&mut self, seed: &ActionSeed) -> Value {
+        let Some(table) = self.pick_table(seed.a) else {
+            return self.create_table(seed);
+        };
+
+        self.schema.remove(&table);
+        json!({ "type": "remove_table", "table": table })
+    }
+
+    fn add_column(&mut self, seed: &ActionSeed) -> Value {
+        let Some(table) = self.pick_table(seed.a) else {
+            return self.create_table(seed);
+        };
+
+        let column = self.fresh_name("column");
+        let data_type = TYPES[seed.b % TYPES.len()];
+        self.schema
+            .get_mut(&table)
+            .unwrap()
+            .insert(column.clone(), data_type.to_string());
+        json!({
+            "type": "add_column",
+            "table": table,
+            "column": { "name": column, "type": data_type, "nullable": true },
+        })
+    }
+
+    fn rename_column(&mut self, seed: &ActionSeed) -> Value {
+        let Some((table, column)) = self.pick_column(seed.a, seed.b) else {
+            return self.add_column(seed);
+        };
+
+        let new_name = self.fresh_name("column");
+        let columns = self.schema.get_mut(&table).unwrap();
+        let data_type = columns.remove(&column).unwrap();
+        columns.insert(new_name.clone(), data_type);
+        json!({
+            "type": "alter_column",
+            "table": table,
+            "column": column,
+            "changes": { "name": new_name },
+        })
+    }
+
+    // Change the type of a column, and sometimes its name along with it
+    fn change_column_type(&mut self, seed: &ActionSeed) -> Value {
+        let Some((table, column)) = self.pick_column(seed.a, seed.b) else {
+            return self.add_column(seed);
+        };
+
+        let columns = self.schema.get_mut(&table).unwrap();
+        let old_type = columns.remove(&column).unwrap();
+        let new_type = TYPES
+            .iter()
+            .filter(|data_type| **data_type != old_type)
+            .nth(seed.c % (TYPES.len() - 1))
+            .unwrap()
+            .to_string();
+
+        let mut changes = json!({ "type": new_type });
+        let new_name = if seed.c % 2 == 0 {
+            column.clone()
+        } else {
+            self.next_name += 1;
+            let new_name = format!("column{}", self.next_name);
+            changes["name"] = json!(new_name);
+            new_name
+        };
+        self.schema
+            .get_mut(&table)
+            .unwrap()
+            .insert(new_name, new_type.clone());
+
+        json!({
+            "type": "alter_column",
+            "table": table,
+            "column": column,
+            "up": format!("CAST({} AS {})", column, new_type),
+            "down": format!("CAST({} AS {})", column, old_type),
+            "changes": changes,
+        })
+    }
+
+    // Remove a column, with a down function filling it in for rows inserted
+    // through the new schema
+    fn remove_column(&mut self, seed: &ActionSeed) -> Value {
+        let Some((table, column)) = self.pick_column(seed.a, seed.b) else {
+            return self.add_column(seed);
+        };
+
+        self.schema.get_mut(&table).unwrap().remove(&column);
+        json!({
+            "type": "remove_column",
+            "table": table,
+            "column": column,
+            "down": "NULL",
+        })
+    }
+}
+
+// The columns of every table in the schema, with their types
+fn schema_columns(db: &mut Client, schema: &str) -> LogicalSchema {
+    let mut tables = LogicalSchema::new();
+    let rows = db
+        .query(
+            "
+            SELECT table_name::TEXT, column_name::TEXT, UPPER(data_type)
+            FROM information_schema.columns
+            WHERE table_schema = $1
+            ",
+            &[&schema],
+        )
+        .unwrap();
+    for row in rows {
+        tables
+            .entry(row.get(0))
+            .or_default()
+            .insert(row.get(1), row.get(2));
+    }
+    tables
+}
+
+fn assert_views(db: &mut Client, migration: &str, expected: &LogicalSchema) {
+    let schema = reshape::schema_name_for_migration(migration);
+    assert_eq!(
+        expected,
+        &schema_columns(db, &schema),
+        "views of schema {} don't match",
+        schema
+    );
+}
+
+// Insert a row into every table through the schema, checking that the
+// triggers keeping the schemas in sync work
+fn insert_rows(db: &mut Client, migration: &str, schema: &LogicalSchema, next_id: &mut i32) {
+    db.simple_query(&reshape::schema_query_for_migration(migration))
+        .unwrap();
+    for table in schema.keys() {
+        *next_id += 1;
+        db.execute(
+            &format!("INSERT INTO {} (id) VALUES ($1)", table),
+            &[&*next_id],
+        )
+        .unwrap_or_else(|err| panic!("failed to insert into {}: {}", table, err));
+    }
+    db.simple_query("SET search_path TO public").unwrap();
+}
+
+fn reshape() -> Reshape {
+    Reshape::new(&connection_string()).unwrap()
+}
+
+// The tables and columns in the public schema, which is where Reshape keeps
+// the actual tables
+fn physical_schema(db: &mut Client) -> LogicalSchema {
+    schema_columns(db, "public")
+}
+
+fn run_batches(batches: &[BatchSeed], with_outcomes: bool) -> LogicalSchema {
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape().remove().unwrap();
+
+    // A failed case can leave anything behind, which would otherwise make
+    // every case after it fail while shrinking
+    db.simple_query("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+        .unwrap();
+
+    let mut generator = Generator::default();
+    let mut migrations: Vec<Migration> = Vec::new();
+    let mut next_id = 0;
+
+    for (batch_index, batch) in batches.iter().enumerate() {
+        let old_schema = generator.schema.clone();
+        let old_migration = migrations.last().map(|migration| migration.name.clone());
+
+        for (index, seeds) in batch.migrations.iter().enumerate() {
+            let name = format!("batch{}_{}", batch_index, index);
+            migrations.push(generator.migration(&name, seeds));
+        }
+        let new_migration = migrations.last().unwrap().name.clone();
+        let new_schema = generator.schema.clone();
+
+        let outcome = if with_outcomes {
+            batch.outcome
+        } else {
+            Outcome::Complete
+        };
+
+        // Apply the batch, resuming if interrupted
+        if let Outcome::Interrupt(Phase::Migrate, after) = outcome {
+            let all = migrations.clone();
+            if run_interrupted(&connection_string(), Phase::Migrate, after, |reshape| {
+                reshape.migrate(all)
+            }) {
+                reshape().migrate(migrations.clone()).unwrap();
+            }
+        } else {
+            reshape().migrate(migrations.clone()).unwrap();
+        }
+
+        assert_views(&mut db, &new_migration, &new_schema);
+        insert_rows(&mut db, &new_migration, &new_schema, &mut next_id);
+        if let Some(old_migration) = &old_migration {
+            assert_views(&mut db, old_migration, &old_schema);
+            insert_rows(&mut db, old_migration, &old_schema, &mut next_id);
+        }
+
+        match outcome {
+            Outcome::AbortAndRetry | Outcome::Interrupt(Phase::Abort, _) => {
+                // An interruption which didn't happen means the abort finished
+                let aborted = match outcome {
+                    Outcome::Interrupt(Phase::Abort, after) => {
+                        !run_interrupted(&connection_string(), Phase::Abort, after, |reshape| {
+                            reshape.abort()
+                        })
+                    }
+                    _ => false,
+                };
+                if !aborted {
+                    reshape().abort().unwrap();
+                }
+
+                assert_cleaned_up(&mut db);
+                if let Some(old_migration) = &old_migration {
+                    assert_views(&mut db, old_migration, &old_schema);
+                }
+
+                reshape().migrate(migrations.clone()).unwrap();
+                reshape().complete().unwrap();
+            }
+            Outcome::Interrupt(Phase::Complete, after) => {
+                if run_interrupted(&connection_string(), Phase::Complete, after, |reshape| {
+                    reshape.complete()
+                }) {
+                    reshape().complete().unwrap();
+                }
+            }
+            _ => reshape().complete().unwrap(),
+        }
+
+        assert_eq!("idle", reshape().status().unwrap().state);
+        assert_cleaned_up(&mut db);
+        assert_views(&mut db, &new_migration, &new_schema);
+    }
+
+    let physical = physical_schema(&mut db);
+    reshape().remove().unwrap();
+    physical
+}
+
+fn cases() -> u32 {
+    std::env::var("PROPTEST_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(16)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(cases()))]
+
+    #[test]
+    fn random_migrations(batches in prop::collection::vec(batch_seed(), 1..5)) {
+        let expected = run_batches(&batches, false);
+        let actual = run_batches(&batches, true);
+        prop_assert_eq!(expected, actual);
+    }
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{assert_columns, assert_rows, Test};
 
 #[test]
 fn remove_column() {
@@ -215,3 +214,74 @@ fn remove_column_with_complex_down() {
 
     test.run();
 }
+
+#[test]
+fn remove_column_after_rename_table() {
+    let mut test = Test::new("Remove column after renaming table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    // The table keeps its old name until the migration is completed, so the
+    // triggers have to be created on the table using its real name
+    test.second_migration(
+        r#"
+        name = "rename_users_and_remove_name"
+
+        [[actions]]
+        type = "rename_table"
+        table = "users"
+        new_name = "customers"
+
+        [[actions]]
+        type = "remove_column"
+        table = "customers"
+        column = "name"
+        down = "'TEST_DOWN_VALUE'"
+        "#,
+    );
+
+    test.intermediate(|old_db, new_db| {
+        // Inserts through the new schema get the down value
+        new_db
+            .simple_query("INSERT INTO customers (id) VALUES (1)")
+            .unwrap();
+        assert_rows(
+            old_db,
+            "SELECT id, name FROM users ORDER BY id",
+            &[&[Some("1"), Some("TEST_DOWN_VALUE")]],
+        );
+
+        // The column is still required when inserting through the old schema
+        assert!(old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .is_err());
+    });
+
+    test.after_completion(|db| {
+        assert_columns(db, "customers", &["id"]);
+    });
+
+    test.after_abort(|db| {
+        assert_columns(db, "users", &["id", "name"]);
+    });
+
+    test.run();
+}
@@ -0,0 +1,132 @@
+use postgres::{Client, NoTls};
+use reshape::{
+    testing::{connection_string, parse_migration},
+    Reshape,
+};
+
+fn schema_exists(db: &mut Client, migration: &str) -> bool {
+    db.query_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)",
+        &[&format!("migration_{}", migration)],
+    )
+    .unwrap()
+    .get(0)
+}
+
+#[test]
+fn retained_schemas() {
+    let create_users_table = parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+    let rename_name_column = parse_migration(
+        r#"
+        name = "rename_name_column"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+        "#,
+    );
+    let add_email_column = parse_migration(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+    let remove_full_name_column = parse_migration(
+        r#"
+        name = "remove_full_name_column"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "full_name"
+        down = "'unknown'"
+        "#,
+    );
+
+    let mut reshape = Reshape::new(&connection_string())
+        .unwrap()
+        .with_retained_schemas(1);
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    let mut migrations = vec![create_users_table.clone()];
+    reshape.migrate(migrations.clone()).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query(&reshape::schema_query_for_migration("create_users_table"))
+        .unwrap();
+    db.simple_query("INSERT INTO users (id, name) VALUES (1, 'Test')")
+        .unwrap();
+
+    migrations.push(rename_name_column);
+    reshape.migrate(migrations.clone()).unwrap();
+    reshape.complete().unwrap();
+
+    // The first schema is kept and still works after the column was renamed,
+    // but can no longer be written to
+    assert!(schema_exists(&mut db, "create_users_table"));
+    db.simple_query(&reshape::schema_query_for_migration("create_users_table"))
+        .unwrap();
+    let name: String = db
+        .query_one("SELECT name FROM users WHERE id = 1", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!("Test", name);
+    let error = db
+        .simple_query("INSERT INTO users (id, name) VALUES (2, 'Test 2')")
+        .unwrap_err();
+    let error = error.as_db_error().unwrap();
+    assert_eq!(
+        "schema migration_create_users_table is read-only as a newer migration has been completed",
+        error.message()
+    );
+    assert_eq!(
+        Some("Write through the schema of the latest migration instead"),
+        error.hint()
+    );
+
+    // Only one previous schema is kept, so the oldest one is removed
+    migrations.push(add_email_column);
+    reshape.migrate(migrations.clone()).unwrap();
+    reshape.complete().unwrap();
+    assert!(!schema_exists(&mut db, "create_users_table"));
+    assert!(schema_exists(&mut db, "rename_name_column"));
+
+    // Schemas which use a removed column are removed, even when they should be kept
+    let mut reshape = reshape.with_retained_schemas(2);
+    migrations.push(remove_full_name_column);
+    reshape.migrate(migrations.clone()).unwrap();
+    reshape.complete().unwrap();
+    assert!(!schema_exists(&mut db, "rename_name_column"));
+    assert!(!schema_exists(&mut db, "add_email_column"));
+    assert!(schema_exists(&mut db, "remove_full_name_column"));
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,261 @@
+use postgres::{Client, NoTls};
+use reshape::{
+    migrations::Migration,
+    testing::{connection_string, parse_migration},
+    Reshape,
+};
+
+fn create_users_table() -> Migration {
+    parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+}
+
+fn reshape_users_table() -> Migration {
+    parse_migration(
+        r#"
+        name = "reshape_users_table"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_email_idx"
+            columns = ["email"]
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+
+        [[actions]]
+        type = "rename_table"
+        table = "users"
+        new_name = "customers"
+        "#,
+    )
+}
+
+fn remove_name_column() -> Migration {
+    parse_migration(
+        r#"
+        name = "remove_name_column"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "name"
+        "#,
+    )
+}
+
+fn column_names(db: &mut Client, table: &str) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = $1
+        ORDER BY ordinal_position
+        ",
+        &[&table],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn revert_migration() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut old_db = Client::connect(&connection_string(), NoTls).unwrap();
+    let mut new_db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_users_table()]).unwrap();
+    reshape.complete().unwrap();
+    reshape
+        .migrate(vec![create_users_table(), reshape_users_table()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    old_db
+        .simple_query(&reshape::schema_query_for_migration("reshape_users_table"))
+        .unwrap();
+    old_db
+        .simple_query(
+            "INSERT INTO customers (id, full_name, email) VALUES (1, 'John Doe', 'john@example.com')",
+        )
+        .unwrap();
+
+    // During the revert, both the current schema and the schema of the previous migration are usable
+    reshape.revert().unwrap();
+    new_db
+        .simple_query(&reshape::schema_query_for_migration("create_users_table"))
+        .unwrap();
+
+    let name: String = new_db
+        .query_one("SELECT name FROM users WHERE id = 1", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!("John Doe", name);
+
+    old_db
+        .simple_query("INSERT INTO customers (id, full_name) VALUES (2, 'Jane Doe')")
+        .unwrap();
+    new_db
+        .simple_query("INSERT INTO users (id, name) VALUES (3, 'Test Testsson')")
+        .unwrap();
+
+    reshape.complete().unwrap();
+
+    // The reverted migration is no longer recorded as applied
+    let applied: Vec<String> = new_db
+        .query("SELECT name FROM reshape.migrations ORDER BY index", &[])
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(vec!["create_users_table"], applied);
+
+    assert_eq!(vec!["id", "name"], column_names(&mut new_db, "users"));
+    let indices: i64 = new_db
+        .query_one(
+            "SELECT COUNT(*) FROM pg_indexes WHERE indexname = 'users_email_idx'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(0, indices);
+
+    let names: Vec<String> = new_db
+        .query("SELECT name FROM users ORDER BY id", &[])
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(vec!["John Doe", "Jane Doe", "Test Testsson"], names);
+
+    // The reverted migration can be applied again
+    reshape
+        .migrate(vec![create_users_table(), reshape_users_table()])
+        .unwrap();
+    reshape.complete().unwrap();
+}
+
+#[test]
+fn abort_revert() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_users_table()]).unwrap();
+    reshape.complete().unwrap();
+    reshape
+        .migrate(vec![create_users_table(), reshape_users_table()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    reshape.revert().unwrap();
+    reshape.abort().unwrap();
+
+    // The migration is still applied and its schema usable
+    db.simple_query(&reshape::schema_query_for_migration("reshape_users_table"))
+        .unwrap();
+    db.simple_query("INSERT INTO customers (id, full_name, email) VALUES (1, 'John Doe', NULL)")
+        .unwrap();
+
+    let applied: i64 = db
+        .query_one("SELECT COUNT(*) FROM reshape.migrations", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!(2, applied);
+
+    // Completing another migration should record it as usual
+    let add_column = parse_migration(
+        r#"
+        name = "add_age_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "customers"
+
+            [actions.column]
+            name = "age"
+            type = "INTEGER"
+        "#,
+    );
+    reshape
+        .migrate(vec![
+            create_users_table(),
+            reshape_users_table(),
+            add_column,
+        ])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    let applied: i64 = db
+        .query_one("SELECT COUNT(*) FROM reshape.migrations", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!(3, applied);
+}
+
+#[test]
+fn revert_irreversible_migration_fails() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_users_table()]).unwrap();
+    reshape.complete().unwrap();
+
+    // The first migration has no previous schema to go back to
+    let error = reshape.revert().unwrap_err();
+    assert_eq!(
+        "migration create_users_table can't be reverted as it's the first migration, so there is no earlier schema to go back to. Undo it with a new migration instead.",
+        error.to_string()
+    );
+
+    reshape
+        .migrate(vec![create_users_table(), remove_name_column()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    let error = reshape.revert().unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("the action \"Removing column \"name\" from \"users\"\" can't be reversed"));
+
+    // Nothing should have been started
+    reshape.abort().unwrap();
+    reshape
+        .migrate(vec![create_users_table(), remove_name_column()])
+        .unwrap();
+}
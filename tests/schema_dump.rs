@@ -0,0 +1,163 @@
+use reshape::{
+    testing::{connection_string, parse_migration},
+    Reshape, SchemaDump,
+};
+
+fn column_names(schema: &SchemaDump, table: &str) -> Vec<String> {
+    schema
+        .tables
+        .iter()
+        .find(|t| t.name == table)
+        .unwrap()
+        .columns
+        .iter()
+        .map(|column| column.name.to_string())
+        .collect()
+}
+
+#[test]
+fn dump_schema() {
+    let first_migration = parse_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_enum"
+        name = "mood"
+        values = ["happy", "sad"]
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "VARCHAR(100)"
+            nullable = false
+
+            [[actions.columns]]
+            name = "mood"
+            type = "mood"
+            default = "'happy'"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+
+            [[actions.foreign_keys]]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+            unique = true
+        "#,
+    );
+    let second_migration = parse_migration(
+        r#"
+        name = "rename_name_column"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(full_name)"
+
+            [actions.changes]
+            name = "full_name"
+        "#,
+    );
+
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    let schemas = reshape.dump_schema(None).unwrap();
+    assert_eq!(1, schemas.len());
+    let schema = &schemas[0];
+    assert_eq!("create_tables", schema.migration);
+    assert_eq!("migration_create_tables", schema.schema);
+
+    let table_names: Vec<&str> = schema.tables.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(vec!["posts", "users"], table_names);
+    assert_eq!(vec!["id", "name", "mood"], column_names(schema, "users"));
+
+    let users = &schema.tables[1];
+    assert_eq!(vec!["id"], users.primary_key);
+    assert_eq!("character varying(100)", users.columns[1].data_type);
+    assert!(!users.columns[1].nullable);
+    assert_eq!("mood", users.columns[2].data_type);
+    assert!(users.columns[2].default.is_some());
+    assert_eq!(1, users.indices.len());
+    assert_eq!("users_name_idx", users.indices[0].name);
+    assert!(users.indices[0].unique);
+
+    let posts = &schema.tables[0];
+    assert_eq!(1, posts.foreign_keys.len());
+    assert_eq!(vec!["user_id"], posts.foreign_keys[0].columns);
+    assert_eq!("users", posts.foreign_keys[0].referenced_table);
+
+    assert_eq!(1, schema.enums.len());
+    assert_eq!(vec!["happy", "sad"], schema.enums[0].values);
+
+    let sql = schema.to_string();
+    assert!(sql.contains("CREATE TYPE \"mood\" AS ENUM ('happy', 'sad');"));
+    assert!(sql.contains("\"name\" character varying(100) NOT NULL"));
+    assert!(sql.contains("PRIMARY KEY (\"id\")"));
+    assert!(sql.contains("FOREIGN KEY (\"user_id\") REFERENCES \"users\" (\"id\")"));
+    assert!(sql.contains("CREATE UNIQUE INDEX \"users_name_idx\" ON \"users\" (\"name\");"));
+
+    // While a migration is in progress, both the old and new schema are dumped
+    // using their logical names and without any temporary columns
+    reshape
+        .migrate(vec![first_migration.clone(), second_migration.clone()])
+        .unwrap();
+
+    let schemas = reshape.dump_schema(None).unwrap();
+    assert_eq!(2, schemas.len());
+    assert_eq!("create_tables", schemas[0].migration);
+    assert_eq!(
+        vec!["id", "name", "mood"],
+        column_names(&schemas[0], "users")
+    );
+    assert_eq!("rename_name_column", schemas[1].migration);
+    assert_eq!(
+        vec!["id", "full_name", "mood"],
+        column_names(&schemas[1], "users")
+    );
+
+    let new_users = &schemas[1].tables[1];
+    assert_eq!("character varying(100)", new_users.columns[1].data_type);
+    assert_eq!(vec!["full_name"], new_users.indices[0].columns);
+
+    let schemas = reshape.dump_schema(Some("rename_name_column")).unwrap();
+    assert_eq!(1, schemas.len());
+    assert_eq!("rename_name_column", schemas[0].migration);
+
+    assert!(reshape.dump_schema(Some("unknown")).is_err());
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
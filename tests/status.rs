@@ -0,0 +1,132 @@
+use postgres::{Client, NoTls};
+use reshape::{
+    testing::{connection_string, parse_migration},
+    DeclarativeSchema, Reshape, StatusCursor,
+};
+
+#[test]
+fn status() {
+    let first_migration = parse_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+    let second_migration = parse_migration(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let status = reshape.status().unwrap();
+    assert_eq!("idle", status.state);
+    assert!(status.pending_migrations.is_empty());
+    assert_eq!(None, status.current_schema);
+    assert_eq!(None, status.target_schema);
+    assert!(!status.helpers_installed);
+    assert!(!status.locked);
+    assert!(status.temporary_objects.is_empty());
+
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+    reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap();
+
+    let status = reshape.status().unwrap();
+    assert_eq!("in_progress", status.state);
+    assert_eq!(vec!["add_email_column"], status.pending_migrations);
+    assert_eq!(None, status.cursor);
+    assert_eq!(
+        Some("migration_create_users_table".to_string()),
+        status.current_schema
+    );
+    assert_eq!(
+        Some("migration_add_email_column".to_string()),
+        status.target_schema
+    );
+    assert!(status.helpers_installed);
+    assert!(status
+        .temporary_objects
+        .iter()
+        .any(|object| object.kind == "column" && object.name.starts_with("users.__reshape_")));
+
+    // The human-readable output should include the pending migration
+    let output = status.to_string();
+    assert!(output.contains("State: in_progress"));
+    assert!(output.contains("  - add_email_column"));
+
+    reshape.complete().unwrap();
+
+    let status = reshape.status().unwrap();
+    assert_eq!("idle", status.state);
+    assert!(status.pending_migrations.is_empty());
+    assert_eq!(None::<StatusCursor>, status.cursor);
+    assert_eq!(
+        Some("migration_add_email_column".to_string()),
+        status.current_schema
+    );
+    assert!(!status.helpers_installed);
+    assert!(status.temporary_objects.is_empty());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn read_only_commands_before_setup() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    db.simple_query("DROP SCHEMA IF EXISTS reshape CASCADE")
+        .unwrap();
+
+    // Commands which don't take the lock see an empty database without
+    // creating Reshape's tables
+    let status = reshape.status().unwrap();
+    assert_eq!("idle", status.state);
+    assert!(status.current_schema.is_none());
+
+    let history = reshape.history(vec![]).unwrap();
+    assert!(history.applied.is_empty());
+
+    assert_eq!(
+        "no migrations have been applied",
+        reshape.dump_schema(None).unwrap_err().to_string()
+    );
+
+    let target: DeclarativeSchema = toml::from_str("").unwrap();
+    assert!(reshape
+        .generate_migration(&target, |_| Ok(None))
+        .unwrap()
+        .is_empty());
+
+    let set_up: bool = db
+        .query_one("SELECT TO_REGNAMESPACE('reshape') IS NOT NULL", &[])
+        .unwrap()
+        .get(0);
+    assert!(!set_up);
+}
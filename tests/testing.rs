@@ -0,0 +1,57 @@
+use reshape::testing::{assert_columns, assert_rows, Test};
+
+#[test]
+fn test_harness_with_loaded_migrations() {
+    let mut test = Test::new("Harness with loaded migrations");
+
+    // The migrations of a project, with one more under test
+    test.first_migrations(reshape::load_migrations(&["tests/fixtures/migrations"]).unwrap());
+    test.second_migration(
+        r#"
+        name = "add_display_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "UPPER(name)"
+
+            [actions.column]
+            name = "display_name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        assert_columns(db, "users", &["id", "name", "email"]);
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'john')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        assert_columns(old_db, "users", &["id", "name", "email"]);
+        assert_columns(new_db, "users", &["id", "name", "email", "display_name"]);
+
+        // Rows inserted through the old schema are filled in for the new one
+        old_db
+            .simple_query("INSERT INTO users (id, name) VALUES (2, 'jane')")
+            .unwrap();
+        assert_rows(
+            new_db,
+            "SELECT id, display_name, email FROM users ORDER BY id",
+            &[
+                &[Some("1"), Some("JOHN"), None],
+                &[Some("2"), Some("JANE"), None],
+            ],
+        );
+    });
+
+    test.after_completion(|db| {
+        assert_columns(db, "users", &["id", "name", "email", "display_name"]);
+    });
+
+    test.after_abort(|db| {
+        assert_columns(db, "users", &["id", "name", "email"]);
+    });
+
+    test.run();
+}
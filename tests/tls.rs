@@ -0,0 +1,136 @@
+#![cfg(feature = "tls")]
+
+// These tests require a Postgres server with TLS enabled. The server certificate
+// must be issued for "localhost" and signed by the CA certificate which
+// POSTGRES_SSL_ROOT_CERT points to. The tests are skipped unless it's set, so
+// run them with:
+//
+//   POSTGRES_SSL_ROOT_CERT=/path/to/root.crt cargo test --features tls --test tls
+
+use postgres::{Client, NoTls};
+use reshape::{testing::connection_string, Reshape, TlsMode, TlsOptions};
+
+// The CA certificate of the test server, or None if the tests should be skipped
+fn root_cert() -> Option<String> {
+    let root_cert = std::env::var("POSTGRES_SSL_ROOT_CERT").ok();
+    if root_cert.is_none() {
+        eprintln!("skipping test as POSTGRES_SSL_ROOT_CERT is not set");
+    }
+    root_cert
+}
+
+fn count_tls_connections() -> i64 {
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    db.query_one("SELECT COUNT(*) FROM pg_stat_ssl WHERE ssl", &[])
+        .unwrap()
+        .get(0)
+}
+
+#[test]
+fn tls_require() {
+    if root_cert().is_none() {
+        return;
+    }
+
+    let mut reshape = Reshape::new(&format!("{}?sslmode=require", connection_string())).unwrap();
+    reshape.abort().unwrap();
+
+    assert!(count_tls_connections() > 0);
+}
+
+#[test]
+fn tls_verify_full() {
+    let root_cert = match root_cert() {
+        Some(root_cert) => root_cert,
+        None => return,
+    };
+
+    let url = format!(
+        "{}?sslmode=verify-full&sslrootcert={}",
+        connection_string(),
+        root_cert
+    );
+    let mut reshape = Reshape::new(&url).unwrap();
+    reshape.abort().unwrap();
+}
+
+#[test]
+fn tls_verify_full_key_value_connection_string() {
+    let root_cert = match root_cert() {
+        Some(root_cert) => root_cert,
+        None => return,
+    };
+
+    let connection_string = format!(
+        "host=localhost user=postgres password=postgres dbname=reshape_test sslmode=verify-full sslrootcert='{}'",
+        root_cert
+    );
+    let mut reshape = Reshape::new(&connection_string).unwrap();
+    reshape.abort().unwrap();
+}
+
+#[test]
+fn tls_verify_full_from_options() {
+    let root_cert = match root_cert() {
+        Some(root_cert) => root_cert,
+        None => return,
+    };
+
+    let tls = TlsOptions {
+        mode: Some(TlsMode::VerifyFull),
+        root_cert: Some(root_cert.into()),
+        ..Default::default()
+    };
+    let mut reshape =
+        Reshape::new_with_tls(&format!("{}?sslmode=disable", connection_string()), tls).unwrap();
+    reshape.abort().unwrap();
+}
+
+#[test]
+fn tls_verify_full_rejects_hostname_mismatch() {
+    let root_cert = match root_cert() {
+        Some(root_cert) => root_cert,
+        None => return,
+    };
+
+    // The certificate is issued for "localhost" so connecting by IP should fail
+    let tls = TlsOptions {
+        mode: Some(TlsMode::VerifyFull),
+        root_cert: Some(root_cert.into()),
+        ..Default::default()
+    };
+    let result = Reshape::new_with_options_and_tls(
+        "127.0.0.1",
+        5432,
+        "reshape_test",
+        "postgres",
+        "postgres",
+        tls.clone(),
+    );
+    assert!(result.is_err());
+
+    // Verifying only the CA should succeed
+    let tls = TlsOptions {
+        mode: Some(TlsMode::VerifyCa),
+        ..tls
+    };
+    Reshape::new_with_options_and_tls(
+        "127.0.0.1",
+        5432,
+        "reshape_test",
+        "postgres",
+        "postgres",
+        tls,
+    )
+    .unwrap();
+}
+
+#[test]
+fn tls_verify_full_rejects_untrusted_certificate() {
+    if root_cert().is_none() {
+        return;
+    }
+
+    let url = format!("{}?sslmode=verify-full", connection_string());
+    assert!(Reshape::new(&url).is_err());
+}